@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Only the server is generated, the clients are built by the other services.
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&["proto/catalog.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS teacher (
+    id serial PRIMARY KEY,
+    name varchar(100),
+    url_picture varchar(200),
+    profile varchar(2000)
+);
+
+CREATE TABLE IF NOT EXISTS course (
+    id serial PRIMARY KEY,
+    teacher_id int NOT NULL,
+    name varchar(140) NOT NULL,
+    time timestamp DEFAULT now(),
+    description varchar(2000),
+    format varchar(30),
+    structure varchar(200),
+    duration varchar(30),
+    price int,
+    language varchar(30),
+    level varchar(30)
+);
@@ -0,0 +1,8 @@
+-- The response columns stay empty while the first request holding the key is in progress.
+CREATE TABLE idempotency_key (
+    key varchar(255) PRIMARY KEY,
+    request_hash char(64) NOT NULL,
+    status_code smallint,
+    response_body bytea,
+    created_at timestamp NOT NULL DEFAULT now()
+);
@@ -0,0 +1,14 @@
+CREATE TABLE course_material (
+    id serial PRIMARY KEY,
+    course_id int NOT NULL REFERENCES course (id) ON DELETE CASCADE,
+    teacher_id int NOT NULL,
+    file_name varchar(255) NOT NULL,
+    content_type varchar(100) NOT NULL,
+    size_bytes bigint NOT NULL,
+    checksum char(64) NOT NULL,
+    storage_key varchar(255) NOT NULL,
+    time timestamp DEFAULT now()
+);
+
+CREATE INDEX course_material_course_id_idx ON course_material (course_id);
+CREATE INDEX course_material_teacher_id_idx ON course_material (teacher_id);
@@ -0,0 +1,25 @@
+CREATE TABLE course_section (
+    id serial PRIMARY KEY,
+    course_id int NOT NULL REFERENCES course (id) ON DELETE CASCADE,
+    title varchar(140) NOT NULL,
+    position int NOT NULL,
+    -- Deferred, so that a transaction can swap positions.
+    UNIQUE (course_id, position) DEFERRABLE INITIALLY DEFERRED
+);
+
+CREATE TABLE course_lesson (
+    id serial PRIMARY KEY,
+    section_id int NOT NULL REFERENCES course_section (id) ON DELETE CASCADE,
+    course_id int NOT NULL REFERENCES course (id) ON DELETE CASCADE,
+    title varchar(140) NOT NULL,
+    position int NOT NULL,
+    duration_minutes int NOT NULL DEFAULT 0 CHECK (duration_minutes >= 0),
+    is_preview boolean NOT NULL DEFAULT false,
+    UNIQUE (section_id, position) DEFERRABLE INITIALLY DEFERRED
+);
+
+CREATE INDEX course_lesson_course_id_idx ON course_lesson (course_id);
+
+-- The duration of a course is now the sum of the durations of its lessons. The free-form
+-- duration is kept as it was for the clients of the v1 API.
+COMMENT ON COLUMN course.duration IS 'Deprecated, superseded by the sum of the lesson durations';
@@ -0,0 +1,21 @@
+CREATE TABLE course_review (
+    id serial PRIMARY KEY,
+    course_id int NOT NULL REFERENCES course (id) ON DELETE CASCADE,
+    student_id int NOT NULL,
+    rating smallint NOT NULL CHECK (rating BETWEEN 1 AND 5),
+    text varchar(2000),
+    -- Hidden reviews are left out of the listings and of the course rating.
+    is_hidden boolean NOT NULL DEFAULT false,
+    -- Reported by a reader, waiting for a moderator.
+    is_flagged boolean NOT NULL DEFAULT false,
+    time timestamp NOT NULL DEFAULT now(),
+    updated_at timestamp NOT NULL DEFAULT now(),
+    UNIQUE (course_id, student_id)
+);
+
+-- Kept up to date by every review write, so that courses can be sorted by rating.
+ALTER TABLE course
+    ADD COLUMN rating_average double precision,
+    ADD COLUMN rating_count int NOT NULL DEFAULT 0;
+
+CREATE INDEX course_teacher_id_rating_idx ON course (teacher_id, rating_average DESC NULLS LAST);
@@ -0,0 +1,16 @@
+-- The courses created so far were visible right away, they stay published.
+ALTER TABLE course
+    ADD COLUMN status varchar(20) NOT NULL DEFAULT 'published'
+        CHECK (status IN ('draft', 'in_review', 'published', 'archived'));
+ALTER TABLE course ALTER COLUMN status SET DEFAULT 'draft';
+
+CREATE TABLE course_status_transition (
+    id serial PRIMARY KEY,
+    course_id int NOT NULL REFERENCES course (id) ON DELETE CASCADE,
+    from_status varchar(20) NOT NULL,
+    to_status varchar(20) NOT NULL,
+    actor varchar(100) NOT NULL,
+    time timestamp NOT NULL DEFAULT now()
+);
+
+CREATE INDEX course_status_transition_course_id_idx ON course_status_transition (course_id);
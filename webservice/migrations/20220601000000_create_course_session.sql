@@ -0,0 +1,17 @@
+CREATE TABLE course_session (
+    id serial PRIMARY KEY,
+    course_id int NOT NULL REFERENCES course (id) ON DELETE CASCADE,
+    -- The first occurrence, the following ones are computed from `recurrence`.
+    starts_at timestamptz NOT NULL,
+    ends_at timestamptz NOT NULL,
+    -- An IANA name such as `Europe/Paris`, recurrences follow its daylight saving time.
+    time_zone varchar(64) NOT NULL,
+    -- An iCalendar RRULE, e.g. `FREQ=WEEKLY;COUNT=8`.
+    recurrence varchar(200),
+    capacity int CHECK (capacity > 0),
+    time timestamp DEFAULT now(),
+    CHECK (ends_at > starts_at)
+);
+
+CREATE INDEX course_session_course_id_idx ON course_session (course_id);
+CREATE INDEX course_session_starts_at_idx ON course_session (starts_at);
@@ -0,0 +1,29 @@
+CREATE TABLE audit_event (
+    id bigserial PRIMARY KEY,
+    actor varchar(100) NOT NULL,
+    action varchar(30) NOT NULL,
+    entity_type varchar(30) NOT NULL,
+    entity_id int NOT NULL,
+    -- The changed fields, as `{"field": {"before": ..., "after": ...}}`.
+    diff jsonb NOT NULL,
+    request_id varchar(64),
+    time timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_event_entity_idx ON audit_event (entity_type, entity_id, time);
+CREATE INDEX audit_event_time_idx ON audit_event (time);
+
+-- The log is append-only.
+CREATE FUNCTION audit_event_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_event is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_event_append_only
+    BEFORE UPDATE OR DELETE ON audit_event
+    FOR EACH ROW EXECUTE FUNCTION audit_event_append_only();
+
+CREATE TRIGGER audit_event_no_truncate
+    BEFORE TRUNCATE ON audit_event
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_event_append_only();
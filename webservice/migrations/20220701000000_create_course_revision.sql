@@ -0,0 +1,11 @@
+-- The state of a course before each of its updates.
+CREATE TABLE course_revision (
+    id serial PRIMARY KEY,
+    course_id int NOT NULL REFERENCES course (id) ON DELETE CASCADE,
+    -- Numbered from 1 for each course.
+    revision int NOT NULL,
+    snapshot jsonb NOT NULL,
+    actor varchar(100) NOT NULL,
+    time timestamptz NOT NULL DEFAULT now(),
+    UNIQUE (course_id, revision)
+);
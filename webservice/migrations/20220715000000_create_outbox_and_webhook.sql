@@ -0,0 +1,33 @@
+CREATE TABLE webhook (
+    id serial PRIMARY KEY,
+    url varchar(2000) NOT NULL,
+    -- Key of the HMAC-SHA256 signature of the deliveries.
+    secret varchar(200) NOT NULL,
+    time timestamptz NOT NULL DEFAULT now()
+);
+
+-- Domain events, written in the same transaction as the change they describe.
+CREATE TABLE outbox_event (
+    id bigserial PRIMARY KEY,
+    event_type varchar(50) NOT NULL,
+    entity_id int NOT NULL,
+    data jsonb NOT NULL,
+    time timestamptz NOT NULL DEFAULT now()
+);
+
+-- One delivery of each event to each webhook registered when the event was written.
+CREATE TABLE webhook_delivery (
+    id bigserial PRIMARY KEY,
+    event_id bigint NOT NULL REFERENCES outbox_event (id) ON DELETE CASCADE,
+    webhook_id int NOT NULL REFERENCES webhook (id) ON DELETE CASCADE,
+    status varchar(20) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'delivered', 'dead')),
+    attempts int NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL DEFAULT now(),
+    last_error text,
+    delivered_at timestamptz,
+    UNIQUE (event_id, webhook_id)
+);
+
+CREATE INDEX webhook_delivery_due_idx ON webhook_delivery (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX webhook_delivery_dead_idx ON webhook_delivery (id) WHERE status = 'dead';
@@ -0,0 +1,21 @@
+-- The teacher itself, or the teacher of the course, to filter the live changes.
+ALTER TABLE outbox_event ADD COLUMN teacher_id int;
+
+UPDATE outbox_event SET teacher_id = CASE
+    WHEN event_type LIKE 'Teacher%' THEN entity_id
+    ELSE coalesce((data->>'teacher_id')::int, (SELECT teacher_id FROM course WHERE id = entity_id))
+END;
+
+CREATE INDEX outbox_event_teacher_idx ON outbox_event (teacher_id, id);
+
+-- Sent on commit, to every instance listening to `catalog_changes`.
+CREATE FUNCTION outbox_event_notify() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('catalog_changes', NEW.id::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER outbox_event_notify
+    AFTER INSERT ON outbox_event
+    FOR EACH ROW EXECUTE FUNCTION outbox_event_notify();
@@ -0,0 +1,23 @@
+-- Sent as `Last-Modified`, and bumped by every write of the row.
+ALTER TABLE teacher ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE course ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
+
+-- The duration of a course is summed from its lessons, so changing them changes the course.
+CREATE FUNCTION course_lesson_touch_course() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        UPDATE course SET updated_at = now() WHERE id = OLD.course_id;
+    ELSE
+        UPDATE course SET updated_at = now() WHERE id = NEW.course_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER course_lesson_touch_course
+    AFTER INSERT OR UPDATE OR DELETE ON course_lesson
+    FOR EACH ROW EXECUTE FUNCTION course_lesson_touch_course();
+
+-- Deleted rows leave nothing to date the lists by but their outbox events.
+CREATE INDEX outbox_event_deleted_idx ON outbox_event (teacher_id, time)
+    WHERE event_type IN ('TeacherDeleted', 'CourseDeleted');
@@ -0,0 +1,16 @@
+-- Sent on commit, to every instance listening to `read_cache`, as `teacher:<id>` or
+-- `course:<id>`. Lesson and rating changes bump the course, so they are sent too.
+CREATE FUNCTION read_cache_notify() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('read_cache', TG_TABLE_NAME || ':' || OLD.id);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER teacher_read_cache_notify
+    AFTER UPDATE OR DELETE ON teacher
+    FOR EACH ROW EXECUTE FUNCTION read_cache_notify();
+
+CREATE TRIGGER course_read_cache_notify
+    AFTER UPDATE OR DELETE ON course
+    FOR EACH ROW EXECUTE FUNCTION read_cache_notify();
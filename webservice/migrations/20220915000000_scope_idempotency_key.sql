@@ -0,0 +1,4 @@
+-- The same key sent by two clients stands for two requests.
+ALTER TABLE idempotency_key ADD COLUMN scope varchar(255) NOT NULL DEFAULT 'anonymous';
+ALTER TABLE idempotency_key DROP CONSTRAINT idempotency_key_pkey;
+ALTER TABLE idempotency_key ADD PRIMARY KEY (scope, key);
@@ -0,0 +1,19 @@
+-- The blobs of the deleted materials, queued by the same transaction as the deletion,
+-- including the materials deleted along with their course. The cleanup worker removes
+-- them from the material store.
+CREATE TABLE orphaned_blob (
+    id bigserial PRIMARY KEY,
+    storage_key varchar(255) NOT NULL,
+    created_at timestamp NOT NULL DEFAULT now()
+);
+
+CREATE FUNCTION course_material_orphan_blob() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO orphaned_blob (storage_key) VALUES (OLD.storage_key);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER course_material_orphan_blob
+    AFTER DELETE ON course_material
+    FOR EACH ROW EXECUTE FUNCTION course_material_orphan_blob();
@@ -0,0 +1,2 @@
+-- Requests in progress renew their lease, so that only the keys of abandoned requests are taken over.
+ALTER TABLE idempotency_key ADD COLUMN renewed_at timestamp NOT NULL DEFAULT now();
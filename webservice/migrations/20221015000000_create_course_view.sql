@@ -0,0 +1,7 @@
+-- The courses with the duration summed from their lessons, as the API returns them.
+CREATE VIEW course_view AS
+SELECT c.id, c.teacher_id, c.name, c.time, c.description, c.format, c.structure, c.duration, c.price,
+    c.language, c.level, c.rating_average, c.rating_count, c.status, c.updated_at,
+    (SELECT coalesce(sum(l.duration_minutes), 0) FROM course_lesson l WHERE l.course_id = c.id)::bigint
+        as duration_minutes
+FROM course c;
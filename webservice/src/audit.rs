@@ -0,0 +1,172 @@
+use crate::errors::MyError;
+use crate::security::AccessConfig;
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::future::{ready, Ready};
+use tracing_actix_web::RequestId;
+
+pub const ACTOR_HEADER: &str = "X-Actor";
+const MAX_ACTOR_LENGTH: usize = 100;
+
+// Who made a request and which request it was, recorded along with every mutation.
+// The actor is taken from the `X-Actor` header, set by a trusted gateway once it has
+// authenticated the caller.
+#[derive(Clone, Debug)]
+pub struct AuditContext {
+    pub actor: String,
+    pub request_id: Option<String>,
+}
+
+impl Default for AuditContext {
+    fn default() -> Self {
+        AuditContext {
+            actor: "anonymous".to_owned(),
+            request_id: None,
+        }
+    }
+}
+
+// Validates the value of the actor header, `None` when it is missing and
+// `Some("")` when it isn't readable.
+pub fn parse_actor(value: Option<&str>) -> Result<String, MyError> {
+    match value {
+        None => Ok(AuditContext::default().actor),
+        Some(actor) if !actor.is_empty() && actor.len() <= MAX_ACTOR_LENGTH => Ok(actor.to_owned()),
+        Some(_) => Err(MyError::InvalidInput(format!(
+            "{} must hold between 1 and {} characters",
+            ACTOR_HEADER, MAX_ACTOR_LENGTH
+        ))),
+    }
+}
+
+// Whether a request comes from one of the trusted proxies, the only peers allowed to
+// name its actor and its client.
+pub fn from_trusted_proxy(req: &HttpRequest) -> bool {
+    req.app_data::<web::Data<AccessConfig>>()
+        .is_some_and(|config| config.trusts(req.peer_addr().map(|addr| addr.ip())))
+}
+
+// The address of the client, as forwarded by a trusted proxy, else that of the peer.
+pub fn client_ip(req: &HttpRequest) -> String {
+    if from_trusted_proxy(req) {
+        if let Some(ip) = req.connection_info().realip_remote_addr() {
+            return ip.to_owned();
+        }
+    }
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+// The actor of a request, "anonymous" when it has none or when it doesn't come
+// from one of the trusted proxies, since a client could name anyone.
+pub fn request_actor(req: &HttpRequest) -> Result<String, MyError> {
+    if !from_trusted_proxy(req) {
+        return Ok(AuditContext::default().actor);
+    }
+    parse_actor(
+        req.headers()
+            .get(ACTOR_HEADER)
+            .map(|value| value.to_str().unwrap_or_default()),
+    )
+}
+
+impl FromRequest for AuditContext {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let actor = request_actor(req);
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(|request_id| request_id.to_string());
+        ready(actor.map(|actor| AuditContext { actor, request_id }))
+    }
+}
+
+// The teacher making a request, from an actor of the form `teacher:<id>`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Viewer {
+    pub teacher_id: Option<i32>,
+}
+
+impl Viewer {
+    pub fn from_actor(actor: &str) -> Self {
+        Viewer {
+            teacher_id: actor
+                .strip_prefix("teacher:")
+                .and_then(|id| id.parse().ok()),
+        }
+    }
+}
+
+impl FromRequest for Viewer {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(request_actor(req).map(|actor| Viewer::from_actor(&actor)))
+    }
+}
+
+// Changes with every write, so it would only add noise to the diffs.
+const UNTRACKED_FIELDS: &[&str] = &["updated_at"];
+
+// The fields that differ between two versions of an entity, `None` standing for
+// the missing version of a created or deleted entity.
+pub fn diff<T: Serialize>(before: Option<&T>, after: Option<&T>) -> Result<Value, MyError> {
+    let to_map = |entity: Option<&T>| match entity.map(serde_json::to_value).transpose() {
+        Ok(Some(Value::Object(map))) => Ok(map),
+        Ok(_) => Ok(Map::new()),
+        Err(err) => Err(MyError::ActixError(err.to_string())),
+    };
+    let (before, after) = (to_map(before)?, to_map(after)?);
+    let mut changes = Map::new();
+    for key in before.keys().chain(after.keys()) {
+        let (old, new) = (before.get(key), after.get(key));
+        if old != new && !changes.contains_key(key) && !UNTRACKED_FIELDS.contains(&key.as_str()) {
+            changes.insert(key.clone(), json!({ "before": old, "after": new }));
+        }
+    }
+    Ok(Value::Object(changes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Entity {
+        name: &'static str,
+        level: u8,
+        updated_at: u32,
+    }
+
+    #[test]
+    fn diff_should_only_hold_the_changed_fields() {
+        let before = Entity {
+            name: "Rust",
+            level: 1,
+            updated_at: 1,
+        };
+        let after = Entity {
+            name: "Rust",
+            level: 2,
+            updated_at: 2,
+        };
+        let changes = diff(Some(&before), Some(&after)).unwrap();
+        assert_eq!(changes, json!({ "level": { "before": 1, "after": 2 } }));
+        let created = diff(None, Some(&after)).unwrap();
+        assert_eq!(created["name"], json!({ "before": null, "after": "Rust" }));
+    }
+
+    #[test]
+    fn only_teacher_actors_should_be_viewers() {
+        assert_eq!(Viewer::from_actor("teacher:12").teacher_id, Some(12));
+        assert_eq!(Viewer::from_actor("teacher:").teacher_id, None);
+        assert_eq!(Viewer::from_actor("admin").teacher_id, None);
+    }
+}
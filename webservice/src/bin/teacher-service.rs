@@ -1,46 +1,204 @@
+use actix_web::middleware::Compress;
 use actix_web::{web, App, HttpServer};
+use chrono::{DateTime, Utc};
 use dotenv::dotenv;
-use errors::MyError;
+use handlers::material::MaterialLimits;
 use sqlx::postgres::PgPoolOptions;
 use std::env;
 use std::io;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing_actix_web::TracingLogger;
 
+#[path = "../audit.rs"]
+mod audit;
+#[path = "../blob_cleanup.rs"]
+mod blob_cleanup;
+#[path = "../caching.rs"]
+mod caching;
 #[path = "../dbaccesses/mod.rs"]
 mod dbaccesses;
 #[path = "../errors.rs"]
 mod errors;
+#[path = "../events.rs"]
+mod events;
+#[path = "../graphql.rs"]
+mod graphql;
+#[path = "../grpc.rs"]
+mod grpc;
 #[path = "../handlers/mod.rs"]
 mod handlers;
+#[path = "../idempotency.rs"]
+mod idempotency;
+#[path = "../images.rs"]
+mod images;
 #[path = "../models/mod.rs"]
 mod models;
+#[path = "../negotiation.rs"]
+mod negotiation;
+#[path = "../openapi.rs"]
+mod openapi;
+#[path = "../outbox.rs"]
+mod outbox;
+#[path = "../rate_limit.rs"]
+mod rate_limit;
+#[path = "../read_cache.rs"]
+mod read_cache;
 #[path = "../routers.rs"]
 mod routers;
+#[path = "../schedule.rs"]
+mod schedule;
+#[path = "../security.rs"]
+mod security;
+#[path = "../shutdown.rs"]
+mod shutdown;
 #[path = "../state.rs"]
 mod state;
+#[path = "../storage.rs"]
+mod storage;
+#[path = "../streaming.rs"]
+mod streaming;
+#[path = "../telemetry.rs"]
+mod telemetry;
+#[path = "../tls.rs"]
+mod tls;
+#[path = "../versioning.rs"]
+mod versioning;
 
+use caching::HttpCache;
+use events::{CatalogEvents, CatalogListener};
+use outbox::OutboxConfig;
+use rate_limit::{InMemoryStore, RateLimiter};
+use read_cache::{ReadCache, ReadCacheListener};
 use routers::*;
+use security::{
+    json_config, security_headers, AccessConfig, BodyLimit, CorsConfig, DEFAULT_JSON_LIMIT,
+};
+use shutdown::ShutdownConfig;
 use state::AppState;
+use storage::{BlobStore, LocalFsStore, MaterialStore};
+use tls::{CertificateStore, HttpsRedirect, TlsConfig};
+use versioning::{api_path, Deprecation};
 
 #[actix_rt::main]
 async fn main() -> io::Result<()> {
     dotenv().ok();
+    telemetry::init_telemetry();
     let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missings");
     let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
     let shared_data = web::Data::new(AppState {
         health_check_response: "I'm OK".to_owned(),
         visit_count: Mutex::new(0),
+        shutting_down: AtomicBool::new(false),
+        read_cache: ReadCache::from_env(),
         db: pool_db,
     });
+    let mut deprecation = Deprecation::new().successor(&api_path("v1"));
+    if let Ok(sunset) = env::var("API_LEGACY_SUNSET") {
+        let sunset = DateTime::parse_from_rfc3339(&sunset).expect("API_LEGACY_SUNSET is invalid");
+        deprecation = deprecation.sunset(sunset.with_timezone(&Utc));
+    }
+    let app_state = shared_data.clone();
+    let blob_store: web::Data<dyn BlobStore> =
+        web::Data::from(Arc::new(LocalFsStore::from_env()) as Arc<dyn BlobStore>);
+    let material_store =
+        web::Data::new(MaterialStore(Arc::new(LocalFsStore::materials_from_env())));
+    let blob_cleanup = actix_rt::spawn(blob_cleanup::run_blob_cleanup(
+        app_state.clone(),
+        material_store.clone(),
+        blob_cleanup::cleanup_interval_from_env(),
+    ));
+    let material_limits = web::Data::new(MaterialLimits::from_env());
+    let catalog_events = web::Data::new(CatalogEvents::new(1024));
+    let catalog_schema = web::Data::new(graphql::build_schema(app_state.db.clone()));
+    let graphiql_enabled = graphql::graphiql_enabled();
+    let catalog_listener = CatalogListener::connect(&app_state.db)
+        .await
+        .expect("Unable to listen to the catalog changes");
+    let catalog_listener =
+        actix_rt::spawn(catalog_listener.run(app_state.clone(), catalog_events.clone()));
+    let read_cache_listener = ReadCacheListener::connect(&app_state.db)
+        .await
+        .expect("Unable to listen to the read cache invalidations");
+    let read_cache_listener = actix_rt::spawn(read_cache_listener.run(app_state.clone()));
+    let cors_config = CorsConfig::from_env().expect("The CORS configuration is invalid");
+    let access_config = AccessConfig::from_env().expect("The access configuration is invalid");
+    let access = web::Data::new(access_config.clone());
+    let outbox_config = OutboxConfig::from_env();
+    let rate_limiter = RateLimiter::from_env(Arc::new(InMemoryStore::new()));
+    let http_cache = HttpCache::from_env();
+    let tls_config = TlsConfig::from_env();
+    let https_redirect = match &tls_config {
+        Some(tls_config) => HttpsRedirect::new(tls_config.redirect_http, tls_config.https_port()),
+        None => HttpsRedirect::new(false, 443),
+    };
     let app = move || {
         App::new()
+            .wrap(http_cache.clone())
+            // Outside the cache, so that its weak `ETag` is that of the uncompressed body.
+            .wrap(Compress::default())
+            .wrap(https_redirect.clone())
+            .wrap(rate_limiter.clone())
+            .wrap(security_headers())
+            .wrap(cors_config.cors())
+            .wrap(TracingLogger::default())
             .app_data(shared_data.clone())
-            .app_data(web::JsonConfig::default().error_handler(|_err, _req| {
-                MyError::InvalidInput("Please provide valid JSON input".to_owned()).into()
-            }))
+            .app_data(access.clone())
+            .app_data(web::Data::new(outbox_config))
+            .app_data(blob_store.clone())
+            .app_data(material_store.clone())
+            .app_data(material_limits.clone())
+            .app_data(catalog_events.clone())
+            .app_data(catalog_schema.clone())
+            .app_data(json_config(DEFAULT_JSON_LIMIT))
+            .app_data(BodyLimit(DEFAULT_JSON_LIMIT))
             .configure(routes_general)
-            .configure(routes_course)
-            .configure(routes_teacher)
+            .configure(routes_openapi)
+            .configure(|cfg| routes_graphql(cfg, graphiql_enabled))
+            .configure(routes_v1)
+            .configure(|cfg| routes_legacy(cfg, &deprecation))
     };
-    HttpServer::new(app).bind("127.0.0.1:3000")?.run().await
+    let shutdown_config = ShutdownConfig::from_env();
+    let bind_address = env::var("BIND_ADDRESS").unwrap_or_else(|_| "127.0.0.1:3000".to_owned());
+    let grpc_address = env::var("GRPC_BIND_ADDRESS")
+        .unwrap_or_else(|_| "127.0.0.1:50051".to_owned())
+        .parse()
+        .expect("GRPC_BIND_ADDRESS is invalid");
+    let grpc_incoming = grpc::bind(grpc_address)?;
+    let mut server = HttpServer::new(app)
+        .shutdown_timeout(shutdown_config.timeout.as_secs())
+        .disable_signals()
+        .bind(&bind_address)?;
+    if let Some(tls_config) = tls_config {
+        let certificates = CertificateStore::new(tls_config.clone())?;
+        server = server.bind_openssl(&tls_config.bind_address, certificates.acceptor_builder()?)?;
+        actix_rt::spawn(tls::reload_on_sighup(certificates));
+    }
+    let server = server.run();
+    actix_rt::spawn(shutdown::shutdown_on_signal(
+        server.handle(),
+        app_state.clone(),
+        shutdown_config,
+    ));
+    let outbox_worker =
+        actix_rt::spawn(outbox::run_outbox_worker(app_state.clone(), outbox_config));
+    let grpc_server = actix_rt::spawn(grpc::serve(grpc_incoming, app_state.clone(), access_config));
+    let result = server.await;
+    // Already set on a signal, but the server may also have stopped on an error. The tasks
+    // using the pool are then waited for, so that none of them runs against a closed pool.
+    app_state.shutting_down.store(true, Ordering::SeqCst);
+    for task in [
+        grpc_server,
+        outbox_worker,
+        blob_cleanup,
+        catalog_listener,
+        read_cache_listener,
+    ] {
+        if let Err(err) = task.await {
+            tracing::error!("A background task failed: {}", err);
+        }
+    }
+    app_state.db.close().await;
+    telemetry::shutdown_telemetry();
+    result
 }
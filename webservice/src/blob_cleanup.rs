@@ -0,0 +1,125 @@
+use crate::dbaccesses::material::{delete_orphaned_blob_db, get_orphaned_blobs_db};
+use crate::errors::MyError;
+use crate::shutdown::sleep_unless_shutting_down;
+use crate::state::AppState;
+use crate::storage::MaterialStore;
+use actix_web::web;
+use sqlx::postgres::PgPool;
+use std::env;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+const BATCH_SIZE: i64 = 100;
+
+pub fn cleanup_interval_from_env() -> Duration {
+    Duration::from_secs(
+        env::var("BLOB_CLEANUP_INTERVAL_SECS")
+            .map(|value| {
+                value
+                    .parse()
+                    .expect("BLOB_CLEANUP_INTERVAL_SECS is invalid")
+            })
+            .unwrap_or(60),
+    )
+}
+
+// Removes a batch of the blobs left behind by deleted materials, and returns its size.
+// Removing a blob twice is harmless, so several instances can run this at once.
+pub async fn remove_orphaned_blobs(
+    pool: &PgPool,
+    material_store: &MaterialStore,
+) -> Result<usize, MyError> {
+    let blobs = get_orphaned_blobs_db(pool, BATCH_SIZE).await?;
+    for (id, storage_key) in &blobs {
+        material_store.delete(storage_key).await?;
+        delete_orphaned_blob_db(pool, *id).await?;
+    }
+    Ok(blobs.len())
+}
+
+// Runs until shutdown, and goes on right away while full batches come back.
+pub async fn run_blob_cleanup(
+    app_state: web::Data<AppState>,
+    material_store: web::Data<MaterialStore>,
+    interval: Duration,
+) {
+    while !app_state.shutting_down.load(Ordering::SeqCst) {
+        match remove_orphaned_blobs(&app_state.db, &material_store).await {
+            Ok(count) if count as i64 == BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(err) => tracing::error!("Unable to remove the orphaned blobs: {}", err),
+        }
+        sleep_unless_shutting_down(&app_state, interval).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::AuditContext;
+    use crate::dbaccesses::course::{create_one_course_db, delete_one_course_db};
+    use crate::dbaccesses::material::create_one_material_db;
+    use crate::models::course::CourseCreation;
+    use crate::models::material::CourseMaterialCreation;
+    use crate::storage::LocalFsStore;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::sync::Arc;
+
+    #[actix_rt::test]
+    async fn blobs_of_deleted_courses_should_be_removed() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let root = env::temp_dir().join(format!(
+            "materials-{}",
+            chrono::Utc::now().timestamp_nanos()
+        ));
+        let material_store = MaterialStore(Arc::new(LocalFsStore::new(&root)));
+        let course = create_one_course_db(
+            &pool,
+            CourseCreation {
+                teacher_id: 1,
+                name: "Course with materials".into(),
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: None,
+                language: None,
+                level: None,
+            },
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+        let storage_key = format!("courses/{}/materials/1.txt", course.id);
+        material_store
+            .put(&storage_key, b"notes".to_vec())
+            .await
+            .unwrap();
+        let new_material = CourseMaterialCreation {
+            course_id: course.id,
+            teacher_id: course.teacher_id,
+            file_name: "notes.txt".into(),
+            content_type: "text/plain".into(),
+            size_bytes: 5,
+            checksum: "0".repeat(64),
+            storage_key: storage_key.clone(),
+        };
+        create_one_material_db(&pool, new_material, i64::MAX, &AuditContext::default())
+            .await
+            .unwrap();
+        delete_one_course_db(
+            &pool,
+            course.teacher_id,
+            course.id,
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+        while remove_orphaned_blobs(&pool, &material_store).await.unwrap() as i64 == BATCH_SIZE {}
+        assert!(material_store.get(&storage_key).await.is_err());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,431 @@
+use crate::audit::{request_actor, AuditContext};
+use crate::errors::MyError;
+use actix_web::body::{self, BodySize, BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{Error, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures_util::future::LocalBoxFuture;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+
+// Caches may keep the responses, but have to revalidate them, which mostly costs a 304.
+pub const DEFAULT_CACHE_CONTROL: &str = "no-cache";
+// Sent instead of the policy of the route to the teachers and administrators, whose
+// responses may hold drafts that a shared cache mustn't serve to anyone else.
+const PERSONAL_CACHE_CONTROL: &str = "private, no-cache";
+// The headers naming the caller, which decides whether drafts are seen.
+const VARY_CALLER: &str = "X-Actor, Authorization";
+// Larger or streamed bodies are sent as they are, without an `ETag`.
+const MAX_ETAG_BODY_SIZE: u64 = 1024 * 1024;
+
+// Sets `Last-Modified`, compared by `HttpCache` with `If-Modified-Since`.
+pub fn last_modified(mut resp: HttpResponse, time: Option<DateTime<Utc>>) -> HttpResponse {
+    let value = time.and_then(|time| {
+        HeaderValue::from_str(&time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()).ok()
+    });
+    if let Some(value) = value {
+        resp.headers_mut().insert(header::LAST_MODIFIED, value);
+    }
+    resp
+}
+
+// Weak, since the body may then be compressed: its gzip and identity variants share it.
+fn etag(body: &[u8]) -> String {
+    format!("W/\"{:x}\"", Sha256::digest(body))
+}
+
+// The version of a streamed list, which gets no `ETag` from its body.
+#[derive(Clone, Copy, Debug)]
+pub struct ListVersion {
+    pub last_modified: Option<DateTime<Utc>>,
+    pub count: i64,
+}
+
+// Sets `Last-Modified` and a weak `ETag` made of the version of the list and of its content
+// type, so that any change, deletion or other representation gets another one.
+pub fn list_version(resp: HttpResponse, version: ListVersion) -> HttpResponse {
+    let mut resp = last_modified(resp, version.last_modified);
+    let content_type = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let tag = format!(
+        "{}|{}|{}",
+        content_type,
+        version.count,
+        version
+            .last_modified
+            .map_or(0, |time| time.timestamp_nanos())
+    );
+    if let Ok(value) = HeaderValue::from_str(&etag(tag.as_bytes())) {
+        resp.headers_mut().insert(header::ETAG, value);
+    }
+    resp
+}
+
+// `If-None-Match` uses the weak comparison, so `W/` prefixes are ignored.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/"))
+}
+
+fn not_modified_since(if_modified_since: &str, last_modified: &str) -> bool {
+    match (
+        DateTime::parse_from_rfc2822(if_modified_since),
+        DateTime::parse_from_rfc2822(last_modified),
+    ) {
+        (Ok(since), Ok(last_modified)) => last_modified.timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+// Adds an `ETag` to the successful GET responses, and answers the conditional GETs whose
+// `If-None-Match` or, failing that, `If-Modified-Since` still holds with a 304.
+// `Cache-Control` is set by route, unless the handler set it, and `Vary` names the headers
+// identifying the caller.
+#[derive(Clone, Debug, Default)]
+pub struct HttpCache {
+    policies: Arc<Vec<(String, HeaderValue)>>,
+}
+
+impl HttpCache {
+    pub fn new() -> Self {
+        HttpCache::default()
+    }
+
+    // The `Cache-Control` of the routes whose pattern ends with `pattern`, so that it holds
+    // for every API version.
+    pub fn policy(mut self, pattern: &str, cache_control: &str) -> Self {
+        let cache_control = HeaderValue::from_str(cache_control).expect("Invalid Cache-Control");
+        Arc::make_mut(&mut self.policies).push((pattern.to_owned(), cache_control));
+        self
+    }
+
+    pub fn from_env() -> Self {
+        let cache_control =
+            |name: &str| env::var(name).unwrap_or_else(|_| DEFAULT_CACHE_CONTROL.to_owned());
+        let teacher_list = cache_control("CACHE_CONTROL_TEACHER_LIST");
+        let teacher = cache_control("CACHE_CONTROL_TEACHER");
+        let course_list = cache_control("CACHE_CONTROL_COURSE_LIST");
+        let course = cache_control("CACHE_CONTROL_COURSE");
+        HttpCache::new()
+            .policy("/teachers/", &teacher_list)
+            .policy("/teachers/{teacher_id}", &teacher)
+            .policy("/teachers/{teacher_id}/courses", &course_list)
+            .policy("/courses/{course_id}", &course)
+            // The unversioned course routes.
+            .policy("/courses/{teacher_id}", &course_list)
+            .policy("/courses/{teacher_id}/{course_id}", &course)
+    }
+
+    fn cache_control(&self, pattern: &str) -> Option<&HeaderValue> {
+        self.policies
+            .iter()
+            .find(|(suffix, _)| pattern.ends_with(suffix.as_str()))
+            .map(|(_, cache_control)| cache_control)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for HttpCache
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = HttpCacheMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HttpCacheMiddleware {
+            service: Rc::new(service),
+            cache: self.clone(),
+        }))
+    }
+}
+
+pub struct HttpCacheMiddleware<S> {
+    service: Rc<S>,
+    cache: HttpCache,
+}
+
+impl<S, B> Service<ServiceRequest> for HttpCacheMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            if req.method() != Method::GET {
+                return Ok(service.call(req).await?.map_into_left_body());
+            }
+            let header = |name: header::HeaderName| {
+                req.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_owned())
+            };
+            let if_none_match = header(header::IF_NONE_MATCH);
+            let if_modified_since = header(header::IF_MODIFIED_SINCE);
+            let personal = request_actor(req.request())
+                .is_ok_and(|actor| actor != AuditContext::default().actor);
+            let mut res = service.call(req).await?;
+            if res.status() != StatusCode::OK {
+                return Ok(res.map_into_left_body());
+            }
+            let cache_control = if personal {
+                Some(HeaderValue::from_static(PERSONAL_CACHE_CONTROL))
+            } else {
+                res.request()
+                    .match_pattern()
+                    .and_then(|pattern| cache.cache_control(&pattern).cloned())
+            };
+            res.headers_mut()
+                .append(header::VARY, HeaderValue::from_static(VARY_CALLER));
+            if let Some(cache_control) = cache_control {
+                if !res.headers().contains_key(header::CACHE_CONTROL) {
+                    res.headers_mut()
+                        .insert(header::CACHE_CONTROL, cache_control);
+                }
+            }
+            let (req, res) = res.into_parts();
+            let (mut res, body) = res.into_parts();
+            // Streamed bodies keep streaming, and are only compared by date.
+            let body = match body.size() {
+                BodySize::Sized(size) if size <= MAX_ETAG_BODY_SIZE => {
+                    let body = body::to_bytes(body).await.map_err(|err| {
+                        let err: Box<dyn std::error::Error> = err.into();
+                        MyError::ActixError(err.to_string())
+                    })?;
+                    if !res.headers().contains_key(header::ETAG) {
+                        if let Ok(value) = HeaderValue::from_str(&etag(&body)) {
+                            res.headers_mut().insert(header::ETAG, value);
+                        }
+                    }
+                    EitherBody::right(BoxBody::new(body))
+                }
+                _ => EitherBody::left(body),
+            };
+            let response_header = |name: header::HeaderName| {
+                res.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default()
+            };
+            let not_modified = match (if_none_match, if_modified_since) {
+                (Some(if_none_match), _) => {
+                    etag_matches(&if_none_match, response_header(header::ETAG))
+                }
+                (None, Some(if_modified_since)) => {
+                    not_modified_since(&if_modified_since, response_header(header::LAST_MODIFIED))
+                }
+                (None, None) => false,
+            };
+            if not_modified {
+                let mut res = res.set_body(EitherBody::right(BoxBody::new(())));
+                *res.status_mut() = StatusCode::NOT_MODIFIED;
+                res.headers_mut().remove(header::CONTENT_TYPE);
+                res.headers_mut().remove(header::CONTENT_LENGTH);
+                return Ok(ServiceResponse::new(req, res));
+            }
+            Ok(ServiceResponse::new(req, res.set_body(body)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::ACTOR_HEADER;
+    use crate::security::AccessConfig;
+    use actix_web::{test, web, App};
+    use chrono::TimeZone;
+
+    async fn get_item() -> HttpResponse {
+        let time = Utc.ymd(2022, 8, 15).and_hms(12, 0, 0);
+        last_modified(
+            HttpResponse::Ok().json(serde_json::json!({ "id": 1 })),
+            Some(time),
+        )
+    }
+
+    async fn get_streamed_list() -> HttpResponse {
+        let time = Utc.ymd(2022, 8, 15).and_hms(12, 0, 0);
+        let chunks = futures_util::stream::iter(vec![Ok::<_, Error>(web::Bytes::from("[]"))]);
+        last_modified(HttpResponse::Ok().streaming(chunks), Some(time))
+    }
+
+    async fn get_versioned_list(req: actix_web::HttpRequest) -> HttpResponse {
+        let time = Utc.ymd(2022, 8, 15).and_hms(12, 0, 0);
+        let chunks = futures_util::stream::iter(vec![Ok::<_, Error>(web::Bytes::from("[]"))]);
+        let content_type = if req.query_string() == "csv" {
+            "text/csv"
+        } else {
+            "application/json"
+        };
+        let resp = HttpResponse::Ok()
+            .content_type(content_type)
+            .streaming(chunks);
+        list_version(
+            resp,
+            ListVersion {
+                last_modified: Some(time),
+                count: 0,
+            },
+        )
+    }
+
+    #[actix_rt::test]
+    async fn conditional_gets_should_be_answered_with_304() {
+        let app = test::init_service(
+            App::new()
+                .wrap(HttpCache::new().policy("/items/{id}", "public, max-age=60"))
+                .route("/items/{id}", web::get().to(get_item))
+                .route("/other", web::get().to(get_item))
+                .route("/list", web::get().to(get_streamed_list))
+                .route("/versioned-list", web::get().to(get_versioned_list)),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/items/1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=60"
+        );
+        assert_eq!(
+            resp.headers().get(header::LAST_MODIFIED).unwrap(),
+            "Mon, 15 Aug 2022 12:00:00 GMT"
+        );
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+
+        let req = test::TestRequest::get()
+            .uri("/items/1")
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), &etag);
+        assert!(test::read_body(resp).await.is_empty());
+
+        // `If-None-Match` takes precedence over `If-Modified-Since`.
+        let req = test::TestRequest::get()
+            .uri("/items/1")
+            .insert_header((header::IF_NONE_MATCH, "\"stale\""))
+            .insert_header((header::IF_MODIFIED_SINCE, "Mon, 15 Aug 2022 12:00:00 GMT"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get()
+            .uri("/other")
+            .insert_header((header::IF_MODIFIED_SINCE, "Tue, 16 Aug 2022 00:00:00 GMT"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert!(resp.headers().get(header::CACHE_CONTROL).is_none());
+
+        let req = test::TestRequest::get()
+            .uri("/other")
+            .insert_header((header::IF_MODIFIED_SINCE, "Sun, 14 Aug 2022 00:00:00 GMT"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Streamed bodies get no `ETag`, but are still compared by date.
+        let req = test::TestRequest::get()
+            .uri("/list")
+            .insert_header((header::IF_MODIFIED_SINCE, "Tue, 16 Aug 2022 00:00:00 GMT"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        let req = test::TestRequest::get().uri("/list").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get(header::ETAG).is_none());
+        assert_eq!(test::read_body(resp).await, "[]");
+
+        // Unless they set a weak one from their version, which differs by content type.
+        let req = test::TestRequest::get().uri("/versioned-list").to_request();
+        let resp = test::call_service(&app, req).await;
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        assert!(etag.to_str().unwrap().starts_with("W/\""));
+        assert_eq!(test::read_body(resp).await, "[]");
+        let req = test::TestRequest::get()
+            .uri("/versioned-list")
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        let req = test::TestRequest::get()
+            .uri("/versioned-list?csv")
+            .insert_header((header::IF_NONE_MATCH, etag))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn responses_to_an_actor_should_be_private() {
+        let proxy: std::net::SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let config = AccessConfig {
+            trusted_proxies: vec![proxy.ip()],
+            ..AccessConfig::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .wrap(HttpCache::new().policy("/items/{id}", "public, max-age=60"))
+                .route("/items/{id}", web::get().to(get_item)),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/items/1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=60"
+        );
+        assert_eq!(
+            resp.headers().get(header::VARY).unwrap(),
+            "X-Actor, Authorization"
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/items/1")
+            .peer_addr(proxy)
+            .insert_header((ACTOR_HEADER, "teacher:1"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(header::CACHE_CONTROL).unwrap(),
+            "private, no-cache"
+        );
+        assert_eq!(
+            resp.headers().get(header::VARY).unwrap(),
+            "X-Actor, Authorization"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn weak_etags_should_match() {
+        assert!(etag_matches("W/\"abc\", \"def\"", "\"abc\""));
+        assert!(etag_matches("*", "\"abc\""));
+        assert!(!etag_matches("\"abcd\"", "\"abc\""));
+    }
+}
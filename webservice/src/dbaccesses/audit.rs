@@ -0,0 +1,54 @@
+use crate::audit::AuditContext;
+use crate::errors::MyError;
+use crate::models::audit::{AuditEvent, AuditEventCreation, AuditEventQuery};
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::Transaction;
+use tracing::instrument;
+
+pub const MAX_AUDIT_EVENTS: i64 = 1000;
+
+// Takes the transaction of the mutation, so that the event is only kept along with it.
+pub async fn insert_audit_event_db(
+    tx: &mut Transaction<'_, Postgres>,
+    audit: &AuditContext,
+    event: AuditEventCreation,
+) -> Result<(), MyError> {
+    sqlx::query!(
+        r#"INSERT INTO audit_event (actor, action, entity_type, entity_id, diff, request_id)
+        VALUES ($1, $2, $3, $4, $5, $6)"#,
+        audit.actor,
+        event.action,
+        event.entity_type,
+        event.entity_id,
+        event.diff,
+        audit.request_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "audit_event"))]
+pub async fn get_audit_events_db(
+    pool: &PgPool,
+    query: AuditEventQuery,
+) -> Result<Vec<AuditEvent>, MyError> {
+    let rows: Vec<AuditEvent> = sqlx::query_as!(
+        AuditEvent,
+        r#"SELECT * FROM audit_event
+        WHERE ($1::varchar IS NULL or entity_type = $1)
+            and ($2::int IS NULL or entity_id = $2)
+            and ($3::timestamptz IS NULL or time >= $3)
+            and ($4::timestamptz IS NULL or time < $4)
+        ORDER BY time, id
+        LIMIT $5"#,
+        query.entity_type,
+        query.entity_id,
+        query.from,
+        query.to,
+        query.limit.unwrap_or(MAX_AUDIT_EVENTS).clamp(1, MAX_AUDIT_EVENTS),
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
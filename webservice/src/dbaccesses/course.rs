@@ -1,32 +1,187 @@
+use crate::audit::{diff, AuditContext};
+use crate::caching::ListVersion;
+use crate::dbaccesses::audit::insert_audit_event_db;
+use crate::dbaccesses::outbox::insert_outbox_event_db;
+use crate::dbaccesses::revision::{get_latest_revision_db, insert_course_revision_db};
 use crate::errors::MyError;
-use crate::models::course::{Course, CourseCreation, CourseUpdate};
+use crate::models::audit::AuditEventCreation;
+use crate::models::course::{
+    Course, CourseCreation, CourseFilter, CourseSort, CourseStatus, CourseTransition,
+    CourseTransitionCreation, CourseUpdate,
+};
+use crate::models::revision::CourseSnapshot;
+use crate::models::webhook::OutboxEventCreation;
+use crate::streaming::fetch_rows;
+use futures_util::{Stream, TryStreamExt};
 use sqlx::postgres::PgPool;
+use tracing::instrument;
 
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course", teacher_id))]
 pub async fn get_courses_for_teacher_db(
     pool: &PgPool,
     teacher_id: i32,
+    sort: CourseSort,
 ) -> Result<Vec<Course>, MyError> {
     let rows: Vec<Course> = sqlx::query_as!(
         Course,
-        "SELECT * FROM course WHERE teacher_id = $1",
-        teacher_id
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view WHERE teacher_id = $1 and status = 'published'
+        ORDER BY CASE WHEN $2 THEN rating_average END DESC NULLS LAST,
+            CASE WHEN $2 THEN rating_count END DESC, id"#,
+        teacher_id,
+        sort == CourseSort::Rating,
     )
     .fetch_all(pool)
     .await?;
     Ok(rows)
 }
 
-pub async fn get_one_course_detail_db(
+// The same courses, streamed from the query as they come.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course", teacher_id))]
+pub fn stream_courses_for_teacher_db(
+    pool: &PgPool,
+    teacher_id: i32,
+    sort: CourseSort,
+) -> impl Stream<Item = Result<Course, MyError>> {
+    let pool = pool.clone();
+    fetch_rows(move |courses| async move {
+        let mut rows = sqlx::query_as!(
+            Course,
+            r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+            FROM course_view WHERE teacher_id = $1 and status = 'published'
+            ORDER BY CASE WHEN $2 THEN rating_average END DESC NULLS LAST,
+                CASE WHEN $2 THEN rating_count END DESC, id"#,
+            teacher_id,
+            sort == CourseSort::Rating,
+        )
+        .fetch(&pool);
+        while let Some(course) = rows.try_next().await? {
+            courses.send(course).await?;
+        }
+        Ok(())
+    })
+}
+
+// The time of the last change to the courses of a teacher, whatever their status since
+// leaving `published` removes a course from the list, deletions included, and the number
+// of their published courses.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course", teacher_id))]
+pub async fn get_courses_version_db(
     pool: &PgPool,
     teacher_id: i32,
+) -> Result<ListVersion, MyError> {
+    let row = sqlx::query!(
+        r#"SELECT greatest(
+            (SELECT max(updated_at) FROM course WHERE teacher_id = $1),
+            (SELECT max(time) FROM outbox_event
+                WHERE teacher_id = $1 and event_type IN ('TeacherDeleted', 'CourseDeleted'))
+        ) as last_modified,
+        (SELECT count(*) FROM course WHERE teacher_id = $1 and status = 'published') as "count!""#,
+        teacher_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(ListVersion {
+        last_modified: row.last_modified,
+        count: row.count,
+    })
+}
+
+// The published courses of several teachers in one query, by teacher and id.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course"))]
+pub async fn get_courses_for_teachers_db(
+    pool: &PgPool,
+    teacher_ids: &[i32],
+) -> Result<Vec<Course>, MyError> {
+    let rows: Vec<Course> = sqlx::query_as!(
+        Course,
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view WHERE teacher_id = ANY($1) and status = 'published'
+        ORDER BY teacher_id, id"#,
+        teacher_ids
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+// The published courses matching `filter`, by id order after the `after` id.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course"))]
+pub async fn get_courses_page_db(
+    pool: &PgPool,
+    filter: CourseFilter,
+    after: i32,
+    limit: i64,
+) -> Result<Vec<Course>, MyError> {
+    let rows: Vec<Course> = sqlx::query_as!(
+        Course,
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view
+        WHERE status = 'published'
+            and ($1::int IS NULL or teacher_id = $1)
+            and ($2::varchar IS NULL or language = $2)
+            and ($3::varchar IS NULL or level = $3)
+            and ($4::int IS NULL or price <= $4)
+            and ($5::float8 IS NULL or rating_average >= $5)
+            and id > $6
+        ORDER BY id
+        LIMIT $7"#,
+        filter.teacher_id,
+        filter.language,
+        filter.level,
+        filter.max_price,
+        filter.min_rating,
+        after,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+// Any course whatever its status, for the changes made by its teacher.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course", course_id))]
+pub async fn get_one_course_by_id_db(pool: &PgPool, course_id: i32) -> Result<Course, MyError> {
+    let row: Option<Course> = sqlx::query_as!(
+        Course,
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view WHERE id = $1"#,
+        course_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(course) = row {
+        Ok(course)
+    } else {
+        Err(MyError::NotFound("Course not found".into()))
+    }
+}
+
+// A published course, or a course of `viewer` whatever its status.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course", course_id))]
+pub async fn get_one_visible_course_db(
+    pool: &PgPool,
     course_id: i32,
+    viewer: Option<i32>,
 ) -> Result<Course, MyError> {
     let row: Option<Course> = sqlx::query_as!(
         Course,
-        r#"SELECT * FROM course
-        WHERE teacher_id = $1 and id = $2"#,
-        teacher_id,
-        course_id
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view WHERE id = $1 and (status = 'published' or teacher_id = $2)"#,
+        course_id,
+        viewer
     )
     .fetch_optional(pool)
     .await?;
@@ -37,15 +192,39 @@ pub async fn get_one_course_detail_db(
     }
 }
 
+// The courses of a teacher that aren't published, drafts first.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course", teacher_id))]
+pub async fn get_unpublished_courses_for_teacher_db(
+    pool: &PgPool,
+    teacher_id: i32,
+) -> Result<Vec<Course>, MyError> {
+    let rows: Vec<Course> = sqlx::query_as!(
+        Course,
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view WHERE teacher_id = $1 and status <> 'published'
+        ORDER BY CASE status WHEN 'draft' THEN 0 WHEN 'in_review' THEN 1 ELSE 2 END, id"#,
+        teacher_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course"))]
 pub async fn create_one_course_db(
     pool: &PgPool,
     new_course: CourseCreation,
+    audit: &AuditContext,
 ) -> Result<Course, MyError> {
+    let mut tx = pool.begin().await?;
     let row = sqlx::query_as!(
         Course,
         r#"INSERT INTO course (teacher_id, name, description, format, structure, duration, price, language, level)
         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-        RETURNING id, teacher_id, name, time, description, format, structure, duration, price, language, level"#,
+        RETURNING id, teacher_id, name, time, description, format, structure, duration, price, language, level,
+            rating_average, rating_count, status as "status: CourseStatus", updated_at, 0::bigint as "duration_minutes!""#,
         new_course.teacher_id,
         new_course.name,
         new_course.description,
@@ -56,41 +235,86 @@ pub async fn create_one_course_db(
         new_course.language,
         new_course.level,
     )
-    .fetch_one(pool)
+    .fetch_one(&mut tx)
     .await?;
+    let event = AuditEventCreation {
+        action: "create",
+        entity_type: "course",
+        entity_id: row.id,
+        diff: diff(None, Some(&row))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    let event = OutboxEventCreation::new("CourseCreated", row.teacher_id, row.id, &row)?;
+    insert_outbox_event_db(&mut tx, event).await?;
+    tx.commit().await?;
     Ok(row)
 }
 
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course", teacher_id, id))]
 pub async fn delete_one_course_db(
     pool: &PgPool,
     teacher_id: i32,
     id: i32,
+    audit: &AuditContext,
 ) -> Result<String, MyError> {
+    let mut tx = pool.begin().await?;
+    let current = sqlx::query_as!(
+        Course,
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view WHERE teacher_id = $1 and id = $2 FOR UPDATE"#,
+        teacher_id,
+        id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
     let row = sqlx::query!(
         "DELETE FROM course WHERE teacher_id = $1 and id = $2",
         teacher_id,
         id,
     )
-    .execute(pool)
+    .execute(&mut tx)
     .await?;
+    if let Some(course) = current {
+        let event = AuditEventCreation {
+            action: "delete",
+            entity_type: "course",
+            entity_id: course.id,
+            diff: diff(Some(&course), None)?,
+        };
+        insert_audit_event_db(&mut tx, audit, event).await?;
+        let event =
+            OutboxEventCreation::new("CourseDeleted", course.teacher_id, course.id, &course)?;
+        insert_outbox_event_db(&mut tx, event).await?;
+    }
+    tx.commit().await?;
     Ok(format!("Deleted {:?} record", row))
 }
 
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course", teacher_id, id))]
 pub async fn update_one_course_detail_db(
     pool: &PgPool,
     teacher_id: i32,
     id: i32,
     course_update: CourseUpdate,
+    audit: &AuditContext,
 ) -> Result<Course, MyError> {
+    let mut tx = pool.begin().await?;
     let current_course_row = sqlx::query_as!(
         Course,
-        "SELECT * FROM course WHERE teacher_id = $1 and id = $2",
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view WHERE teacher_id = $1 and id = $2 FOR UPDATE"#,
         teacher_id,
         id
     )
-    .fetch_one(pool)
+    .fetch_one(&mut tx)
     .await
     .map_err(|_err| MyError::NotFound("Course not found".into()))?;
+    let before = current_course_row.clone();
+    insert_course_revision_db(&mut tx, audit, &before).await?;
     let name: String = if let Some(name) = course_update.name {
         name
     } else {
@@ -116,11 +340,7 @@ pub async fn update_one_course_detail_db(
     } else {
         current_course_row.structure.unwrap_or_default()
     };
-    let duration: String = if let Some(duration) = course_update.duration {
-        duration
-    } else {
-        current_course_row.duration.unwrap_or_default()
-    };
+    let duration = course_update.duration.or(current_course_row.duration);
     let level: String = if let Some(level) = course_update.level {
         level
     } else {
@@ -131,29 +351,183 @@ pub async fn update_one_course_detail_db(
     } else {
         current_course_row.price.unwrap_or_default()
     };
-    let course_row = sqlx::query_as!(
-        Course,
-        r#"
-        UPDATE course SET name = $1, description = $2, format = $3, structure = $4, duration = $5, price = $6, language = $7, level = $8
-        WHERE teacher_id = $9 and id = $10
-        RETURNING id, teacher_id, name, time, description, format, structure, duration, price, language, level
-        "#,
+    sqlx::query!(
+        "UPDATE course SET name = $1, description = $2, format = $3, structure = $4, price = $5, language = $6,
+            level = $7, duration = $10, updated_at = now()
+        WHERE teacher_id = $8 and id = $9",
         name,
         description,
         format,
         structure,
-        duration,
         price,
         language,
         level,
         teacher_id,
+        id,
+        duration
+    )
+    .execute(&mut tx)
+    .await?;
+    let course_row = sqlx::query_as!(
+        Course,
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view WHERE id = $1"#,
         id
     )
-    .fetch_one(pool)
-    .await;
-    if let Ok(course) = course_row {
-        Ok(course)
-    } else {
-        Err(MyError::NotFound("Course not found".into()))
+    .fetch_one(&mut tx)
+    .await?;
+    let event = AuditEventCreation {
+        action: "update",
+        entity_type: "course",
+        entity_id: id,
+        diff: diff(Some(&before), Some(&course_row))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    let event = OutboxEventCreation::new("CourseUpdated", teacher_id, id, &course_row)?;
+    insert_outbox_event_db(&mut tx, event).await?;
+    tx.commit().await?;
+    Ok(course_row)
+}
+
+// Unlike an update, a restore writes every field of the snapshot, NULLs included.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course", id))]
+pub async fn restore_one_course_db(
+    pool: &PgPool,
+    id: i32,
+    snapshot: CourseSnapshot,
+    expected_revision: i32,
+    audit: &AuditContext,
+) -> Result<Course, MyError> {
+    let mut tx = pool.begin().await?;
+    let before = sqlx::query_as!(
+        Course,
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view WHERE id = $1 FOR UPDATE"#,
+        id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Course not found".into()))?;
+    let latest_revision = get_latest_revision_db(&mut tx, id).await?;
+    if latest_revision != expected_revision {
+        return Err(MyError::Conflict(format!(
+            "The course was updated since revision {}, its newest revision is {}",
+            expected_revision, latest_revision
+        )));
+    }
+    insert_course_revision_db(&mut tx, audit, &before).await?;
+    sqlx::query!(
+        "UPDATE course SET name = $1, description = $2, format = $3, structure = $4, duration = $5, price = $6,
+            language = $7, level = $8, updated_at = now()
+        WHERE id = $9",
+        snapshot.name,
+        snapshot.description,
+        snapshot.format,
+        snapshot.structure,
+        snapshot.duration,
+        snapshot.price,
+        snapshot.language,
+        snapshot.level,
+        id
+    )
+    .execute(&mut tx)
+    .await?;
+    let course_row = sqlx::query_as!(
+        Course,
+        r#"SELECT teacher_id as "teacher_id!", id as "id!", name as "name!", time, description, format, structure,
+            duration, price, language, level, rating_average, rating_count as "rating_count!",
+            status as "status!: CourseStatus", updated_at as "updated_at!", duration_minutes as "duration_minutes!"
+        FROM course_view WHERE id = $1"#,
+        id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    let event = AuditEventCreation {
+        action: "restore",
+        entity_type: "course",
+        entity_id: id,
+        diff: diff(Some(&before), Some(&course_row))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    let event = OutboxEventCreation::new("CourseUpdated", course_row.teacher_id, id, &course_row)?;
+    insert_outbox_event_db(&mut tx, event).await?;
+    tx.commit().await?;
+    Ok(course_row)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_status_transition", course_id))]
+pub async fn get_transitions_for_course_db(
+    pool: &PgPool,
+    course_id: i32,
+) -> Result<Vec<CourseTransition>, MyError> {
+    let rows: Vec<CourseTransition> = sqlx::query_as!(
+        CourseTransition,
+        "SELECT * FROM course_status_transition WHERE course_id = $1 ORDER BY id",
+        course_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+// The status is read and changed under a row lock, so that two concurrent
+// transitions from the same status can't both succeed.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course", course_id))]
+pub async fn transition_one_course_db(
+    pool: &PgPool,
+    course_id: i32,
+    transition: CourseTransitionCreation,
+    audit: &AuditContext,
+) -> Result<CourseTransition, MyError> {
+    let mut tx = pool.begin().await?;
+    let course = sqlx::query!(
+        r#"SELECT teacher_id, status as "status: CourseStatus" FROM course WHERE id = $1 FOR UPDATE"#,
+        course_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Course not found".into()))?;
+    let from = course.status;
+    if !from.can_move_to(transition.to_status) {
+        return Err(MyError::Conflict(format!(
+            "A course can't move from {} to {}",
+            from.as_str(),
+            transition.to_status.as_str()
+        )));
     }
+    sqlx::query!(
+        "UPDATE course SET status = $1, updated_at = now() WHERE id = $2",
+        transition.to_status.as_str(),
+        course_id
+    )
+    .execute(&mut tx)
+    .await?;
+    let row = sqlx::query_as!(
+        CourseTransition,
+        r#"INSERT INTO course_status_transition (course_id, from_status, to_status, actor)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, course_id, from_status, to_status, actor, time"#,
+        course_id,
+        from.as_str(),
+        transition.to_status.as_str(),
+        audit.actor,
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    let event = AuditEventCreation {
+        action: "transition",
+        entity_type: "course",
+        entity_id: course_id,
+        diff: serde_json::json!({ "status": { "before": row.from_status, "after": row.to_status } }),
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    let event =
+        OutboxEventCreation::new("CourseStatusChanged", course.teacher_id, course_id, &row)?;
+    insert_outbox_event_db(&mut tx, event).await?;
+    tx.commit().await?;
+    Ok(row)
 }
@@ -0,0 +1,397 @@
+use crate::audit::{diff, AuditContext};
+use crate::dbaccesses::audit::insert_audit_event_db;
+use crate::errors::MyError;
+use crate::models::audit::AuditEventCreation;
+use crate::models::curriculum::*;
+use serde_json::json;
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+// The position constraints are deferred, so a duplicate can also surface at commit.
+fn position_conflict(err: sqlx::Error) -> MyError {
+    match &err {
+        sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some("23505") => {
+            MyError::Conflict("A concurrent change took the same position, please retry".into())
+        }
+        _ => err.into(),
+    }
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_section", course_id))]
+pub async fn get_curriculum_db(
+    pool: &PgPool,
+    course_id: i32,
+) -> Result<Vec<SectionWithLessons>, MyError> {
+    let sections: Vec<Section> = sqlx::query_as!(
+        Section,
+        "SELECT * FROM course_section WHERE course_id = $1 ORDER BY position",
+        course_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut lessons: Vec<Lesson> = sqlx::query_as!(
+        Lesson,
+        "SELECT * FROM course_lesson WHERE course_id = $1 ORDER BY section_id, position",
+        course_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(sections
+        .into_iter()
+        .map(|section| {
+            let (section_lessons, others) = lessons
+                .drain(..)
+                .partition(|lesson| lesson.section_id == section.id);
+            lessons = others;
+            SectionWithLessons::new(section, section_lessons)
+        })
+        .collect())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_section", course_id))]
+pub async fn create_one_section_db(
+    pool: &PgPool,
+    course_id: i32,
+    new_section: SectionCreation,
+    audit: &AuditContext,
+) -> Result<Section, MyError> {
+    let mut tx = pool.begin().await?;
+    // Locking the course serializes the concurrent appends to its sections.
+    let course = sqlx::query!("SELECT id FROM course WHERE id = $1 FOR UPDATE", course_id)
+        .fetch_optional(&mut tx)
+        .await?;
+    if course.is_none() {
+        return Err(MyError::NotFound("Course not found".into()));
+    }
+    let row: Section = sqlx::query_as!(
+        Section,
+        r#"INSERT INTO course_section (course_id, title, position)
+        SELECT $1, $2, coalesce(max(position) + 1, 0) FROM course_section WHERE course_id = $1
+        RETURNING id as "id!", course_id as "course_id!", title as "title!", position as "position!""#,
+        course_id,
+        new_section.title,
+    )
+    .fetch_one(&mut tx)
+    .await
+    .map_err(position_conflict)?;
+    let event = AuditEventCreation {
+        action: "create",
+        entity_type: "course_section",
+        entity_id: row.id,
+        diff: diff(None, Some(&row))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await.map_err(position_conflict)?;
+    Ok(row)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_section", course_id, section_id))]
+pub async fn update_one_section_db(
+    pool: &PgPool,
+    course_id: i32,
+    section_id: i32,
+    section_update: SectionUpdate,
+    audit: &AuditContext,
+) -> Result<Section, MyError> {
+    let mut tx = pool.begin().await?;
+    let before: Section = sqlx::query_as!(
+        Section,
+        "SELECT * FROM course_section WHERE course_id = $1 and id = $2 FOR UPDATE",
+        course_id,
+        section_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Section not found".into()))?;
+    let row: Section = sqlx::query_as!(
+        Section,
+        r#"UPDATE course_section SET title = coalesce($1, title)
+        WHERE id = $2
+        RETURNING id, course_id, title, position"#,
+        section_update.title,
+        section_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    let event = AuditEventCreation {
+        action: "update",
+        entity_type: "course_section",
+        entity_id: section_id,
+        diff: diff(Some(&before), Some(&row))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+// The lessons of the section are deleted with it.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_section", course_id, section_id))]
+pub async fn delete_one_section_db(
+    pool: &PgPool,
+    course_id: i32,
+    section_id: i32,
+    audit: &AuditContext,
+) -> Result<Section, MyError> {
+    let mut tx = pool.begin().await?;
+    let row: Section = sqlx::query_as!(
+        Section,
+        r#"DELETE FROM course_section WHERE course_id = $1 and id = $2
+        RETURNING id, course_id, title, position"#,
+        course_id,
+        section_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Section not found".into()))?;
+    let event = AuditEventCreation {
+        action: "delete",
+        entity_type: "course_section",
+        entity_id: section_id,
+        diff: diff(Some(&row), None)?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+// `ids` must list every section of the course exactly once.
+// The rows are locked until the new positions are written.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_section", course_id))]
+pub async fn reorder_sections_db(
+    pool: &PgPool,
+    course_id: i32,
+    reorder: Reorder,
+    audit: &AuditContext,
+) -> Result<Vec<Section>, MyError> {
+    let mut tx = pool.begin().await?;
+    let before: Vec<i32> = sqlx::query!(
+        "SELECT id FROM course_section WHERE course_id = $1 ORDER BY position FOR UPDATE",
+        course_id
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+    validate_reorder(&before, &reorder.ids)?;
+    for (position, id) in reorder.ids.iter().enumerate() {
+        sqlx::query!(
+            "UPDATE course_section SET position = $1 WHERE id = $2",
+            position as i32,
+            id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    let rows: Vec<Section> = sqlx::query_as!(
+        Section,
+        "SELECT * FROM course_section WHERE course_id = $1 ORDER BY position",
+        course_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let event = AuditEventCreation {
+        action: "reorder_sections",
+        entity_type: "course",
+        entity_id: course_id,
+        diff: json!({ "sections": { "before": before, "after": reorder.ids } }),
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(rows)
+}
+
+// Nothing is inserted when the section doesn't belong to the course.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_lesson", course_id, section_id))]
+pub async fn create_one_lesson_db(
+    pool: &PgPool,
+    course_id: i32,
+    section_id: i32,
+    new_lesson: LessonCreation,
+    audit: &AuditContext,
+) -> Result<Lesson, MyError> {
+    let mut tx = pool.begin().await?;
+    // Locking the section serializes the concurrent appends to its lessons.
+    let section = sqlx::query!(
+        "SELECT id FROM course_section WHERE course_id = $1 and id = $2 FOR UPDATE",
+        course_id,
+        section_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    if section.is_none() {
+        return Err(MyError::NotFound("Section not found".into()));
+    }
+    let row: Lesson = sqlx::query_as!(
+        Lesson,
+        r#"INSERT INTO course_lesson (section_id, course_id, title, position, duration_minutes, is_preview)
+        SELECT s.id, s.course_id, $3, coalesce((SELECT max(position) + 1 FROM course_lesson WHERE section_id = s.id), 0), $4, $5
+        FROM course_section s WHERE s.course_id = $1 and s.id = $2
+        RETURNING id as "id!", section_id as "section_id!", course_id as "course_id!", title as "title!",
+            position as "position!", duration_minutes as "duration_minutes!", is_preview as "is_preview!""#,
+        course_id,
+        section_id,
+        new_lesson.title,
+        new_lesson.duration_minutes,
+        new_lesson.is_preview.unwrap_or(false),
+    )
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(position_conflict)?
+    .ok_or_else(|| MyError::NotFound("Section not found".into()))?;
+    let event = AuditEventCreation {
+        action: "create",
+        entity_type: "course_lesson",
+        entity_id: row.id,
+        diff: diff(None, Some(&row))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await.map_err(position_conflict)?;
+    Ok(row)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_lesson", course_id, section_id, lesson_id))]
+pub async fn update_one_lesson_db(
+    pool: &PgPool,
+    course_id: i32,
+    section_id: i32,
+    lesson_id: i32,
+    lesson_update: LessonUpdate,
+    audit: &AuditContext,
+) -> Result<Lesson, MyError> {
+    let mut tx = pool.begin().await?;
+    let before: Lesson = sqlx::query_as!(
+        Lesson,
+        "SELECT * FROM course_lesson WHERE course_id = $1 and section_id = $2 and id = $3 FOR UPDATE",
+        course_id,
+        section_id,
+        lesson_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Lesson not found".into()))?;
+    let row: Lesson = sqlx::query_as!(
+        Lesson,
+        r#"UPDATE course_lesson SET title = coalesce($1, title),
+            duration_minutes = coalesce($2, duration_minutes),
+            is_preview = coalesce($3, is_preview)
+        WHERE id = $4
+        RETURNING id, section_id, course_id, title, position, duration_minutes, is_preview"#,
+        lesson_update.title,
+        lesson_update.duration_minutes,
+        lesson_update.is_preview,
+        lesson_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    let event = AuditEventCreation {
+        action: "update",
+        entity_type: "course_lesson",
+        entity_id: lesson_id,
+        diff: diff(Some(&before), Some(&row))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_lesson", course_id, section_id, lesson_id))]
+pub async fn delete_one_lesson_db(
+    pool: &PgPool,
+    course_id: i32,
+    section_id: i32,
+    lesson_id: i32,
+    audit: &AuditContext,
+) -> Result<Lesson, MyError> {
+    let mut tx = pool.begin().await?;
+    let row: Lesson = sqlx::query_as!(
+        Lesson,
+        r#"DELETE FROM course_lesson WHERE course_id = $1 and section_id = $2 and id = $3
+        RETURNING id, section_id, course_id, title, position, duration_minutes, is_preview"#,
+        course_id,
+        section_id,
+        lesson_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Lesson not found".into()))?;
+    let event = AuditEventCreation {
+        action: "delete",
+        entity_type: "course_lesson",
+        entity_id: lesson_id,
+        diff: diff(Some(&row), None)?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+// `ids` must list every lesson of the section exactly once.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_lesson", course_id, section_id))]
+pub async fn reorder_lessons_db(
+    pool: &PgPool,
+    course_id: i32,
+    section_id: i32,
+    reorder: Reorder,
+    audit: &AuditContext,
+) -> Result<Vec<Lesson>, MyError> {
+    let mut tx = pool.begin().await?;
+    let section = sqlx::query!(
+        "SELECT id FROM course_section WHERE course_id = $1 and id = $2 FOR UPDATE",
+        course_id,
+        section_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    if section.is_none() {
+        return Err(MyError::NotFound("Section not found".into()));
+    }
+    let before: Vec<i32> = sqlx::query!(
+        "SELECT id FROM course_lesson WHERE section_id = $1 ORDER BY position FOR UPDATE",
+        section_id
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+    validate_reorder(&before, &reorder.ids)?;
+    for (position, id) in reorder.ids.iter().enumerate() {
+        sqlx::query!(
+            "UPDATE course_lesson SET position = $1 WHERE id = $2",
+            position as i32,
+            id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    let rows: Vec<Lesson> = sqlx::query_as!(
+        Lesson,
+        "SELECT * FROM course_lesson WHERE section_id = $1 ORDER BY position",
+        section_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let event = AuditEventCreation {
+        action: "reorder_lessons",
+        entity_type: "course_section",
+        entity_id: section_id,
+        diff: json!({ "lessons": { "before": before, "after": reorder.ids } }),
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(rows)
+}
+
+fn validate_reorder(current: &[i32], ids: &[i32]) -> Result<(), MyError> {
+    let (mut current, mut requested) = (current.to_vec(), ids.to_vec());
+    current.sort_unstable();
+    requested.sort_unstable();
+    if current == requested {
+        Ok(())
+    } else {
+        Err(MyError::InvalidInput(
+            "The new order must list every item exactly once".into(),
+        ))
+    }
+}
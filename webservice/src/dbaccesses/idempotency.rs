@@ -0,0 +1,121 @@
+use crate::errors::MyError;
+use crate::models::idempotency::IdempotencyRecord;
+use chrono::NaiveDateTime;
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+// Returns the lease on the key, `None` when the key is already held by another request.
+// Keys older than a day are expired, and those of requests that haven't renewed their lease
+// within 30 seconds were abandoned: both can be taken over.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "idempotency_key"))]
+pub async fn reserve_idempotency_key_db(
+    pool: &PgPool,
+    scope: &str,
+    key: &str,
+    request_hash: &str,
+) -> Result<Option<NaiveDateTime>, MyError> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO idempotency_key (scope, key, request_hash)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (scope, key) DO UPDATE
+        SET request_hash = $3, status_code = NULL, response_body = NULL,
+            created_at = clock_timestamp(), renewed_at = clock_timestamp()
+        WHERE idempotency_key.created_at < now() - interval '1 day'
+            OR (idempotency_key.status_code IS NULL
+                AND idempotency_key.renewed_at < now() - interval '30 seconds')
+        RETURNING created_at
+        "#,
+        scope,
+        key,
+        request_hash
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| row.created_at))
+}
+
+// Keeps the key held by `lease` while its request is in progress.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "idempotency_key"))]
+pub async fn renew_idempotency_key_db(
+    pool: &PgPool,
+    scope: &str,
+    key: &str,
+    lease: NaiveDateTime,
+) -> Result<(), MyError> {
+    sqlx::query!(
+        r#"UPDATE idempotency_key SET renewed_at = clock_timestamp()
+        WHERE scope = $1 AND key = $2 AND created_at = $3 AND status_code IS NULL"#,
+        scope,
+        key,
+        lease
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "idempotency_key"))]
+pub async fn get_idempotency_record_db(
+    pool: &PgPool,
+    scope: &str,
+    key: &str,
+) -> Result<IdempotencyRecord, MyError> {
+    let row = sqlx::query_as!(
+        IdempotencyRecord,
+        r#"SELECT request_hash, status_code, response_body FROM idempotency_key
+        WHERE scope = $1 AND key = $2"#,
+        scope,
+        key
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(record) = row {
+        Ok(record)
+    } else {
+        Err(MyError::NotFound("Idempotency key not found".into()))
+    }
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "idempotency_key"))]
+// Only saves the response while `lease` still holds the key.
+pub async fn save_idempotency_response_db(
+    pool: &PgPool,
+    scope: &str,
+    key: &str,
+    lease: NaiveDateTime,
+    status_code: i16,
+    response_body: &[u8],
+) -> Result<(), MyError> {
+    sqlx::query!(
+        r#"UPDATE idempotency_key SET status_code = $1, response_body = $2
+        WHERE scope = $3 AND key = $4 AND created_at = $5"#,
+        status_code,
+        response_body,
+        scope,
+        key,
+        lease
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "idempotency_key"))]
+pub async fn release_idempotency_key_db(
+    pool: &PgPool,
+    scope: &str,
+    key: &str,
+    lease: NaiveDateTime,
+) -> Result<(), MyError> {
+    sqlx::query!(
+        r#"DELETE FROM idempotency_key
+        WHERE scope = $1 AND key = $2 AND created_at = $3 AND status_code IS NULL"#,
+        scope,
+        key,
+        lease
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use crate::audit::{diff, AuditContext};
+use crate::dbaccesses::audit::insert_audit_event_db;
+use crate::errors::MyError;
+use crate::models::audit::AuditEventCreation;
+use crate::models::material::{CourseMaterial, CourseMaterialCreation};
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_material", course_id))]
+pub async fn get_materials_for_course_db(
+    pool: &PgPool,
+    course_id: i32,
+) -> Result<Vec<CourseMaterial>, MyError> {
+    let rows: Vec<CourseMaterial> = sqlx::query_as!(
+        CourseMaterial,
+        "SELECT * FROM course_material WHERE course_id = $1 ORDER BY id",
+        course_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_material", course_id, material_id))]
+pub async fn get_one_material_db(
+    pool: &PgPool,
+    course_id: i32,
+    material_id: i32,
+) -> Result<CourseMaterial, MyError> {
+    let row: Option<CourseMaterial> = sqlx::query_as!(
+        CourseMaterial,
+        "SELECT * FROM course_material WHERE course_id = $1 and id = $2",
+        course_id,
+        material_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(material) = row {
+        Ok(material)
+    } else {
+        Err(MyError::NotFound("Course material not found".into()))
+    }
+}
+
+// The bytes stored by the materials of a teacher.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_material", teacher_id))]
+pub async fn get_material_usage_db(pool: &PgPool, teacher_id: i32) -> Result<i64, MyError> {
+    let row = sqlx::query!(
+        r#"SELECT coalesce(sum(size_bytes), 0)::bigint as "used!" FROM course_material WHERE teacher_id = $1"#,
+        teacher_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.used)
+}
+
+// The materials of a teacher are created one at a time, under a lock on the teacher,
+// so that two uploads can't both fit in the same remaining quota.
+// Nothing is inserted when the teacher would exceed `quota_bytes`.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_material"))]
+pub async fn create_one_material_db(
+    pool: &PgPool,
+    new_material: CourseMaterialCreation,
+    quota_bytes: i64,
+    audit: &AuditContext,
+) -> Result<CourseMaterial, MyError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"SELECT 1 as "locked!" FROM pg_advisory_xact_lock(hashtext('course_material_quota'), $1)"#,
+        new_material.teacher_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    let row: CourseMaterial = sqlx::query_as!(
+        CourseMaterial,
+        r#"INSERT INTO course_material (course_id, teacher_id, file_name, content_type, size_bytes, checksum, storage_key)
+        SELECT $1, $2, $3, $4, $5::bigint, $6, $7
+        WHERE (SELECT coalesce(sum(size_bytes), 0)::bigint FROM course_material WHERE teacher_id = $2) + $5::bigint <= $8
+        RETURNING id as "id!", course_id as "course_id!", teacher_id as "teacher_id!", file_name as "file_name!",
+            content_type as "content_type!", size_bytes as "size_bytes!", checksum as "checksum!",
+            storage_key as "storage_key!", time"#,
+        new_material.course_id,
+        new_material.teacher_id,
+        new_material.file_name,
+        new_material.content_type,
+        new_material.size_bytes,
+        new_material.checksum,
+        new_material.storage_key,
+        quota_bytes,
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| {
+        MyError::PayloadTooLarge("The storage quota of the teacher is exceeded".into())
+    })?;
+    let event = AuditEventCreation {
+        action: "create",
+        entity_type: "course_material",
+        entity_id: row.id,
+        diff: diff(None, Some(&row))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+// The blobs left behind by the deleted materials, including those deleted along with
+// their course, oldest first.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "orphaned_blob"))]
+pub async fn get_orphaned_blobs_db(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Vec<(i64, String)>, MyError> {
+    let rows = sqlx::query!(
+        "SELECT id, storage_key FROM orphaned_blob ORDER BY id LIMIT $1",
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.id, row.storage_key))
+        .collect())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "orphaned_blob", id))]
+pub async fn delete_orphaned_blob_db(pool: &PgPool, id: i64) -> Result<(), MyError> {
+    sqlx::query!("DELETE FROM orphaned_blob WHERE id = $1", id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_material", course_id, material_id))]
+pub async fn delete_one_material_db(
+    pool: &PgPool,
+    course_id: i32,
+    material_id: i32,
+    audit: &AuditContext,
+) -> Result<CourseMaterial, MyError> {
+    let mut tx = pool.begin().await?;
+    let row: CourseMaterial = sqlx::query_as!(
+        CourseMaterial,
+        r#"DELETE FROM course_material WHERE course_id = $1 and id = $2
+        RETURNING id, course_id, teacher_id, file_name, content_type, size_bytes, checksum, storage_key, time"#,
+        course_id,
+        material_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Course material not found".into()))?;
+    let event = AuditEventCreation {
+        action: "delete",
+        entity_type: "course_material",
+        entity_id: material_id,
+        diff: diff(Some(&row), None)?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(row)
+}
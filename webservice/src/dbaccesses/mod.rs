@@ -1,2 +1,10 @@
+pub mod audit;
 pub mod course;
+pub mod curriculum;
+pub mod idempotency;
+pub mod material;
+pub mod outbox;
+pub mod review;
+pub mod revision;
+pub mod session;
 pub mod teacher;
@@ -0,0 +1,228 @@
+use crate::errors::MyError;
+use crate::models::webhook::{
+    DueDelivery, OutboxEvent, OutboxEventCreation, Webhook, WebhookCreation, WebhookDelivery,
+};
+use chrono::{DateTime, Utc};
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::Transaction;
+use tracing::instrument;
+
+pub const MAX_DEAD_DELIVERIES: i64 = 1000;
+
+// Takes the transaction of the mutation, so that the event is only kept along with it.
+// A delivery is queued for each webhook registered at that time.
+pub async fn insert_outbox_event_db(
+    tx: &mut Transaction<'_, Postgres>,
+    event: OutboxEventCreation,
+) -> Result<(), MyError> {
+    sqlx::query!(
+        r#"WITH event AS (
+            INSERT INTO outbox_event (event_type, teacher_id, entity_id, data)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+        )
+        INSERT INTO webhook_delivery (event_id, webhook_id)
+        SELECT event.id, webhook.id FROM event, webhook"#,
+        event.event_type,
+        event.teacher_id,
+        event.entity_id,
+        event.data,
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "outbox_event", id))]
+pub async fn get_outbox_event_db(pool: &PgPool, id: i64) -> Result<OutboxEvent, MyError> {
+    let row = sqlx::query_as!(
+        OutboxEvent,
+        r#"SELECT id, event_type, teacher_id, entity_id, data, time
+        FROM outbox_event WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(event) = row {
+        Ok(event)
+    } else {
+        Err(MyError::NotFound("Event not found".into()))
+    }
+}
+
+// The events written after `after_id`, oldest first, of a teacher or of all of them.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "outbox_event", after_id, teacher_id = ?teacher_id))]
+pub async fn get_outbox_events_after_db(
+    pool: &PgPool,
+    after_id: i64,
+    teacher_id: Option<i32>,
+    limit: i64,
+) -> Result<Vec<OutboxEvent>, MyError> {
+    let rows: Vec<OutboxEvent> = sqlx::query_as!(
+        OutboxEvent,
+        r#"SELECT id, event_type, teacher_id, entity_id, data, time
+        FROM outbox_event
+        WHERE id > $1 and ($2::int IS NULL or teacher_id = $2)
+        ORDER BY id
+        LIMIT $3"#,
+        after_id,
+        teacher_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "webhook"))]
+pub async fn get_all_webhooks_db(pool: &PgPool) -> Result<Vec<Webhook>, MyError> {
+    let rows: Vec<Webhook> = sqlx::query_as!(
+        Webhook,
+        "SELECT id, url, time FROM webhook ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "webhook"))]
+pub async fn create_one_webhook_db(
+    pool: &PgPool,
+    new_webhook: WebhookCreation,
+) -> Result<Webhook, MyError> {
+    let row = sqlx::query_as!(
+        Webhook,
+        "INSERT INTO webhook (url, secret) VALUES ($1, $2) RETURNING id, url, time",
+        new_webhook.url,
+        new_webhook.secret
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "webhook", webhook_id))]
+pub async fn delete_one_webhook_db(pool: &PgPool, webhook_id: i32) -> Result<Webhook, MyError> {
+    let row = sqlx::query_as!(
+        Webhook,
+        "DELETE FROM webhook WHERE id = $1 RETURNING id, url, time",
+        webhook_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(webhook) = row {
+        Ok(webhook)
+    } else {
+        Err(MyError::NotFound("Webhook not found".into()))
+    }
+}
+
+// Leases up to `limit` due deliveries for `lease_secs`, so that several workers can share
+// the queue and a delivery left by a crashed worker is picked up again.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "webhook_delivery"))]
+pub async fn claim_due_deliveries_db(
+    pool: &PgPool,
+    limit: i64,
+    lease_secs: f64,
+) -> Result<Vec<DueDelivery>, MyError> {
+    let rows: Vec<DueDelivery> = sqlx::query_as!(
+        DueDelivery,
+        r#"UPDATE webhook_delivery d SET next_attempt_at = now() + make_interval(secs => $2)
+        FROM outbox_event e, webhook w
+        WHERE d.id IN (
+            SELECT id FROM webhook_delivery
+            WHERE status = 'pending' and next_attempt_at <= now()
+            ORDER BY next_attempt_at, id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+            and e.id = d.event_id and w.id = d.webhook_id
+        RETURNING d.id, d.attempts, w.url as "url!", w.secret as "secret!",
+            e.id as "event_id!", e.event_type as "event_type!", e.teacher_id,
+            e.entity_id as "entity_id!", e.data as "data!", e.time as "time!""#,
+        limit,
+        lease_secs,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "webhook_delivery", delivery_id))]
+pub async fn mark_delivery_delivered_db(pool: &PgPool, delivery_id: i64) -> Result<(), MyError> {
+    sqlx::query!(
+        r#"UPDATE webhook_delivery
+        SET status = 'delivered', attempts = attempts + 1, last_error = NULL, delivered_at = now()
+        WHERE id = $1"#,
+        delivery_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Schedules the next attempt, or moves the delivery to the dead letters when there is none.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "webhook_delivery", delivery_id))]
+pub async fn mark_delivery_failed_db(
+    pool: &PgPool,
+    delivery_id: i64,
+    error: &str,
+    next_attempt_at: Option<DateTime<Utc>>,
+) -> Result<(), MyError> {
+    sqlx::query!(
+        r#"UPDATE webhook_delivery
+        SET status = CASE WHEN $3::timestamptz IS NULL THEN 'dead' ELSE 'pending' END,
+            attempts = attempts + 1,
+            last_error = $2,
+            next_attempt_at = coalesce($3, next_attempt_at)
+        WHERE id = $1"#,
+        delivery_id,
+        error,
+        next_attempt_at,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "webhook_delivery"))]
+pub async fn get_dead_deliveries_db(pool: &PgPool) -> Result<Vec<WebhookDelivery>, MyError> {
+    let rows: Vec<WebhookDelivery> = sqlx::query_as!(
+        WebhookDelivery,
+        r#"SELECT d.id, d.event_id, e.event_type, d.webhook_id, d.status, d.attempts,
+            d.next_attempt_at, d.last_error, d.delivered_at
+        FROM webhook_delivery d JOIN outbox_event e ON e.id = d.event_id
+        WHERE d.status = 'dead'
+        ORDER BY d.id DESC
+        LIMIT $1"#,
+        MAX_DEAD_DELIVERIES
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+// Gives a dead delivery a new series of attempts.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "webhook_delivery", delivery_id))]
+pub async fn retry_one_delivery_db(
+    pool: &PgPool,
+    delivery_id: i64,
+) -> Result<WebhookDelivery, MyError> {
+    let row = sqlx::query_as!(
+        WebhookDelivery,
+        r#"UPDATE webhook_delivery d
+        SET status = 'pending', attempts = 0, next_attempt_at = now()
+        FROM outbox_event e
+        WHERE d.id = $1 and d.status = 'dead' and e.id = d.event_id
+        RETURNING d.id, d.event_id, e.event_type as "event_type!", d.webhook_id, d.status,
+            d.attempts, d.next_attempt_at, d.last_error, d.delivered_at"#,
+        delivery_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(delivery) = row {
+        Ok(delivery)
+    } else {
+        Err(MyError::NotFound("Dead delivery not found".into()))
+    }
+}
@@ -0,0 +1,234 @@
+use crate::audit::{diff, AuditContext};
+use crate::dbaccesses::audit::insert_audit_event_db;
+use crate::errors::MyError;
+use crate::models::audit::AuditEventCreation;
+use crate::models::review::{Review, ReviewCreation, ReviewModeration, ReviewUpdate};
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::Transaction;
+use tracing::instrument;
+
+// Every review write locks the course first, so that concurrent writes
+// recompute the rating one after the other and see each other's reviews.
+async fn lock_course(tx: &mut Transaction<'_, Postgres>, course_id: i32) -> Result<(), MyError> {
+    let course = sqlx::query!("SELECT id FROM course WHERE id = $1 FOR UPDATE", course_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if course.is_some() {
+        Ok(())
+    } else {
+        Err(MyError::NotFound("Course not found".into()))
+    }
+}
+
+async fn get_locked_review(
+    tx: &mut Transaction<'_, Postgres>,
+    course_id: i32,
+    review_id: i32,
+) -> Result<Review, MyError> {
+    sqlx::query_as!(
+        Review,
+        "SELECT * FROM course_review WHERE course_id = $1 and id = $2 FOR UPDATE",
+        course_id,
+        review_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Review not found".into()))
+}
+
+async fn refresh_course_rating(
+    tx: &mut Transaction<'_, Postgres>,
+    course_id: i32,
+) -> Result<(), MyError> {
+    sqlx::query!(
+        r#"UPDATE course SET
+            rating_average = (SELECT avg(rating)::float8 FROM course_review WHERE course_id = $1 and not is_hidden),
+            rating_count = (SELECT count(*) FROM course_review WHERE course_id = $1 and not is_hidden),
+            updated_at = now()
+        WHERE id = $1"#,
+        course_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+// Hidden reviews are only listed for moderation.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_review", course_id))]
+pub async fn get_reviews_for_course_db(
+    pool: &PgPool,
+    course_id: i32,
+    include_hidden: bool,
+) -> Result<Vec<Review>, MyError> {
+    let rows: Vec<Review> = sqlx::query_as!(
+        Review,
+        "SELECT * FROM course_review WHERE course_id = $1 and ($2 or not is_hidden) ORDER BY id",
+        course_id,
+        include_hidden
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_review", course_id, review_id))]
+pub async fn get_one_review_db(
+    pool: &PgPool,
+    course_id: i32,
+    review_id: i32,
+) -> Result<Review, MyError> {
+    let row: Option<Review> = sqlx::query_as!(
+        Review,
+        "SELECT * FROM course_review WHERE course_id = $1 and id = $2",
+        course_id,
+        review_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(review) = row {
+        Ok(review)
+    } else {
+        Err(MyError::NotFound("Review not found".into()))
+    }
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_review", course_id))]
+pub async fn create_one_review_db(
+    pool: &PgPool,
+    course_id: i32,
+    new_review: ReviewCreation,
+    audit: &AuditContext,
+) -> Result<Review, MyError> {
+    let mut tx = pool.begin().await?;
+    lock_course(&mut tx, course_id).await?;
+    let row: Option<Review> = sqlx::query_as!(
+        Review,
+        r#"INSERT INTO course_review (course_id, student_id, rating, text)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (course_id, student_id) DO NOTHING
+        RETURNING id, course_id, student_id, rating, text, is_hidden, is_flagged, time, updated_at"#,
+        course_id,
+        new_review.student_id,
+        new_review.rating,
+        new_review.text,
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    let review = match row {
+        Some(review) => review,
+        None => {
+            return Err(MyError::Conflict(
+                "The student already reviewed this course".into(),
+            ))
+        }
+    };
+    refresh_course_rating(&mut tx, course_id).await?;
+    let event = AuditEventCreation {
+        action: "create",
+        entity_type: "review",
+        entity_id: review.id,
+        diff: diff(None, Some(&review))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(review)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_review", course_id, review_id))]
+pub async fn update_one_review_db(
+    pool: &PgPool,
+    course_id: i32,
+    review_id: i32,
+    review_update: ReviewUpdate,
+    audit: &AuditContext,
+) -> Result<Review, MyError> {
+    let mut tx = pool.begin().await?;
+    lock_course(&mut tx, course_id).await?;
+    let before = get_locked_review(&mut tx, course_id, review_id).await?;
+    let review: Review = sqlx::query_as!(
+        Review,
+        r#"UPDATE course_review SET rating = coalesce($1, rating), text = coalesce($2, text), updated_at = now()
+        WHERE id = $3
+        RETURNING id, course_id, student_id, rating, text, is_hidden, is_flagged, time, updated_at"#,
+        review_update.rating,
+        review_update.text,
+        review_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    refresh_course_rating(&mut tx, course_id).await?;
+    let event = AuditEventCreation {
+        action: "update",
+        entity_type: "review",
+        entity_id: review_id,
+        diff: diff(Some(&before), Some(&review))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(review)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_review", course_id, review_id))]
+pub async fn moderate_one_review_db(
+    pool: &PgPool,
+    course_id: i32,
+    review_id: i32,
+    moderation: ReviewModeration,
+    audit: &AuditContext,
+) -> Result<Review, MyError> {
+    let mut tx = pool.begin().await?;
+    lock_course(&mut tx, course_id).await?;
+    let before = get_locked_review(&mut tx, course_id, review_id).await?;
+    let review: Review = sqlx::query_as!(
+        Review,
+        r#"UPDATE course_review SET is_hidden = coalesce($1, is_hidden), is_flagged = coalesce($2, is_flagged)
+        WHERE id = $3
+        RETURNING id, course_id, student_id, rating, text, is_hidden, is_flagged, time, updated_at"#,
+        moderation.is_hidden,
+        moderation.is_flagged,
+        review_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    refresh_course_rating(&mut tx, course_id).await?;
+    let event = AuditEventCreation {
+        action: "moderate",
+        entity_type: "review",
+        entity_id: review_id,
+        diff: diff(Some(&before), Some(&review))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(review)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_review", course_id, review_id))]
+pub async fn delete_one_review_db(
+    pool: &PgPool,
+    course_id: i32,
+    review_id: i32,
+    audit: &AuditContext,
+) -> Result<Review, MyError> {
+    let mut tx = pool.begin().await?;
+    lock_course(&mut tx, course_id).await?;
+    let row: Option<Review> = sqlx::query_as!(
+        Review,
+        r#"DELETE FROM course_review WHERE course_id = $1 and id = $2
+        RETURNING id, course_id, student_id, rating, text, is_hidden, is_flagged, time, updated_at"#,
+        course_id,
+        review_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    let review = row.ok_or_else(|| MyError::NotFound("Review not found".into()))?;
+    refresh_course_rating(&mut tx, course_id).await?;
+    let event = AuditEventCreation {
+        action: "delete",
+        entity_type: "review",
+        entity_id: review_id,
+        diff: diff(Some(&review), None)?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(review)
+}
@@ -0,0 +1,80 @@
+use crate::audit::AuditContext;
+use crate::errors::MyError;
+use crate::models::course::Course;
+use crate::models::revision::CourseRevision;
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::Transaction;
+use tracing::instrument;
+
+// Called by the course update with the course row locked, so revisions are numbered in order.
+pub async fn insert_course_revision_db(
+    tx: &mut Transaction<'_, Postgres>,
+    audit: &AuditContext,
+    course: &Course,
+) -> Result<(), MyError> {
+    let snapshot =
+        serde_json::to_value(course).map_err(|err| MyError::ActixError(err.to_string()))?;
+    sqlx::query!(
+        r#"INSERT INTO course_revision (course_id, revision, snapshot, actor)
+        SELECT $1, coalesce(max(revision) + 1, 1), $2, $3 FROM course_revision WHERE course_id = $1"#,
+        course.id,
+        snapshot,
+        audit.actor,
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_revision", course_id))]
+pub async fn get_revisions_for_course_db(
+    pool: &PgPool,
+    course_id: i32,
+) -> Result<Vec<CourseRevision>, MyError> {
+    let rows: Vec<CourseRevision> = sqlx::query_as!(
+        CourseRevision,
+        r#"SELECT id, course_id, revision, snapshot, actor, time
+        FROM course_revision WHERE course_id = $1 ORDER BY revision DESC"#,
+        course_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+// The newest revision of a course, 0 when it was never updated. Read with the course row
+// locked, since revisions are only added under that lock.
+pub async fn get_latest_revision_db(
+    tx: &mut Transaction<'_, Postgres>,
+    course_id: i32,
+) -> Result<i32, MyError> {
+    let row = sqlx::query!(
+        r#"SELECT coalesce(max(revision), 0) as "revision!" FROM course_revision WHERE course_id = $1"#,
+        course_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    Ok(row.revision)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_revision", course_id))]
+pub async fn get_one_revision_db(
+    pool: &PgPool,
+    course_id: i32,
+    revision: i32,
+) -> Result<CourseRevision, MyError> {
+    let row: Option<CourseRevision> = sqlx::query_as!(
+        CourseRevision,
+        r#"SELECT id, course_id, revision, snapshot, actor, time
+        FROM course_revision WHERE course_id = $1 and revision = $2"#,
+        course_id,
+        revision
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(revision) = row {
+        Ok(revision)
+    } else {
+        Err(MyError::NotFound("Revision not found".into()))
+    }
+}
@@ -0,0 +1,131 @@
+use crate::audit::{diff, AuditContext};
+use crate::dbaccesses::audit::insert_audit_event_db;
+use crate::errors::MyError;
+use crate::models::audit::AuditEventCreation;
+use crate::models::session::{CourseSession, NewCourseSession, ScheduledSession};
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_session", course_id))]
+pub async fn get_sessions_for_course_db(
+    pool: &PgPool,
+    course_id: i32,
+) -> Result<Vec<CourseSession>, MyError> {
+    let rows: Vec<CourseSession> = sqlx::query_as!(
+        CourseSession,
+        r#"SELECT id, course_id, starts_at, ends_at, time_zone, recurrence, capacity
+        FROM course_session WHERE course_id = $1 ORDER BY starts_at"#,
+        course_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_session", course_id))]
+pub async fn create_one_session_db(
+    pool: &PgPool,
+    course_id: i32,
+    new_session: NewCourseSession,
+    audit: &AuditContext,
+) -> Result<CourseSession, MyError> {
+    let mut tx = pool.begin().await?;
+    let row: CourseSession = sqlx::query_as!(
+        CourseSession,
+        r#"INSERT INTO course_session (course_id, starts_at, ends_at, time_zone, recurrence, capacity)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, course_id, starts_at, ends_at, time_zone, recurrence, capacity"#,
+        course_id,
+        new_session.starts_at,
+        new_session.ends_at,
+        new_session.time_zone,
+        new_session.recurrence,
+        new_session.capacity,
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    let event = AuditEventCreation {
+        action: "create",
+        entity_type: "course_session",
+        entity_id: row.id,
+        diff: diff(None, Some(&row))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_session", course_id, session_id))]
+pub async fn delete_one_session_db(
+    pool: &PgPool,
+    course_id: i32,
+    session_id: i32,
+    audit: &AuditContext,
+) -> Result<CourseSession, MyError> {
+    let mut tx = pool.begin().await?;
+    let row: CourseSession = sqlx::query_as!(
+        CourseSession,
+        r#"DELETE FROM course_session WHERE course_id = $1 and id = $2
+        RETURNING id, course_id, starts_at, ends_at, time_zone, recurrence, capacity"#,
+        course_id,
+        session_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Session not found".into()))?;
+    let event = AuditEventCreation {
+        action: "delete",
+        entity_type: "course_session",
+        entity_id: session_id,
+        diff: diff(Some(&row), None)?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+// Recurring sessions are returned whenever they start before `to`,
+// their occurrences are expanded by the caller.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_session"))]
+pub async fn get_sessions_between_db(
+    pool: &PgPool,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<ScheduledSession>, MyError> {
+    let rows: Vec<ScheduledSession> = sqlx::query_as!(
+        ScheduledSession,
+        r#"SELECT s.id, s.course_id, c.teacher_id, c.name as course_name, s.starts_at, s.ends_at,
+            s.time_zone, s.recurrence, s.capacity
+        FROM course_session s JOIN course c ON c.id = s.course_id
+        WHERE c.status = 'published' and s.starts_at < $2 and (s.recurrence IS NOT NULL or s.ends_at > $1)"#,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+// Only the sessions of published courses are part of the calendars.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "course_session", teacher_id = ?teacher_id, course_id = ?course_id))]
+pub async fn get_scheduled_sessions_db(
+    pool: &PgPool,
+    teacher_id: Option<i32>,
+    course_id: Option<i32>,
+) -> Result<Vec<ScheduledSession>, MyError> {
+    let rows: Vec<ScheduledSession> = sqlx::query_as!(
+        ScheduledSession,
+        r#"SELECT s.id, s.course_id, c.teacher_id, c.name as course_name, s.starts_at, s.ends_at,
+            s.time_zone, s.recurrence, s.capacity
+        FROM course_session s JOIN course c ON c.id = s.course_id
+        WHERE c.status = 'published' and ($1::int IS NULL or c.teacher_id = $1)
+            and ($2::int IS NULL or c.id = $2)
+        ORDER BY s.starts_at"#,
+        teacher_id,
+        course_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
@@ -1,7 +1,18 @@
+use crate::audit::{diff, AuditContext};
+use crate::caching::ListVersion;
+use crate::dbaccesses::audit::insert_audit_event_db;
+use crate::dbaccesses::outbox::insert_outbox_event_db;
 use crate::errors::MyError;
+use crate::models::audit::AuditEventCreation;
 use crate::models::teacher::{Teacher, TeacherCreation, TeacherUpdate};
+use crate::models::webhook::OutboxEventCreation;
+use crate::streaming::fetch_rows;
+use futures_util::stream::{self, BoxStream, StreamExt, TryStreamExt};
 use sqlx::postgres::PgPool;
+use std::future::ready;
+use tracing::instrument;
 
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "teacher"))]
 pub async fn get_all_teachers_db(pool: &PgPool) -> Result<Vec<Teacher>, MyError> {
     let rows = sqlx::query!("SELECT * FROM teacher")
         .fetch_all(pool)
@@ -13,6 +24,7 @@ pub async fn get_all_teachers_db(pool: &PgPool) -> Result<Vec<Teacher>, MyError>
             name: r.name.clone().unwrap_or_default(),
             url_picture: r.url_picture.clone().unwrap_or_default(),
             profile: r.profile.clone().unwrap_or_default(),
+            updated_at: r.updated_at,
         })
         .collect();
     match teachers.len() {
@@ -21,6 +33,110 @@ pub async fn get_all_teachers_db(pool: &PgPool) -> Result<Vec<Teacher>, MyError>
     }
 }
 
+// The same teachers, streamed from the query as they come.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "teacher"))]
+pub async fn stream_all_teachers_db(
+    pool: &PgPool,
+) -> Result<BoxStream<'static, Result<Teacher, MyError>>, MyError> {
+    let pool = pool.clone();
+    let mut teachers = fetch_rows(move |teachers| async move {
+        let mut rows = sqlx::query!("SELECT * FROM teacher").fetch(&pool);
+        while let Some(r) = rows.try_next().await? {
+            teachers
+                .send(Teacher {
+                    id: r.id,
+                    name: r.name.unwrap_or_default(),
+                    url_picture: r.url_picture.unwrap_or_default(),
+                    profile: r.profile.unwrap_or_default(),
+                    updated_at: r.updated_at,
+                })
+                .await?;
+        }
+        Ok(())
+    });
+    // Reads the first teacher ahead, so that an empty list is still a 404.
+    match teachers.next().await {
+        Some(Ok(first)) => Ok(stream::once(ready(Ok(first))).chain(teachers).boxed()),
+        Some(Err(err)) => Err(err),
+        None => Err(MyError::NotFound("No teachers found".into())),
+    }
+}
+
+// The time of the last change to the teachers, deletions included, and their number.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "teacher"))]
+pub async fn get_teachers_version_db(pool: &PgPool) -> Result<ListVersion, MyError> {
+    let row = sqlx::query!(
+        r#"SELECT greatest(
+            (SELECT max(updated_at) FROM teacher),
+            (SELECT max(time) FROM outbox_event WHERE event_type = 'TeacherDeleted')
+        ) as last_modified,
+        (SELECT count(*) FROM teacher) as "count!""#
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(ListVersion {
+        last_modified: row.last_modified,
+        count: row.count,
+    })
+}
+
+// Teachers by id order, after the `after` id, with names containing `name` when given.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "teacher"))]
+pub async fn get_teachers_page_db(
+    pool: &PgPool,
+    name: Option<&str>,
+    after: i32,
+    limit: i64,
+) -> Result<Vec<Teacher>, MyError> {
+    let pattern = name.map(|name| {
+        let name = name
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{}%", name)
+    });
+    let rows = sqlx::query!(
+        r#"SELECT * FROM teacher
+        WHERE ($1::varchar IS NULL or name ILIKE $1) and id > $2
+        ORDER BY id
+        LIMIT $3"#,
+        pattern,
+        after,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| Teacher {
+            id: r.id,
+            name: r.name.unwrap_or_default(),
+            url_picture: r.url_picture.unwrap_or_default(),
+            profile: r.profile.unwrap_or_default(),
+            updated_at: r.updated_at,
+        })
+        .collect())
+}
+
+// Loads several teachers in one query, the missing ones are left out.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "teacher"))]
+pub async fn get_teachers_by_ids_db(pool: &PgPool, ids: &[i32]) -> Result<Vec<Teacher>, MyError> {
+    let rows = sqlx::query!("SELECT * FROM teacher WHERE id = ANY($1)", ids)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| Teacher {
+            id: r.id,
+            name: r.name.unwrap_or_default(),
+            url_picture: r.url_picture.unwrap_or_default(),
+            profile: r.profile.unwrap_or_default(),
+            updated_at: r.updated_at,
+        })
+        .collect())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "teacher", teacher_id))]
 pub async fn get_one_teacher_detail_db(pool: &PgPool, teacher_id: i32) -> Result<Teacher, MyError> {
     let row = sqlx::query!(
         r#"
@@ -35,98 +151,188 @@ pub async fn get_one_teacher_detail_db(pool: &PgPool, teacher_id: i32) -> Result
         name: r.name.unwrap_or_default(),
         url_picture: r.url_picture.unwrap_or_default(),
         profile: r.profile.unwrap_or_default(),
+        updated_at: r.updated_at,
     })
     .map_err(|_err| MyError::NotFound("Teacher ID not found".into()))?;
     Ok(row)
 }
 
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "teacher"))]
 pub async fn create_one_teacher_db(
     pool: &PgPool,
     new_teacher: TeacherCreation,
+    audit: &AuditContext,
 ) -> Result<Teacher, MyError> {
+    let mut tx = pool.begin().await?;
     let row = sqlx::query!(
         r#"
-        INSERT INTO teacher (name, url_picture, profile)
-        VALUES ($1, $2, $3)
-        RETURNING id, name, url_picture, profile
+        INSERT INTO teacher (name, profile)
+        VALUES ($1, $2)
+        RETURNING id, name, url_picture, profile, updated_at
         "#,
         new_teacher.name,
-        new_teacher.url_picture,
         new_teacher.profile
     )
-    .fetch_one(pool)
+    .fetch_one(&mut tx)
     .await?;
-    Ok(Teacher {
+    let teacher = Teacher {
         id: row.id,
         name: row.name.unwrap_or_default(),
         url_picture: row.url_picture.unwrap_or_default(),
         profile: row.profile.unwrap_or_default(),
-    })
+        updated_at: row.updated_at,
+    };
+    let event = AuditEventCreation {
+        action: "create",
+        entity_type: "teacher",
+        entity_id: teacher.id,
+        diff: diff(None, Some(&teacher))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    let event = OutboxEventCreation::new("TeacherCreated", teacher.id, teacher.id, &teacher)?;
+    insert_outbox_event_db(&mut tx, event).await?;
+    tx.commit().await?;
+    Ok(teacher)
 }
 
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "teacher", teacher_id))]
 pub async fn update_one_teacher_detail_db(
     pool: &PgPool,
     teacher_id: i32,
     teacher_update: TeacherUpdate,
+    audit: &AuditContext,
+) -> Result<Teacher, MyError> {
+    update_teacher_db(pool, teacher_id, teacher_update, None, audit).await
+}
+
+// Only set by the picture upload, which stores the picture first.
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "teacher", teacher_id))]
+pub async fn update_one_teacher_picture_db(
+    pool: &PgPool,
+    teacher_id: i32,
+    url_picture: String,
+    audit: &AuditContext,
+) -> Result<Teacher, MyError> {
+    let teacher_update = TeacherUpdate {
+        name: None,
+        profile: None,
+    };
+    update_teacher_db(pool, teacher_id, teacher_update, Some(url_picture), audit).await
+}
+
+async fn update_teacher_db(
+    pool: &PgPool,
+    teacher_id: i32,
+    teacher_update: TeacherUpdate,
+    url_picture: Option<String>,
+    audit: &AuditContext,
 ) -> Result<Teacher, MyError> {
+    let mut tx = pool.begin().await?;
     let row = sqlx::query!(
         r#"
-        SELECT * FROM teacher WHERE id = $1
+        SELECT * FROM teacher WHERE id = $1 FOR UPDATE
         "#,
         teacher_id
     )
-    .fetch_one(pool)
+    .fetch_one(&mut tx)
     .await
     .map_err(|_err| MyError::NotFound("Teacher ID not found".into()))?;
-    let tmp = Teacher {
+    let current = Teacher {
         id: row.id,
+        name: row.name.unwrap_or_default(),
+        url_picture: row.url_picture.unwrap_or_default(),
+        profile: row.profile.unwrap_or_default(),
+        updated_at: row.updated_at,
+    };
+    let tmp = Teacher {
+        id: current.id,
         name: if let Some(name) = teacher_update.name {
             name
         } else {
-            row.name.unwrap_or_default()
+            current.name.clone()
         },
-        url_picture: if let Some(url) = teacher_update.url_picture {
+        url_picture: if let Some(url) = url_picture {
             url
         } else {
-            row.url_picture.unwrap_or_default()
+            current.url_picture.clone()
         },
         profile: if let Some(profile) = teacher_update.profile {
             profile
         } else {
-            row.profile.unwrap_or_default()
+            current.profile.clone()
         },
+        updated_at: current.updated_at,
     };
     let updated_row = sqlx::query!(
         r#"
-        UPDATE teacher SET name = $1, url_picture = $2, profile = $3 WHERE id = $4
-        RETURNING id, name, url_picture, profile
+        UPDATE teacher SET name = $1, url_picture = $2, profile = $3, updated_at = now() WHERE id = $4
+        RETURNING id, name, url_picture, profile, updated_at
         "#,
         tmp.name,
         tmp.url_picture,
         tmp.profile,
         teacher_id,
     )
-    .fetch_one(pool)
+    .fetch_one(&mut tx)
     .await
     .map(|r| Teacher {
         id: r.id,
         name: r.name.unwrap_or_default(),
         url_picture: r.url_picture.unwrap_or_default(),
         profile: r.profile.unwrap_or_default(),
+        updated_at: r.updated_at,
     })
     .map_err(|_err| MyError::NotFound("Teacher ID not found".into()))?;
+    let event = AuditEventCreation {
+        action: "update",
+        entity_type: "teacher",
+        entity_id: teacher_id,
+        diff: diff(Some(&current), Some(&updated_row))?,
+    };
+    insert_audit_event_db(&mut tx, audit, event).await?;
+    let event = OutboxEventCreation::new("TeacherUpdated", teacher_id, teacher_id, &updated_row)?;
+    insert_outbox_event_db(&mut tx, event).await?;
+    tx.commit().await?;
     Ok(updated_row)
 }
 
-pub async fn delete_one_teacher_db(pool: &PgPool, teacher_id: i32) -> Result<String, MyError> {
+#[instrument(skip_all, fields(db.system = "postgresql", db.sql.table = "teacher", teacher_id))]
+pub async fn delete_one_teacher_db(
+    pool: &PgPool,
+    teacher_id: i32,
+    audit: &AuditContext,
+) -> Result<String, MyError> {
+    let mut tx = pool.begin().await?;
+    let current = sqlx::query!("SELECT * FROM teacher WHERE id = $1 FOR UPDATE", teacher_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .map(|r| Teacher {
+            id: r.id,
+            name: r.name.unwrap_or_default(),
+            url_picture: r.url_picture.unwrap_or_default(),
+            profile: r.profile.unwrap_or_default(),
+            updated_at: r.updated_at,
+        });
     let row = sqlx::query!(
         r#"
         DELETE FROM teacher WHERE id = $1
         "#,
         teacher_id
     )
-    .execute(pool)
+    .execute(&mut tx)
     .await
     .map_err(|_err| MyError::DBError("Unable to delete the teacher".into()))?;
+    if let Some(teacher) = current {
+        let event = AuditEventCreation {
+            action: "delete",
+            entity_type: "teacher",
+            entity_id: teacher.id,
+            diff: diff(Some(&teacher), None)?,
+        };
+        insert_audit_event_db(&mut tx, audit, event).await?;
+        let event = OutboxEventCreation::new("TeacherDeleted", teacher.id, teacher.id, &teacher)?;
+        insert_outbox_event_db(&mut tx, event).await?;
+    }
+    tx.commit().await?;
     Ok(format!("Deleted {:?} record(s)", row))
 }
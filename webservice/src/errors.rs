@@ -2,16 +2,25 @@ use actix_web::{error, http::StatusCode, HttpResponse, Result};
 use serde::Serialize;
 use sqlx::error::Error as SQLxError;
 use std::fmt;
+use utoipa::ToSchema;
 
 #[derive(Debug, Serialize)]
 pub enum MyError {
     DBError(String),
     ActixError(String),
     NotFound(String),
+    Forbidden(String),
     InvalidInput(String),
+    PayloadTooLarge(String),
+    TooManyRequests(String),
+    Conflict(String),
+    UnprocessableEntity(String),
+    RangeNotSatisfiable(String),
+    NotAcceptable(String),
+    UnsupportedMediaType(String),
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct MyErrorResponse {
     error_message: String,
 }
@@ -31,10 +40,42 @@ impl MyError {
                 println!("Not found error occured: {:?}", msg);
                 msg.into()
             }
+            MyError::Forbidden(msg) => {
+                println!("Forbidden request: {:?}", msg);
+                msg.into()
+            }
             MyError::InvalidInput(msg) => {
                 println!("Invalid input received: {:?}", msg);
                 msg.into()
             }
+            MyError::PayloadTooLarge(msg) => {
+                println!("Payload too large: {:?}", msg);
+                msg.into()
+            }
+            MyError::TooManyRequests(msg) => {
+                println!("Too many requests: {:?}", msg);
+                msg.into()
+            }
+            MyError::Conflict(msg) => {
+                println!("Conflict occured: {:?}", msg);
+                msg.into()
+            }
+            MyError::UnprocessableEntity(msg) => {
+                println!("Unprocessable entity received: {:?}", msg);
+                msg.into()
+            }
+            MyError::RangeNotSatisfiable(msg) => {
+                println!("Range not satisfiable: {:?}", msg);
+                msg.into()
+            }
+            MyError::NotAcceptable(msg) => {
+                println!("Not acceptable: {:?}", msg);
+                msg.into()
+            }
+            MyError::UnsupportedMediaType(msg) => {
+                println!("Unsupported media type: {:?}", msg);
+                msg.into()
+            }
         }
     }
 }
@@ -44,7 +85,15 @@ impl error::ResponseError for MyError {
         match self {
             MyError::DBError(_msg) | MyError::ActixError(_msg) => StatusCode::INTERNAL_SERVER_ERROR,
             MyError::NotFound(_msg) => StatusCode::NOT_FOUND,
+            MyError::Forbidden(_msg) => StatusCode::FORBIDDEN,
             MyError::InvalidInput(_msg) => StatusCode::BAD_REQUEST,
+            MyError::PayloadTooLarge(_msg) => StatusCode::PAYLOAD_TOO_LARGE,
+            MyError::TooManyRequests(_msg) => StatusCode::TOO_MANY_REQUESTS,
+            MyError::Conflict(_msg) => StatusCode::CONFLICT,
+            MyError::UnprocessableEntity(_msg) => StatusCode::UNPROCESSABLE_ENTITY,
+            MyError::RangeNotSatisfiable(_msg) => StatusCode::RANGE_NOT_SATISFIABLE,
+            MyError::NotAcceptable(_msg) => StatusCode::NOT_ACCEPTABLE,
+            MyError::UnsupportedMediaType(_msg) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
         }
     }
     fn error_response(&self) -> HttpResponse {
@@ -54,9 +103,10 @@ impl error::ResponseError for MyError {
     }
 }
 
+// The message sent to the client, which also reaches the GraphQL errors.
 impl fmt::Display for MyError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{}", self)
+        write!(f, "{}", self.error_response())
     }
 }
 
@@ -0,0 +1,333 @@
+use crate::dbaccesses::outbox::{get_outbox_event_db, get_outbox_events_after_db};
+use crate::errors::MyError;
+use crate::models::webhook::OutboxEvent;
+use crate::shutdown::{sleep_unless_shutting_down, wait_for_shutdown};
+use crate::state::AppState;
+use actix_web::web;
+use futures_util::stream::{self, Stream};
+use sqlx::postgres::{PgListener, PgPool};
+use std::collections::{HashSet, VecDeque};
+use std::convert::Infallible;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+// Notified by the `outbox_event` trigger with the id of each new event.
+pub const CATALOG_CHANNEL: &str = "catalog_changes";
+pub const MAX_REPLAYED_EVENTS: i64 = 1000;
+// Ids are taken when an event is written but notified when it commits, so a transaction that
+// commits late notifies an id lower than those already published. Replays go back this many
+// ids to catch such events.
+pub const REPLAY_OVERLAP: i64 = 100;
+// Enough to recognize every event of a replay after a lost connection.
+const RECENT_IDS: usize = 2 * (MAX_REPLAYED_EVENTS + REPLAY_OVERLAP) as usize;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+// Fans the catalog changes seen by this instance out to its `/events` streams.
+#[derive(Clone)]
+pub struct CatalogEvents {
+    sender: broadcast::Sender<Arc<OutboxEvent>>,
+}
+
+impl CatalogEvents {
+    // A stream falling `capacity` events behind is closed, and resumes with `Last-Event-ID`.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        CatalogEvents { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<OutboxEvent>> {
+        self.sender.subscribe()
+    }
+
+    fn publish(&self, event: OutboxEvent) {
+        // Fails only when no stream is open.
+        let _ = self.sender.send(Arc::new(event));
+    }
+}
+
+// The ids published lately, so that replays only publish the events missed.
+#[derive(Default)]
+struct RecentIds {
+    ids: HashSet<i64>,
+    order: VecDeque<i64>,
+}
+
+impl RecentIds {
+    // False when the id was already seen.
+    fn insert(&mut self, id: i64) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > RECENT_IDS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    fn max(&self) -> Option<i64> {
+        self.ids.iter().max().copied()
+    }
+}
+
+pub struct CatalogListener {
+    pool: PgPool,
+    listener: PgListener,
+    published: RecentIds,
+}
+
+impl CatalogListener {
+    pub async fn connect(pool: &PgPool) -> Result<Self, MyError> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(CATALOG_CHANNEL).await?;
+        Ok(CatalogListener {
+            pool: pool.clone(),
+            listener,
+            published: RecentIds::default(),
+        })
+    }
+
+    // Publishes the notified events until shutdown. After a lost connection, which
+    // `PgListener` restores by itself, the events written in the meantime are read back
+    // from the outbox.
+    pub async fn run(mut self, app_state: web::Data<AppState>, events: web::Data<CatalogEvents>) {
+        while !app_state.shutting_down.load(Ordering::SeqCst) {
+            tokio::select! {
+                result = self.receive(&events) => if let Err(err) = result {
+                    tracing::error!("Unable to receive the catalog changes: {}", err);
+                    sleep_unless_shutting_down(&app_state, RECONNECT_DELAY).await;
+                },
+                _ = wait_for_shutdown(&app_state) => {}
+            }
+        }
+    }
+
+    async fn receive(&mut self, events: &CatalogEvents) -> Result<(), MyError> {
+        let missed = match self.listener.try_recv().await? {
+            Some(notification) => {
+                let id = notification.payload().parse().map_err(|_| {
+                    MyError::DBError(format!("Invalid notification {}", notification.payload()))
+                })?;
+                vec![get_outbox_event_db(&self.pool, id).await?]
+            }
+            None => match self.published.max() {
+                Some(last_id) => {
+                    get_outbox_events_after_db(
+                        &self.pool,
+                        last_id - REPLAY_OVERLAP,
+                        None,
+                        MAX_REPLAYED_EVENTS,
+                    )
+                    .await?
+                }
+                None => Vec::new(),
+            },
+        };
+        for event in missed {
+            if self.published.insert(event.id) {
+                events.publish(event);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn frame(event: &OutboxEvent) -> web::Bytes {
+    let data = serde_json::to_string(event).unwrap_or_default();
+    web::Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event.id, event.event_type, data
+    ))
+}
+
+struct CatalogStream {
+    app_state: web::Data<AppState>,
+    receiver: broadcast::Receiver<Arc<OutboxEvent>>,
+    replay: VecDeque<OutboxEvent>,
+    teacher_id: Option<i32>,
+    // The live events are not in id order, so they are only checked against the replay.
+    replayed: HashSet<i64>,
+    keep_alive: actix_rt::time::Interval,
+}
+
+impl CatalogStream {
+    async fn next_frame(mut self) -> Option<(Result<web::Bytes, Infallible>, Self)> {
+        if let Some(event) = self.replay.pop_front() {
+            return Some((Ok(frame(&event)), self));
+        }
+        loop {
+            tokio::select! {
+                event = self.receiver.recv() => match event {
+                    Ok(event) => {
+                        // Skips the events already replayed, and those of other teachers.
+                        if self.replayed.contains(&event.id)
+                            || self.teacher_id.is_some_and(|id| event.teacher_id != Some(id))
+                        {
+                            continue;
+                        }
+                        return Some((Ok(frame(&event)), self));
+                    }
+                    Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => return None,
+                },
+                _ = self.keep_alive.tick() => {
+                    if self.app_state.shutting_down.load(Ordering::SeqCst) {
+                        return None;
+                    }
+                    return Some((Ok(web::Bytes::from_static(b": keep-alive\n\n")), self));
+                }
+            }
+        }
+    }
+}
+
+// The replayed events, then the live ones of `teacher_id` or of all teachers, as SSE frames.
+// The stream ends on shutdown, or when it falls too far behind.
+pub fn catalog_stream(
+    app_state: web::Data<AppState>,
+    receiver: broadcast::Receiver<Arc<OutboxEvent>>,
+    replay: Vec<OutboxEvent>,
+    teacher_id: Option<i32>,
+) -> impl Stream<Item = Result<web::Bytes, Infallible>> {
+    let state = CatalogStream {
+        app_state,
+        receiver,
+        replayed: replay.iter().map(|event| event.id).collect(),
+        replay: replay.into(),
+        teacher_id,
+        keep_alive: actix_rt::time::interval(KEEP_ALIVE),
+    };
+    stream::unfold(state, CatalogStream::next_frame)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::AuditContext;
+    use crate::dbaccesses::course::{create_one_course_db, delete_one_course_db};
+    use crate::models::course::CourseCreation;
+    use crate::read_cache::ReadCache;
+    use chrono::Utc;
+    use dotenv::dotenv;
+    use futures_util::StreamExt;
+    use sqlx::postgres::PgPoolOptions;
+    use std::env;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+
+    fn event(id: i64, teacher_id: i32) -> OutboxEvent {
+        OutboxEvent {
+            id,
+            event_type: "CourseUpdated".into(),
+            teacher_id: Some(teacher_id),
+            entity_id: 1,
+            data: serde_json::json!({}),
+            time: Utc::now(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn stream_should_skip_replayed_and_filtered_events() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: PgPoolOptions::new().connect_lazy(&url_db).unwrap(),
+        });
+        let events = CatalogEvents::new(16);
+        let receiver = events.subscribe();
+        let stream = catalog_stream(app_state, receiver, vec![event(2, 1)], Some(1));
+        futures_util::pin_mut!(stream);
+        events.publish(event(2, 1));
+        events.publish(event(3, 2));
+        events.publish(event(5, 1));
+        // Committed after 5, it is still streamed.
+        events.publish(event(4, 1));
+        let replayed = stream.next().await.unwrap().unwrap();
+        assert!(replayed.starts_with(b"id: 2\nevent: CourseUpdated\ndata: {"));
+        let mut live = Vec::new();
+        while live.len() < 2 {
+            let frame = stream.next().await.unwrap().unwrap();
+            // Keep-alive comments may come in between.
+            if !frame.starts_with(b":") {
+                live.push(frame);
+            }
+        }
+        assert!(live[0].starts_with(b"id: 5\n"));
+        assert!(live[1].starts_with(b"id: 4\n"));
+    }
+
+    #[test]
+    fn recent_ids_should_forget_the_oldest_ones() {
+        let mut recent = RecentIds::default();
+        assert!(recent.insert(5));
+        assert!(recent.insert(4));
+        assert!(!recent.insert(5));
+        for id in 6..(6 + RECENT_IDS as i64) {
+            recent.insert(id);
+        }
+        assert!(recent.insert(5));
+        assert_eq!(recent.max(), Some(5 + RECENT_IDS as i64));
+    }
+
+    #[actix_rt::test]
+    async fn listener_should_publish_committed_changes() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool.clone(),
+        });
+        let events = web::Data::new(CatalogEvents::new(1024));
+        let mut receiver = events.subscribe();
+        let listener = CatalogListener::connect(&pool).await.unwrap();
+        let listening = actix_rt::spawn(listener.run(app_state.clone(), events.clone()));
+        let course = create_one_course_db(
+            &pool,
+            CourseCreation {
+                teacher_id: 1,
+                name: "Live course".into(),
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: None,
+                language: None,
+                level: None,
+            },
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+        let created = actix_rt::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let event = receiver.recv().await.unwrap();
+                if event.event_type == "CourseCreated" && event.entity_id == course.id {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("CourseCreated was not published");
+        assert_eq!(created.teacher_id, Some(1));
+        delete_one_course_db(&pool, course.teacher_id, course.id, &AuditContext::default())
+            .await
+            .unwrap();
+        app_state.shutting_down.store(true, Ordering::SeqCst);
+        actix_rt::time::timeout(Duration::from_secs(5), listening)
+            .await
+            .expect("The listener did not stop on shutdown")
+            .unwrap();
+    }
+}
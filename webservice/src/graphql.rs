@@ -0,0 +1,367 @@
+use crate::audit::AuditContext;
+use crate::dbaccesses::course::*;
+use crate::dbaccesses::teacher::*;
+use crate::errors::MyError;
+use crate::models::course::{Course, CourseCreation, CourseFilter, CourseUpdate};
+use crate::models::teacher::{Teacher, TeacherCreation, TeacherUpdate};
+use actix_web::{web, ResponseError};
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{
+    ComplexObject, Context, EmptySubscription, ErrorExtensions, Object, Schema, SimpleObject,
+};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::env;
+
+pub type CatalogSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+const DEFAULT_PAGE_SIZE: i32 = 20;
+const MAX_PAGE_SIZE: i32 = 100;
+
+// Keeps the HTTP status of the REST error as the `status` extension of the GraphQL error.
+fn graphql_error(err: MyError) -> async_graphql::Error {
+    let status = err.status_code().as_u16();
+    async_graphql::Error::new(err.to_string()).extend_with(|_, e| e.set("status", status))
+}
+
+fn page_size(first: Option<i32>) -> Result<i64, async_graphql::Error> {
+    match first.unwrap_or(DEFAULT_PAGE_SIZE) {
+        first @ 1..=MAX_PAGE_SIZE => Ok(first as i64),
+        _ => Err(graphql_error(MyError::InvalidInput(format!(
+            "`first` must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )))),
+    }
+}
+
+// Loads the relations of a whole response in one query per relation, instead of one per parent.
+pub struct CatalogLoader {
+    pool: PgPool,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct TeacherId(i32);
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct CoursesOfTeacher(i32);
+
+#[async_trait::async_trait]
+impl Loader<TeacherId> for CatalogLoader {
+    type Value = Teacher;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[TeacherId]) -> Result<HashMap<TeacherId, Teacher>, Self::Error> {
+        let ids: Vec<i32> = keys.iter().map(|key| key.0).collect();
+        let teachers = get_teachers_by_ids_db(&self.pool, &ids)
+            .await
+            .map_err(graphql_error)?;
+        Ok(teachers
+            .into_iter()
+            .map(|teacher| (TeacherId(teacher.id), teacher))
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<CoursesOfTeacher> for CatalogLoader {
+    type Value = Vec<Course>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[CoursesOfTeacher],
+    ) -> Result<HashMap<CoursesOfTeacher, Vec<Course>>, Self::Error> {
+        let ids: Vec<i32> = keys.iter().map(|key| key.0).collect();
+        let courses = get_courses_for_teachers_db(&self.pool, &ids)
+            .await
+            .map_err(graphql_error)?;
+        let mut courses_of_teachers: HashMap<CoursesOfTeacher, Vec<Course>> = HashMap::new();
+        for course in courses {
+            courses_of_teachers
+                .entry(CoursesOfTeacher(course.teacher_id))
+                .or_default()
+                .push(course);
+        }
+        Ok(courses_of_teachers)
+    }
+}
+
+#[ComplexObject]
+impl Teacher {
+    // The published courses of the teacher.
+    async fn courses(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Course>> {
+        let loader = ctx.data_unchecked::<DataLoader<CatalogLoader>>();
+        Ok(loader
+            .load_one(CoursesOfTeacher(self.id))
+            .await?
+            .unwrap_or_default())
+    }
+}
+
+#[ComplexObject]
+impl Course {
+    async fn teacher(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Teacher>> {
+        let loader = ctx.data_unchecked::<DataLoader<CatalogLoader>>();
+        loader.load_one(TeacherId(self.teacher_id)).await
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct TeacherPage {
+    pub items: Vec<Teacher>,
+    // The `after` argument of the next page, missing on the last one.
+    pub next_after: Option<i32>,
+}
+
+#[derive(SimpleObject)]
+pub struct CoursePage {
+    pub items: Vec<Course>,
+    // The `after` argument of the next page, missing on the last one.
+    pub next_after: Option<i32>,
+}
+
+fn next_after<T>(items: &[T], limit: i64, id: impl Fn(&T) -> i32) -> Option<i32> {
+    if items.len() as i64 == limit {
+        items.last().map(id)
+    } else {
+        None
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn teachers(
+        &self,
+        ctx: &Context<'_>,
+        name_contains: Option<String>,
+        first: Option<i32>,
+        after: Option<i32>,
+    ) -> async_graphql::Result<TeacherPage> {
+        let pool = ctx.data_unchecked::<PgPool>();
+        let limit = page_size(first)?;
+        let items = get_teachers_page_db(pool, name_contains.as_deref(), after.unwrap_or(0), limit)
+            .await
+            .map_err(graphql_error)?;
+        let next_after = next_after(&items, limit, |teacher| teacher.id);
+        Ok(TeacherPage { items, next_after })
+    }
+
+    async fn teacher(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<Teacher> {
+        let pool = ctx.data_unchecked::<PgPool>();
+        get_one_teacher_detail_db(pool, id)
+            .await
+            .map_err(graphql_error)
+    }
+
+    // The published courses.
+    async fn courses(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<CourseFilter>,
+        first: Option<i32>,
+        after: Option<i32>,
+    ) -> async_graphql::Result<CoursePage> {
+        let pool = ctx.data_unchecked::<PgPool>();
+        let limit = page_size(first)?;
+        let items =
+            get_courses_page_db(pool, filter.unwrap_or_default(), after.unwrap_or(0), limit)
+                .await
+                .map_err(graphql_error)?;
+        let next_after = next_after(&items, limit, |course| course.id);
+        Ok(CoursePage { items, next_after })
+    }
+
+    // Published courses only, like the listings.
+    async fn course(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<Course> {
+        let pool = ctx.data_unchecked::<PgPool>();
+        get_one_visible_course_db(pool, id, None)
+            .await
+            .map_err(graphql_error)
+    }
+}
+
+// Mirrors the REST handlers, with the same validation and audit.
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn create_teacher(
+        &self,
+        ctx: &Context<'_>,
+        input: TeacherCreation,
+    ) -> async_graphql::Result<Teacher> {
+        let pool = ctx.data_unchecked::<PgPool>();
+        let audit = ctx.data_unchecked::<AuditContext>();
+        create_one_teacher_db(pool, web::Json(input).into(), audit)
+            .await
+            .map_err(graphql_error)
+    }
+
+    async fn update_teacher(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        input: TeacherUpdate,
+    ) -> async_graphql::Result<Teacher> {
+        let pool = ctx.data_unchecked::<PgPool>();
+        let audit = ctx.data_unchecked::<AuditContext>();
+        update_one_teacher_detail_db(pool, id, web::Json(input).into(), audit)
+            .await
+            .map_err(graphql_error)
+    }
+
+    async fn delete_teacher(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<String> {
+        let pool = ctx.data_unchecked::<PgPool>();
+        let audit = ctx.data_unchecked::<AuditContext>();
+        delete_one_teacher_db(pool, id, audit)
+            .await
+            .map_err(graphql_error)
+    }
+
+    async fn create_course(
+        &self,
+        ctx: &Context<'_>,
+        input: CourseCreation,
+    ) -> async_graphql::Result<Course> {
+        let pool = ctx.data_unchecked::<PgPool>();
+        let audit = ctx.data_unchecked::<AuditContext>();
+        let new_course: CourseCreation = web::Json(input).try_into().map_err(graphql_error)?;
+        create_one_course_db(pool, new_course, audit)
+            .await
+            .map_err(graphql_error)
+    }
+
+    async fn update_course(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        input: CourseUpdate,
+    ) -> async_graphql::Result<Course> {
+        let pool = ctx.data_unchecked::<PgPool>();
+        let audit = ctx.data_unchecked::<AuditContext>();
+        let course = get_one_course_by_id_db(pool, id)
+            .await
+            .map_err(graphql_error)?;
+        update_one_course_detail_db(pool, course.teacher_id, id, web::Json(input).into(), audit)
+            .await
+            .map_err(graphql_error)
+    }
+
+    async fn delete_course(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<String> {
+        let pool = ctx.data_unchecked::<PgPool>();
+        let audit = ctx.data_unchecked::<AuditContext>();
+        let course = get_one_course_by_id_db(pool, id)
+            .await
+            .map_err(graphql_error)?;
+        delete_one_course_db(pool, course.teacher_id, id, audit)
+            .await
+            .map_err(graphql_error)
+    }
+}
+
+pub fn build_schema(pool: PgPool) -> CatalogSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(pool)
+        .limit_depth(10)
+        .limit_complexity(1000)
+        .finish()
+}
+
+// A loader lives for one request, so that nothing is cached across requests.
+pub fn catalog_loader(pool: &PgPool) -> DataLoader<CatalogLoader> {
+    DataLoader::new(CatalogLoader { pool: pool.clone() }, actix_rt::spawn)
+}
+
+// GraphiQL is served in debug builds, or when `GRAPHIQL_ENABLED` is `true`.
+pub fn graphiql_enabled() -> bool {
+    env::var("GRAPHIQL_ENABLED")
+        .map(|value| value == "true")
+        .unwrap_or(cfg!(debug_assertions))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::course::{CourseStatus, CourseTransitionCreation};
+    use async_graphql::{Request, Variables};
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+
+    async fn execute(
+        schema: &CatalogSchema,
+        pool: &PgPool,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> serde_json::Value {
+        let request = Request::new(query)
+            .variables(Variables::from_json(variables))
+            .data(catalog_loader(pool))
+            .data(AuditContext::default());
+        let resp = schema.execute(request).await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+        resp.data.into_json().unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn courses_should_be_resolved_with_their_teacher() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let schema = build_schema(pool.clone());
+        let created = execute(
+            &schema,
+            &pool,
+            "mutation($input: CourseCreation!) { createCourse(input: $input) { id } }",
+            serde_json::json!({ "input": { "teacherId": 1, "name": "GraphQL course" } }),
+        )
+        .await;
+        let id = created["createCourse"]["id"].as_i64().unwrap();
+        // New courses are drafts, which the public queries don't see.
+        for to_status in [CourseStatus::InReview, CourseStatus::Published] {
+            transition_one_course_db(
+                &pool,
+                id as i32,
+                CourseTransitionCreation { to_status },
+                &AuditContext::default(),
+            )
+            .await
+            .unwrap();
+        }
+        let data = execute(
+            &schema,
+            &pool,
+            "query($id: Int!) { course(id: $id) { name teacher { id courses { id } } } }",
+            serde_json::json!({ "id": id }),
+        )
+        .await;
+        assert_eq!(data["course"]["name"], "GraphQL course");
+        assert_eq!(data["course"]["teacher"]["id"], 1);
+        let data = execute(
+            &schema,
+            &pool,
+            "{ teachers(first: 1) { items { id courses { teacherId } } nextAfter } }",
+            serde_json::json!({}),
+        )
+        .await;
+        let teacher = &data["teachers"]["items"][0];
+        for course in teacher["courses"].as_array().unwrap() {
+            assert_eq!(course["teacherId"], teacher["id"]);
+        }
+        execute(
+            &schema,
+            &pool,
+            "mutation($id: Int!) { deleteCourse(id: $id) }",
+            serde_json::json!({ "id": id }),
+        )
+        .await;
+    }
+
+    #[test]
+    fn page_size_should_be_bounded() {
+        assert_eq!(page_size(None).unwrap(), 20);
+        assert!(page_size(Some(0)).is_err());
+        assert!(page_size(Some(101)).is_err());
+    }
+}
@@ -0,0 +1,390 @@
+use crate::audit::{parse_actor, AuditContext};
+use crate::dbaccesses::course::*;
+use crate::dbaccesses::teacher::*;
+use crate::errors::MyError;
+use crate::models::course::{Course, CourseCreation, CourseSort, CourseUpdate};
+use crate::models::teacher::{Teacher, TeacherCreation, TeacherUpdate};
+use crate::security::AccessConfig;
+use crate::shutdown::wait_for_shutdown;
+use crate::state::AppState;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use futures_util::stream;
+use proto::catalog_server::{Catalog, CatalogServer};
+use proto::*;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status};
+
+#[allow(clippy::all, dead_code)]
+pub mod proto {
+    tonic::include_proto!("catalog.v1");
+}
+
+pub const ACTOR_METADATA: &str = "x-actor";
+pub const REQUEST_ID_METADATA: &str = "x-request-id";
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+impl From<MyError> for Status {
+    fn from(err: MyError) -> Self {
+        let code = match err {
+            MyError::DBError(_) | MyError::ActixError(_) => Code::Internal,
+            MyError::NotFound(_) => Code::NotFound,
+            MyError::Forbidden(_) => Code::PermissionDenied,
+            MyError::InvalidInput(_)
+            | MyError::UnprocessableEntity(_)
+            | MyError::NotAcceptable(_)
+            | MyError::UnsupportedMediaType(_) => Code::InvalidArgument,
+            MyError::PayloadTooLarge(_) | MyError::TooManyRequests(_) => Code::ResourceExhausted,
+            MyError::Conflict(_) => Code::FailedPrecondition,
+            MyError::RangeNotSatisfiable(_) => Code::OutOfRange,
+        };
+        Status::new(code, err.to_string())
+    }
+}
+
+fn timestamp(time: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+impl From<Teacher> for proto::Teacher {
+    fn from(teacher: Teacher) -> Self {
+        proto::Teacher {
+            id: teacher.id,
+            name: teacher.name,
+            url_picture: teacher.url_picture,
+            profile: teacher.profile,
+            updated_at: Some(timestamp(teacher.updated_at)),
+        }
+    }
+}
+
+impl From<Course> for proto::Course {
+    fn from(course: Course) -> Self {
+        proto::Course {
+            teacher_id: course.teacher_id,
+            id: course.id,
+            name: course.name,
+            // The times are stored in UTC.
+            time: course
+                .time
+                .map(|time| timestamp(DateTime::from_utc(time, Utc))),
+            description: course.description,
+            format: course.format,
+            structure: course.structure,
+            price: course.price,
+            language: course.language,
+            level: course.level,
+            duration_minutes: course.duration_minutes,
+            rating_average: course.rating_average,
+            rating_count: course.rating_count,
+            status: course.status.as_str().to_owned(),
+            updated_at: Some(timestamp(course.updated_at)),
+        }
+    }
+}
+
+// The audit context of a call, read from its metadata as it is from the headers over HTTP,
+// and anonymous unless the call comes from a trusted proxy.
+fn audit_context<T>(access: &AccessConfig, request: &Request<T>) -> Result<AuditContext, MyError> {
+    let actor = if access.trusts(request.remote_addr().map(|addr| addr.ip())) {
+        parse_actor(
+            request
+                .metadata()
+                .get(ACTOR_METADATA)
+                .map(|value| value.to_str().unwrap_or_default()),
+        )?
+    } else {
+        AuditContext::default().actor
+    };
+    let request_id = request
+        .metadata()
+        .get(REQUEST_ID_METADATA)
+        .and_then(|value| value.to_str().ok())
+        .filter(|request_id| request_id.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(|request_id| request_id.to_owned());
+    Ok(AuditContext { actor, request_id })
+}
+
+pub struct CatalogService {
+    app_state: web::Data<AppState>,
+    access: AccessConfig,
+}
+
+impl CatalogService {
+    pub fn new(app_state: web::Data<AppState>, access: AccessConfig) -> Self {
+        CatalogService { app_state, access }
+    }
+}
+
+#[tonic::async_trait]
+impl Catalog for CatalogService {
+    async fn list_teachers(
+        &self,
+        _request: Request<ListTeachersRequest>,
+    ) -> Result<Response<ListTeachersResponse>, Status> {
+        let teachers = get_all_teachers_db(&self.app_state.db).await?;
+        Ok(Response::new(ListTeachersResponse {
+            teachers: teachers.into_iter().map(proto::Teacher::from).collect(),
+        }))
+    }
+
+    async fn get_teacher(
+        &self,
+        request: Request<GetTeacherRequest>,
+    ) -> Result<Response<proto::Teacher>, Status> {
+        let teacher = self
+            .app_state
+            .read_cache
+            .teacher(&self.app_state.db, request.get_ref().id)
+            .await?;
+        Ok(Response::new(teacher.into()))
+    }
+
+    async fn create_teacher(
+        &self,
+        request: Request<CreateTeacherRequest>,
+    ) -> Result<Response<proto::Teacher>, Status> {
+        let audit = audit_context(&self.access, &request)?;
+        let request = request.into_inner();
+        let new_teacher = TeacherCreation {
+            name: request.name,
+            profile: request.profile,
+        };
+        let teacher = create_one_teacher_db(&self.app_state.db, new_teacher, &audit).await?;
+        Ok(Response::new(teacher.into()))
+    }
+
+    async fn update_teacher(
+        &self,
+        request: Request<UpdateTeacherRequest>,
+    ) -> Result<Response<proto::Teacher>, Status> {
+        let audit = audit_context(&self.access, &request)?;
+        let request = request.into_inner();
+        let teacher_update = TeacherUpdate {
+            name: request.name,
+            profile: request.profile,
+        };
+        let teacher =
+            update_one_teacher_detail_db(&self.app_state.db, request.id, teacher_update, &audit)
+                .await?;
+        self.app_state.read_cache.invalidate_teacher(request.id);
+        Ok(Response::new(teacher.into()))
+    }
+
+    async fn delete_teacher(
+        &self,
+        request: Request<DeleteTeacherRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let audit = audit_context(&self.access, &request)?;
+        let message =
+            delete_one_teacher_db(&self.app_state.db, request.get_ref().id, &audit).await?;
+        self.app_state
+            .read_cache
+            .invalidate_teacher(request.get_ref().id);
+        Ok(Response::new(DeleteResponse { message }))
+    }
+
+    async fn list_courses(
+        &self,
+        request: Request<ListCoursesRequest>,
+    ) -> Result<Response<ListCoursesResponse>, Status> {
+        let courses = get_courses_for_teacher_db(
+            &self.app_state.db,
+            request.get_ref().teacher_id,
+            CourseSort::Id,
+        )
+        .await?;
+        Ok(Response::new(ListCoursesResponse {
+            courses: courses.into_iter().map(proto::Course::from).collect(),
+        }))
+    }
+
+    async fn get_course(
+        &self,
+        request: Request<GetCourseRequest>,
+    ) -> Result<Response<proto::Course>, Status> {
+        let course = self
+            .app_state
+            .read_cache
+            .course(&self.app_state.db, request.get_ref().id)
+            .await?;
+        Ok(Response::new(course.into()))
+    }
+
+    async fn create_course(
+        &self,
+        request: Request<CreateCourseRequest>,
+    ) -> Result<Response<proto::Course>, Status> {
+        let audit = audit_context(&self.access, &request)?;
+        let request = request.into_inner();
+        let new_course: CourseCreation = web::Json(CourseCreation {
+            teacher_id: request.teacher_id,
+            name: request.name,
+            description: request.description,
+            format: request.format,
+            structure: request.structure,
+            duration: None,
+            price: request.price,
+            language: request.language,
+            level: request.level,
+        })
+        .try_into()?;
+        let course = create_one_course_db(&self.app_state.db, new_course, &audit).await?;
+        Ok(Response::new(course.into()))
+    }
+
+    async fn update_course(
+        &self,
+        request: Request<UpdateCourseRequest>,
+    ) -> Result<Response<proto::Course>, Status> {
+        let audit = audit_context(&self.access, &request)?;
+        let request = request.into_inner();
+        let course = get_one_course_by_id_db(&self.app_state.db, request.id).await?;
+        let course_update = CourseUpdate {
+            name: request.name,
+            description: request.description,
+            format: request.format,
+            structure: request.structure,
+            duration: None,
+            price: request.price,
+            language: request.language,
+            level: request.level,
+        };
+        let course = update_one_course_detail_db(
+            &self.app_state.db,
+            course.teacher_id,
+            course.id,
+            course_update,
+            &audit,
+        )
+        .await?;
+        self.app_state.read_cache.invalidate_course(course.id);
+        Ok(Response::new(course.into()))
+    }
+
+    async fn delete_course(
+        &self,
+        request: Request<DeleteCourseRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let audit = audit_context(&self.access, &request)?;
+        let course = get_one_course_by_id_db(&self.app_state.db, request.get_ref().id).await?;
+        let message =
+            delete_one_course_db(&self.app_state.db, course.teacher_id, course.id, &audit).await?;
+        self.app_state.read_cache.invalidate_course(course.id);
+        Ok(Response::new(DeleteResponse { message }))
+    }
+}
+
+// Binds the gRPC port up front, so that the service does not start when it is taken.
+pub fn bind(address: SocketAddr) -> io::Result<TcpListener> {
+    let listener = std::net::TcpListener::bind(address).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("Unable to bind the gRPC server to {}: {}", address, err),
+        )
+    })?;
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+// Serves the gRPC API until the HTTP server starts shutting down.
+pub async fn serve(listener: TcpListener, app_state: web::Data<AppState>, access: AccessConfig) {
+    let incoming = stream::unfold(listener, |listener| async move {
+        let connection = listener
+            .accept()
+            .await
+            .map(|(connection, _peer)| connection);
+        Some((connection, listener))
+    });
+    let shutting_down = app_state.clone();
+    let shutdown = async move { wait_for_shutdown(&shutting_down).await };
+    let result = Server::builder()
+        .add_service(CatalogServer::new(CatalogService::new(app_state, access)))
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await;
+    if let Err(err) = result {
+        tracing::error!("The gRPC server stopped: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::read_cache::ReadCache;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::env;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+    use tonic::metadata::MetadataValue;
+
+    #[test]
+    fn errors_should_map_to_grpc_codes() {
+        let status = Status::from(MyError::NotFound("Course not found".into()));
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "Course not found");
+        let status = Status::from(MyError::DBError("connection refused".into()));
+        assert_eq!(status.code(), Code::Internal);
+        assert_eq!(status.message(), "Database error");
+        let status = Status::from(MyError::Conflict("Already reviewed".into()));
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+
+    #[actix_rt::test]
+    async fn course_crud_should_go_through_grpc() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let service = CatalogService::new(
+            web::Data::new(AppState {
+                health_check_response: "".to_owned(),
+                visit_count: Mutex::new(0),
+                shutting_down: AtomicBool::new(false),
+                read_cache: ReadCache::default(),
+                db: pool_db,
+            }),
+            AccessConfig::default(),
+        );
+        let mut request = Request::new(CreateCourseRequest {
+            teacher_id: 1,
+            name: "gRPC course".into(),
+            description: None,
+            format: None,
+            structure: None,
+            price: Some(100),
+            language: None,
+            level: None,
+        });
+        request
+            .metadata_mut()
+            .insert(ACTOR_METADATA, MetadataValue::from_static("grpc-test"));
+        let course = service.create_course(request).await.unwrap().into_inner();
+        assert_eq!(course.status, "draft");
+
+        let request = Request::new(UpdateCourseRequest {
+            id: course.id,
+            name: None,
+            description: None,
+            format: None,
+            structure: None,
+            price: Some(200),
+            language: None,
+            level: None,
+        });
+        let updated = service.update_course(request).await.unwrap().into_inner();
+        assert_eq!(updated.name, "gRPC course");
+        assert_eq!(updated.price, Some(200));
+
+        let request = Request::new(DeleteCourseRequest { id: course.id });
+        service.delete_course(request).await.unwrap();
+        let request = Request::new(GetCourseRequest { id: course.id });
+        let status = service.get_course(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+}
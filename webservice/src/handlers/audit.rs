@@ -0,0 +1,109 @@
+use crate::dbaccesses::audit::*;
+use crate::errors::MyError;
+use crate::models::audit::AuditEventQuery;
+use crate::security::Admin;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/audit-events",
+    tag = "audit",
+    params(
+        ("entity_type" = Option<String>, Query, description = "`teacher`, `course`, `course_section`, `course_lesson`, `course_session`, `course_material` or `review`"),
+        ("entity_id" = Option<i32>, Query, description = "Id of the entity"),
+        ("from" = Option<String>, Query, description = "RFC 3339 time of the oldest event"),
+        ("to" = Option<String>, Query, description = "RFC 3339 time after the newest event"),
+        ("limit" = Option<i64>, Query, description = "At most 1000 events, the default")
+    ),
+    responses(
+        (status = 200, description = "The matching events, oldest first", body = [AuditEvent]),
+        (status = 400, description = "Invalid range", body = MyErrorResponse),
+        (status = 403, description = "The actor isn't an administrator", body = MyErrorResponse)
+    )
+)]
+pub async fn get_audit_events(
+    app_state: web::Data<AppState>,
+    query: web::Query<AuditEventQuery>,
+    _admin: Admin,
+) -> Result<HttpResponse, MyError> {
+    let query = query.into_inner();
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if to <= from {
+            return Err(MyError::InvalidInput(
+                "The range must end after it starts".into(),
+            ));
+        }
+    }
+    get_audit_events_db(&app_state.db, query)
+        .await
+        .map(|events| HttpResponse::Ok().json(events))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::AuditContext;
+    use crate::dbaccesses::course::*;
+    use crate::models::course::{CourseCreation, CourseUpdate};
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::env;
+
+    #[actix_rt::test]
+    async fn course_mutations_should_be_audited() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let audit = AuditContext {
+            actor: "auditor".into(),
+            request_id: Some("test-request".into()),
+        };
+        let course = create_one_course_db(
+            &pool,
+            CourseCreation {
+                teacher_id: 1,
+                name: "Audited course".into(),
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: None,
+                language: None,
+                level: None,
+            },
+            &audit,
+        )
+        .await
+        .unwrap();
+        let course_update = CourseUpdate {
+            name: Some("Renamed course".into()),
+            description: None,
+            format: None,
+            structure: None,
+            duration: None,
+            price: None,
+            language: None,
+            level: None,
+        };
+        update_one_course_detail_db(&pool, course.teacher_id, course.id, course_update, &audit)
+            .await
+            .unwrap();
+        delete_one_course_db(&pool, course.teacher_id, course.id, &audit)
+            .await
+            .unwrap();
+        let query = AuditEventQuery {
+            entity_type: Some("course".into()),
+            entity_id: Some(course.id),
+            from: None,
+            to: None,
+            limit: None,
+        };
+        let events = get_audit_events_db(&pool, query).await.unwrap();
+        let actions: Vec<&str> = events.iter().map(|event| event.action.as_str()).collect();
+        assert_eq!(actions, vec!["create", "update", "delete"]);
+        assert_eq!(events[1].diff["name"]["after"], "Renamed course");
+        assert_eq!(events[1].actor, "auditor");
+        assert_eq!(events[1].request_id.as_deref(), Some("test-request"));
+    }
+}
@@ -1,24 +1,84 @@
+use crate::audit::{AuditContext, Viewer};
+use crate::caching::{last_modified, list_version};
 use crate::dbaccesses::course::*;
 use crate::errors::MyError;
-use crate::models::course::{CourseCreation, CourseUpdate};
+use crate::idempotency::{idempotent, IdempotencyKey};
+use crate::models::course::{
+    CourseCreation, CourseListQuery, CourseTransitionCreation, CourseUpdate,
+};
+use crate::negotiation::{Accepted, Body};
+use crate::security::{owner_or_admin, Admin};
 use crate::state::AppState;
 use actix_web::{web, HttpResponse};
 
 pub async fn create_one_course(
     app_state: web::Data<AppState>,
-    new_course: web::Json<CourseCreation>,
+    idempotency_key: IdempotencyKey,
+    accepted: Accepted,
+    audit: AuditContext,
+    new_course: Body<CourseCreation>,
 ) -> Result<HttpResponse, MyError> {
-    create_one_course_db(&app_state.db, new_course.try_into()?)
-        .await
-        .map(|course| HttpResponse::Ok().json(course))
+    let new_course: CourseCreation = new_course.into_json().try_into()?;
+    idempotent(
+        &app_state.db,
+        idempotency_key,
+        &accepted,
+        "create_one_course",
+        &new_course,
+        create_one_course_db(&app_state.db, new_course.clone(), &audit),
+    )
+    .await
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/teachers/{teacher_id}/courses",
+    tag = "course",
+    params(
+        ("teacher_id" = i32, Path, description = "Id of the teacher"),
+        ("sort" = Option<CourseSort>, Query, description = "`rating` lists the best rated courses first, `id` is the default")
+    ),
+    responses(
+        (status = 200, description = "The courses of the teacher", body = [Course])
+    )
+)]
 pub async fn get_courses_for_teacher(
     app_state: web::Data<AppState>,
     params: web::Path<(i32,)>,
+    query: web::Query<CourseListQuery>,
+    accepted: Accepted,
+) -> Result<HttpResponse, MyError> {
+    let (teacher_id,) = params.into_inner();
+    let version = get_courses_version_db(&app_state.db, teacher_id).await?;
+    let courses = stream_courses_for_teacher_db(&app_state.db, teacher_id, query.sort);
+    Ok(list_version(accepted.stream(courses).await?, version))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/teachers/{teacher_id}/drafts",
+    tag = "course",
+    params(
+        ("teacher_id" = i32, Path, description = "Id of the teacher"),
+        ("X-Actor" = String, Header, description = "`teacher:<teacher_id>`, only the teacher sees their drafts")
+    ),
+    responses(
+        (status = 200, description = "The courses of the teacher that aren't published, drafts first", body = [Course]),
+        (status = 403, description = "The actor isn't the teacher", body = MyErrorResponse)
+    )
+)]
+pub async fn get_unpublished_courses_for_teacher(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    viewer: Viewer,
 ) -> Result<HttpResponse, MyError> {
     let (teacher_id,) = params.into_inner();
-    get_courses_for_teacher_db(&app_state.db, teacher_id)
+    if viewer.teacher_id != Some(teacher_id) {
+        return Err(MyError::Forbidden(
+            "Only the teacher can see their unpublished courses".into(),
+        ));
+    }
+    get_unpublished_courses_for_teacher_db(&app_state.db, teacher_id)
         .await
         .map(|courses| HttpResponse::Ok().json(courses))
 }
@@ -26,41 +86,244 @@ pub async fn get_courses_for_teacher(
 pub async fn get_one_course_detail(
     app_state: web::Data<AppState>,
     params: web::Path<(i32, i32)>,
+    accepted: Accepted,
+    viewer: Viewer,
 ) -> Result<HttpResponse, MyError> {
     let (teacher_id, course_id) = params.into_inner();
-    get_one_course_detail_db(&app_state.db, teacher_id, course_id)
-        .await
-        .map(|course| HttpResponse::Ok().json(course))
+    let course = app_state
+        .read_cache
+        .course_of_teacher(&app_state.db, viewer, teacher_id, course_id)
+        .await?;
+    Ok(last_modified(
+        accepted.item(&course)?,
+        Some(course.updated_at),
+    ))
 }
 
 pub async fn delete_one_course(
     app_state: web::Data<AppState>,
     params: web::Path<(i32, i32)>,
+    audit: AuditContext,
 ) -> Result<HttpResponse, MyError> {
     let (teacher_id, course_id) = params.into_inner();
-    delete_one_course_db(&app_state.db, teacher_id, course_id)
-        .await
-        .map(|resp| HttpResponse::Ok().json(resp))
+    let resp = delete_one_course_db(&app_state.db, teacher_id, course_id, &audit).await?;
+    app_state.read_cache.invalidate_course(course_id);
+    Ok(HttpResponse::Ok().json(resp))
 }
 
 pub async fn update_one_course_detail(
     app_state: web::Data<AppState>,
-    course_update: web::Json<CourseUpdate>,
+    course_update: Body<CourseUpdate>,
     params: web::Path<(i32, i32)>,
+    accepted: Accepted,
+    audit: AuditContext,
 ) -> Result<HttpResponse, MyError> {
     let (teacher_id, course_id) = params.into_inner();
-    update_one_course_detail_db(&app_state.db, teacher_id, course_id, course_update.into())
+    let course = update_one_course_detail_db(
+        &app_state.db,
+        teacher_id,
+        course_id,
+        course_update.into_json().into(),
+        &audit,
+    )
+    .await?;
+    app_state.read_cache.invalidate_course(course_id);
+    accepted.item(&course)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/teachers/{teacher_id}/courses",
+    tag = "course",
+    params(
+        ("teacher_id" = i32, Path, description = "Id of the teacher"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Replays the response of a former request with the same key")
+    ),
+    request_body = CourseCreation,
+    responses(
+        (status = 200, description = "The created course", body = Course),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 409, description = "A request with the same key is in progress", body = MyErrorResponse),
+        (status = 422, description = "The key was used for another request", body = MyErrorResponse)
+    )
+)]
+pub async fn create_one_course_for_teacher(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    idempotency_key: IdempotencyKey,
+    accepted: Accepted,
+    audit: AuditContext,
+    new_course: Body<CourseCreation>,
+) -> Result<HttpResponse, MyError> {
+    let (teacher_id,) = params.into_inner();
+    if new_course.teacher_id != teacher_id {
+        return Err(MyError::InvalidInput(
+            "Teacher ID in the path and in the body don't match".into(),
+        ));
+    }
+    let new_course: CourseCreation = new_course.into_json().try_into()?;
+    idempotent(
+        &app_state.db,
+        idempotency_key,
+        &accepted,
+        "create_one_course",
+        &new_course,
+        create_one_course_db(&app_state.db, new_course.clone(), &audit),
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}",
+    tag = "course",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    responses(
+        (status = 200, description = "The course", body = Course),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_one_course_by_id(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    accepted: Accepted,
+    viewer: Viewer,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    let course = app_state
+        .read_cache
+        .visible_course(&app_state.db, viewer, course_id)
+        .await?;
+    Ok(last_modified(
+        accepted.item(&course)?,
+        Some(course.updated_at),
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/courses/{course_id}",
+    tag = "course",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    responses(
+        (status = 200, description = "The number of deleted records", body = String),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn delete_one_course_by_id(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    let course = get_one_course_by_id_db(&app_state.db, course_id).await?;
+    let resp = delete_one_course_db(&app_state.db, course.teacher_id, course_id, &audit).await?;
+    app_state.read_cache.invalidate_course(course_id);
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/courses/{course_id}",
+    tag = "course",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    request_body = CourseUpdate,
+    responses(
+        (status = 200, description = "The updated course", body = Course),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn update_one_course_by_id(
+    app_state: web::Data<AppState>,
+    course_update: Body<CourseUpdate>,
+    params: web::Path<(i32,)>,
+    accepted: Accepted,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    let course = get_one_course_by_id_db(&app_state.db, course_id).await?;
+    let course = update_one_course_detail_db(
+        &app_state.db,
+        course.teacher_id,
+        course_id,
+        course_update.into_json().into(),
+        &audit,
+    )
+    .await?;
+    app_state.read_cache.invalidate_course(course_id);
+    accepted.item(&course)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/transitions",
+    tag = "course",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    responses(
+        (status = 200, description = "The status changes of the course, oldest first", body = [CourseTransition]),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_transitions_for_course(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    viewer: Viewer,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    app_state
+        .read_cache
+        .visible_course(&app_state.db, viewer, course_id)
+        .await?;
+    get_transitions_for_course_db(&app_state.db, course_id)
         .await
-        .map(|course| HttpResponse::Ok().json(course))
+        .map(|transitions| HttpResponse::Ok().json(transitions))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/courses/{course_id}/transitions",
+    tag = "course",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("X-Actor" = String, Header, description = "`teacher:<teacher_id>` of the course or one of the administrators, recorded as the actor of the change")
+    ),
+    request_body = CourseTransitionCreation,
+    responses(
+        (status = 200, description = "The recorded status change", body = CourseTransition),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 403, description = "The actor is neither the teacher of the course nor an administrator", body = MyErrorResponse),
+        (status = 404, description = "Course not found", body = MyErrorResponse),
+        (status = 409, description = "The course can't move to this status from its current one", body = MyErrorResponse)
+    )
+)]
+pub async fn transition_one_course(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    audit: AuditContext,
+    viewer: Viewer,
+    admin: Option<Admin>,
+    transition: web::Json<CourseTransitionCreation>,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    let course = get_one_course_by_id_db(&app_state.db, course_id).await?;
+    owner_or_admin(viewer, admin.as_ref(), course.teacher_id)?;
+    let transition =
+        transition_one_course_db(&app_state.db, course_id, transition.into(), &audit).await?;
+    app_state.read_cache.invalidate_course(course_id);
+    Ok(HttpResponse::Ok().json(transition))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use actix_web::{http::StatusCode, ResponseError};
+    use crate::models::course::{CourseSort, CourseStatus};
+    use crate::read_cache::ReadCache;
+    use actix_web::http::StatusCode;
     use dotenv::dotenv;
     use sqlx::postgres::PgPoolOptions;
     use std::env;
+    use std::sync::atomic::AtomicBool;
     use std::sync::Mutex;
     #[actix_rt::test]
     async fn add_one_course_should_succeed() {
@@ -70,9 +333,11 @@ mod test {
         let app_state: web::Data<AppState> = web::Data::new(AppState {
             health_check_response: "".to_owned(),
             visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
             db: pool_db,
         });
-        let course = web::Json(CourseCreation {
+        let course = Body(CourseCreation {
             teacher_id: 1,
             name: "Test course".into(),
             description: Some("This is a course".into()),
@@ -83,7 +348,15 @@ mod test {
             language: Some("English".into()),
             level: Some("Beginner".into()),
         });
-        let resp = create_one_course(app_state, course).await.unwrap();
+        let resp = create_one_course(
+            app_state,
+            IdempotencyKey::default(),
+            Accepted::default(),
+            AuditContext::default(),
+            course,
+        )
+        .await
+        .unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
     }
     #[actix_rt::test]
@@ -94,10 +367,15 @@ mod test {
         let app_state: web::Data<AppState> = web::Data::new(AppState {
             health_check_response: "".to_owned(),
             visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
             db: pool_db,
         });
         let teacher_id: web::Path<(i32,)> = web::Path::from((1,));
-        let resp = get_courses_for_teacher(app_state, teacher_id)
+        let query = web::Query(CourseListQuery {
+            sort: CourseSort::Rating,
+        });
+        let resp = get_courses_for_teacher(app_state, teacher_id, query, Accepted::default())
             .await
             .unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
@@ -110,10 +388,14 @@ mod test {
         let app_state: web::Data<AppState> = web::Data::new(AppState {
             health_check_response: "".to_owned(),
             visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
             db: pool_db,
         });
         let params: web::Path<(i32, i32)> = web::Path::from((1, 5));
-        let resp = get_one_course_detail(app_state, params).await.unwrap();
+        let resp = get_one_course_detail(app_state, params, Accepted::default(), Viewer::default())
+            .await
+            .unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
     }
     #[actix_rt::test]
@@ -124,9 +406,11 @@ mod test {
         let app_state: web::Data<AppState> = web::Data::new(AppState {
             health_check_response: "".to_owned(),
             visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
             db: pool_db,
         });
-        let course = web::Json(CourseCreation {
+        let course = Body(CourseCreation {
             teacher_id: 1,
             name: "Test course".into(),
             description: None,
@@ -137,7 +421,15 @@ mod test {
             language: Some("English".into()),
             level: Some("Beginner".into()),
         });
-        let resp = create_one_course(app_state, course).await.unwrap();
+        let resp = create_one_course(
+            app_state,
+            IdempotencyKey::default(),
+            Accepted::default(),
+            AuditContext::default(),
+            course,
+        )
+        .await
+        .unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
     }
     #[actix_rt::test]
@@ -148,13 +440,198 @@ mod test {
         let app_state: web::Data<AppState> = web::Data::new(AppState {
             health_check_response: "".to_owned(),
             visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
             db: pool_db,
         });
         let params: web::Path<(i32, i32)> = web::Path::from((1, 100));
-        let resp = get_one_course_detail(app_state, params).await;
-        match resp {
-            Ok(_) => println!("Something went wrong..."),
-            Err(err) => assert_eq!(err.status_code(), StatusCode::NOT_FOUND),
+        let err = get_one_course_detail(app_state, params, Accepted::default(), Viewer::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MyError::NotFound(_)));
+    }
+    #[actix_rt::test]
+    async fn create_one_course_for_teacher_should_fail_on_teacher_mismatch() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let params: web::Path<(i32,)> = web::Path::from((2,));
+        let course = Body(CourseCreation {
+            teacher_id: 1,
+            name: "Test course".into(),
+            description: None,
+            format: None,
+            structure: None,
+            duration: None,
+            price: None,
+            language: Some("English".into()),
+            level: Some("Beginner".into()),
+        });
+        let err = create_one_course_for_teacher(
+            app_state,
+            params,
+            IdempotencyKey::default(),
+            Accepted::default(),
+            AuditContext::default(),
+            course,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, MyError::InvalidInput(_)));
+    }
+    #[actix_rt::test]
+    async fn get_one_course_by_id_should_fail() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let params: web::Path<(i32,)> = web::Path::from((100,));
+        let err = get_one_course_by_id(app_state, params, Accepted::default(), Viewer::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MyError::NotFound(_)));
+    }
+    #[actix_rt::test]
+    async fn transition_one_course_should_follow_the_workflow() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let course = create_one_course_db(
+            &app_state.db,
+            CourseCreation {
+                teacher_id: 1,
+                name: "Workflow course".into(),
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: None,
+                language: None,
+                level: None,
+            },
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(course.status, CourseStatus::Draft);
+        let err = get_one_course_by_id(
+            app_state.clone(),
+            web::Path::from((course.id,)),
+            Accepted::default(),
+            Viewer::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, MyError::NotFound(_)));
+        let err = get_unpublished_courses_for_teacher(
+            app_state.clone(),
+            web::Path::from((1,)),
+            Viewer::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, MyError::Forbidden(_)));
+        let err = get_transitions_for_course(
+            app_state.clone(),
+            web::Path::from((course.id,)),
+            Viewer::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, MyError::NotFound(_)));
+        let owner = Viewer::from_actor("teacher:1");
+        let drafts = get_unpublished_courses_for_teacher_db(&app_state.db, 1)
+            .await
+            .unwrap();
+        assert!(drafts.iter().any(|c| c.id == course.id));
+        let resp = get_one_course_by_id(
+            app_state.clone(),
+            web::Path::from((course.id,)),
+            Accepted::default(),
+            owner,
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let err = transition_one_course(
+            app_state.clone(),
+            web::Path::from((course.id,)),
+            AuditContext::default(),
+            Viewer::default(),
+            None,
+            web::Json(CourseTransitionCreation {
+                to_status: CourseStatus::InReview,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, MyError::Forbidden(_)));
+        let err = transition_one_course(
+            app_state.clone(),
+            web::Path::from((course.id,)),
+            AuditContext::default(),
+            owner,
+            None,
+            web::Json(CourseTransitionCreation {
+                to_status: CourseStatus::Published,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, MyError::Conflict(_)));
+        let audit = AuditContext {
+            actor: "teacher:1".into(),
+            request_id: None,
+        };
+        for to_status in [CourseStatus::InReview, CourseStatus::Published] {
+            transition_one_course(
+                app_state.clone(),
+                web::Path::from((course.id,)),
+                audit.clone(),
+                owner,
+                None,
+                web::Json(CourseTransitionCreation { to_status }),
+            )
+            .await
+            .unwrap();
         }
+        let transitions = get_transitions_for_course_db(&app_state.db, course.id)
+            .await
+            .unwrap();
+        assert_eq!(transitions.len(), 2);
+        assert_eq!(transitions[1].from_status, "in_review");
+        assert_eq!(transitions[1].actor, "teacher:1");
+        let courses = get_courses_for_teacher_db(&app_state.db, 1, CourseSort::Id)
+            .await
+            .unwrap();
+        assert!(courses.iter().any(|c| c.id == course.id));
+        delete_one_course_db(
+            &app_state.db,
+            course.teacher_id,
+            course.id,
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
     }
 }
@@ -0,0 +1,349 @@
+use crate::audit::{AuditContext, Viewer};
+use crate::dbaccesses::curriculum::*;
+use crate::errors::MyError;
+use crate::models::curriculum::*;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/sections",
+    tag = "curriculum",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    responses(
+        (status = 200, description = "The sections of the course with their lessons, in order", body = [SectionWithLessons]),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_curriculum_for_course(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    viewer: Viewer,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    app_state
+        .read_cache
+        .visible_course(&app_state.db, viewer, course_id)
+        .await?;
+    get_curriculum_db(&app_state.db, course_id)
+        .await
+        .map(|sections| HttpResponse::Ok().json(sections))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/courses/{course_id}/sections",
+    tag = "curriculum",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    request_body = SectionCreation,
+    responses(
+        (status = 200, description = "The new section, placed last", body = Section),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 404, description = "Course not found", body = MyErrorResponse),
+        (status = 409, description = "A concurrent change took the same position", body = MyErrorResponse)
+    )
+)]
+pub async fn create_one_section(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    new_section: web::Json<SectionCreation>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    let new_section: SectionCreation = new_section.try_into()?;
+    create_one_section_db(&app_state.db, course_id, new_section, &audit)
+        .await
+        .map(|section| HttpResponse::Ok().json(section))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/courses/{course_id}/sections/order",
+    tag = "curriculum",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    request_body = Reorder,
+    responses(
+        (status = 200, description = "The sections of the course in their new order", body = [Section]),
+        (status = 400, description = "The ids don't match the sections of the course", body = MyErrorResponse)
+    )
+)]
+pub async fn reorder_sections(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    reorder: web::Json<Reorder>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    reorder_sections_db(&app_state.db, course_id, reorder.into(), &audit)
+        .await
+        .map(|sections| HttpResponse::Ok().json(sections))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/courses/{course_id}/sections/{section_id}",
+    tag = "curriculum",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("section_id" = i32, Path, description = "Id of the section")
+    ),
+    request_body = SectionUpdate,
+    responses(
+        (status = 200, description = "The updated section", body = Section),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 404, description = "Section not found", body = MyErrorResponse)
+    )
+)]
+pub async fn update_one_section(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    section_update: web::Json<SectionUpdate>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, section_id) = params.into_inner();
+    let section_update: SectionUpdate = section_update.try_into()?;
+    update_one_section_db(&app_state.db, course_id, section_id, section_update, &audit)
+        .await
+        .map(|section| HttpResponse::Ok().json(section))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/courses/{course_id}/sections/{section_id}",
+    tag = "curriculum",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("section_id" = i32, Path, description = "Id of the section")
+    ),
+    responses(
+        (status = 200, description = "The deleted section, its lessons are deleted too", body = Section),
+        (status = 404, description = "Section not found", body = MyErrorResponse)
+    )
+)]
+pub async fn delete_one_section(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, section_id) = params.into_inner();
+    delete_one_section_db(&app_state.db, course_id, section_id, &audit)
+        .await
+        .map(|section| HttpResponse::Ok().json(section))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/courses/{course_id}/sections/{section_id}/lessons",
+    tag = "curriculum",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("section_id" = i32, Path, description = "Id of the section")
+    ),
+    request_body = LessonCreation,
+    responses(
+        (status = 200, description = "The new lesson, placed last in the section", body = Lesson),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 404, description = "Section not found", body = MyErrorResponse),
+        (status = 409, description = "A concurrent change took the same position", body = MyErrorResponse)
+    )
+)]
+pub async fn create_one_lesson(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    new_lesson: web::Json<LessonCreation>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, section_id) = params.into_inner();
+    let new_lesson: LessonCreation = new_lesson.try_into()?;
+    create_one_lesson_db(&app_state.db, course_id, section_id, new_lesson, &audit)
+        .await
+        .map(|lesson| HttpResponse::Ok().json(lesson))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/courses/{course_id}/sections/{section_id}/lessons/order",
+    tag = "curriculum",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("section_id" = i32, Path, description = "Id of the section")
+    ),
+    request_body = Reorder,
+    responses(
+        (status = 200, description = "The lessons of the section in their new order", body = [Lesson]),
+        (status = 400, description = "The ids don't match the lessons of the section", body = MyErrorResponse),
+        (status = 404, description = "Section not found", body = MyErrorResponse)
+    )
+)]
+pub async fn reorder_lessons(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    reorder: web::Json<Reorder>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, section_id) = params.into_inner();
+    reorder_lessons_db(&app_state.db, course_id, section_id, reorder.into(), &audit)
+        .await
+        .map(|lessons| HttpResponse::Ok().json(lessons))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/courses/{course_id}/sections/{section_id}/lessons/{lesson_id}",
+    tag = "curriculum",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("section_id" = i32, Path, description = "Id of the section"),
+        ("lesson_id" = i32, Path, description = "Id of the lesson")
+    ),
+    request_body = LessonUpdate,
+    responses(
+        (status = 200, description = "The updated lesson", body = Lesson),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 404, description = "Lesson not found", body = MyErrorResponse)
+    )
+)]
+pub async fn update_one_lesson(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32, i32)>,
+    lesson_update: web::Json<LessonUpdate>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, section_id, lesson_id) = params.into_inner();
+    let lesson_update: LessonUpdate = lesson_update.try_into()?;
+    update_one_lesson_db(
+        &app_state.db,
+        course_id,
+        section_id,
+        lesson_id,
+        lesson_update,
+        &audit,
+    )
+    .await
+    .map(|lesson| HttpResponse::Ok().json(lesson))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/courses/{course_id}/sections/{section_id}/lessons/{lesson_id}",
+    tag = "curriculum",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("section_id" = i32, Path, description = "Id of the section"),
+        ("lesson_id" = i32, Path, description = "Id of the lesson")
+    ),
+    responses(
+        (status = 200, description = "The deleted lesson", body = Lesson),
+        (status = 404, description = "Lesson not found", body = MyErrorResponse)
+    )
+)]
+pub async fn delete_one_lesson(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32, i32)>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, section_id, lesson_id) = params.into_inner();
+    delete_one_lesson_db(&app_state.db, course_id, section_id, lesson_id, &audit)
+        .await
+        .map(|lesson| HttpResponse::Ok().json(lesson))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dbaccesses::course::{create_one_course_db, delete_one_course_db};
+    use crate::models::course::CourseCreation;
+    use crate::read_cache::ReadCache;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::env;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+
+    #[actix_rt::test]
+    async fn reorder_sections_should_follow_the_given_ids() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let course = create_one_course_db(
+            &app_state.db,
+            CourseCreation {
+                teacher_id: 1,
+                name: "Curriculum course".into(),
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: None,
+                language: None,
+                level: None,
+            },
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+        let mut ids = Vec::new();
+        for title in ["Basics", "Advanced"] {
+            let section = create_one_section_db(
+                &app_state.db,
+                course.id,
+                SectionCreation {
+                    title: title.into(),
+                },
+                &AuditContext::default(),
+            )
+            .await
+            .unwrap();
+            ids.push(section.id);
+        }
+        create_one_lesson_db(
+            &app_state.db,
+            course.id,
+            ids[0],
+            LessonCreation {
+                title: "Introduction".into(),
+                duration_minutes: 15,
+                is_preview: Some(true),
+            },
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+
+        let params: web::Path<(i32,)> = web::Path::from((course.id,));
+        let reorder = web::Json(Reorder { ids: vec![ids[0]] });
+        let err = reorder_sections(app_state.clone(), params, reorder, AuditContext::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MyError::InvalidInput(_)));
+
+        ids.reverse();
+        let sections = reorder_sections_db(
+            &app_state.db,
+            course.id,
+            Reorder { ids: ids.clone() },
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(sections.iter().map(|s| s.id).collect::<Vec<i32>>(), ids);
+        let curriculum = get_curriculum_db(&app_state.db, course.id).await.unwrap();
+        assert_eq!(curriculum[1].duration_minutes, 15);
+        assert_eq!(curriculum[1].lessons.len(), 1);
+        delete_one_course_db(
+            &app_state.db,
+            course.teacher_id,
+            course.id,
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use crate::dbaccesses::outbox::get_outbox_events_after_db;
+use crate::errors::MyError;
+use crate::events::{catalog_stream, CatalogEvents, MAX_REPLAYED_EVENTS, REPLAY_OVERLAP};
+use crate::models::event::CatalogEventQuery;
+use crate::security::Admin;
+use crate::state::AppState;
+use actix_web::http::header::{ContentEncoding, CACHE_CONTROL, CONTENT_TYPE};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+pub const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/events",
+    tag = "event",
+    params(
+        ("teacher_id" = Option<i32>, Query, description = "Only the changes of this teacher and of their courses"),
+        ("Last-Event-ID" = Option<i64>, Header, description = "Replays the changes made after this event, and the last 100 before it, 1000 at most")
+    ),
+    responses(
+        (status = 200, description = "A stream of the catalog changes, as server-sent events named after their type", content_type = "text/event-stream", body = OutboxEvent),
+        (status = 400, description = "Invalid Last-Event-ID", body = MyErrorResponse),
+        (status = 403, description = "The actor isn't an administrator", body = MyErrorResponse)
+    )
+)]
+pub async fn get_catalog_events(
+    app_state: web::Data<AppState>,
+    catalog_events: web::Data<CatalogEvents>,
+    query: web::Query<CatalogEventQuery>,
+    req: HttpRequest,
+    _admin: Admin,
+) -> Result<HttpResponse, MyError> {
+    let last_event_id = match req.headers().get(LAST_EVENT_ID_HEADER) {
+        None => None,
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse::<i64>().ok())
+                .ok_or_else(|| {
+                    MyError::InvalidInput(format!("Invalid {} header", LAST_EVENT_ID_HEADER))
+                })?,
+        ),
+    };
+    // Subscribes before reading the replay, so that nothing is missed in between.
+    let receiver = catalog_events.subscribe();
+    // The overlap may repeat events the client already got, but also brings those that
+    // committed after the stream was interrupted with a lower id.
+    let replay = match last_event_id {
+        Some(last_event_id) => {
+            get_outbox_events_after_db(
+                &app_state.db,
+                last_event_id - REPLAY_OVERLAP,
+                query.teacher_id,
+                MAX_REPLAYED_EVENTS,
+            )
+            .await?
+        }
+        None => Vec::new(),
+    };
+    let stream = catalog_stream(app_state.clone(), receiver, replay, query.teacher_id);
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/event-stream"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        // Compressed, the events would wait in the encoder instead of being sent.
+        .insert_header(ContentEncoding::Identity)
+        .streaming(stream))
+}
@@ -1,5 +1,6 @@
 use crate::state::AppState;
 use actix_web::{web, HttpResponse};
+use std::sync::atomic::Ordering;
 
 pub async fn health_check_handler(app_state: web::Data<AppState>) -> HttpResponse {
     let health_check_response = &app_state.health_check_response;
@@ -8,3 +9,50 @@ pub async fn health_check_handler(app_state: web::Data<AppState>) -> HttpRespons
     *visit_count += 1;
     HttpResponse::Ok().json(&response)
 }
+
+// Fails as soon as a shutdown starts, so that load balancers stop sending
+// new requests while the in-flight ones are drained.
+pub async fn readiness_check_handler(app_state: web::Data<AppState>) -> HttpResponse {
+    if app_state.shutting_down.load(Ordering::SeqCst) {
+        HttpResponse::ServiceUnavailable().json("Shutting down")
+    } else {
+        HttpResponse::Ok().json("Ready")
+    }
+}
+
+// The metrics of this instance, in the Prometheus text format.
+pub async fn metrics_handler(app_state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(app_state.read_cache.metrics())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::read_cache::ReadCache;
+    use actix_web::http::StatusCode;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::env;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+    #[actix_rt::test]
+    async fn readiness_check_should_fail_when_shutting_down() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let resp = readiness_check_handler(app_state.clone()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        app_state.shutting_down.store(true, Ordering::SeqCst);
+        let resp = readiness_check_handler(app_state).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,25 @@
+use crate::audit::AuditContext;
+use crate::graphql::{catalog_loader, CatalogSchema};
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use async_graphql::http::GraphiQLSource;
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+
+pub async fn graphql(
+    app_state: web::Data<AppState>,
+    schema: web::Data<CatalogSchema>,
+    audit: AuditContext,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let request = request
+        .into_inner()
+        .data(catalog_loader(&app_state.db))
+        .data(audit);
+    schema.execute(request).await.into()
+}
+
+pub async fn graphiql() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(GraphiQLSource::build().endpoint("/graphql").finish())
+}
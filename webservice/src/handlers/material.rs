@@ -0,0 +1,378 @@
+use crate::audit::{AuditContext, Viewer};
+use crate::dbaccesses::course::get_one_course_by_id_db;
+use crate::dbaccesses::material::*;
+use crate::errors::MyError;
+use crate::models::material::CourseMaterialCreation;
+use crate::state::AppState;
+use crate::storage::{content_type, MaterialStore};
+
+use actix_multipart::Multipart;
+use actix_web::body::SizedStream;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::io;
+
+pub struct MaterialLimits {
+    pub max_file_bytes: usize,
+    pub quota_bytes: i64,
+}
+
+impl MaterialLimits {
+    pub fn from_env() -> Self {
+        MaterialLimits {
+            max_file_bytes: env::var("MATERIAL_MAX_FILE_BYTES")
+                .map(|value| value.parse().expect("MATERIAL_MAX_FILE_BYTES is invalid"))
+                .unwrap_or(100 * 1024 * 1024),
+            quota_bytes: env::var("MATERIAL_QUOTA_BYTES")
+                .map(|value| value.parse().expect("MATERIAL_QUOTA_BYTES is invalid"))
+                .unwrap_or(1024 * 1024 * 1024),
+        }
+    }
+}
+
+fn sanitize_file_name(file_name: &str) -> String {
+    let file_name: String = file_name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._- ".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(255)
+        .collect();
+    match file_name.trim_matches('.') {
+        "" => "file".to_owned(),
+        _ => file_name,
+    }
+}
+
+struct UploadedFile {
+    file_name: String,
+    storage_key: String,
+    size_bytes: i64,
+    checksum: String,
+}
+
+fn storage_key(course_id: i32, file_name: &str) -> String {
+    let extension = match file_name.rsplit_once('.') {
+        Some((_, extension)) => format!(".{}", extension.to_ascii_lowercase().replace(' ', "_")),
+        None => String::new(),
+    };
+    format!(
+        "courses/{}/materials/{}{}",
+        course_id,
+        chrono::Utc::now().timestamp_nanos(),
+        extension
+    )
+}
+
+// Streams the `file` field to the store, refusing it as soon as it goes over `max_bytes`.
+// The blob is only kept once the whole field is written.
+async fn write_file_field(
+    mut payload: Multipart,
+    material_store: &MaterialStore,
+    course_id: i32,
+    max_bytes: u64,
+    too_large: &str,
+) -> Result<UploadedFile, MyError> {
+    let multipart_error =
+        |err: actix_multipart::MultipartError| MyError::InvalidInput(err.to_string());
+    while let Some(mut field) = payload.try_next().await.map_err(multipart_error)? {
+        if field.content_disposition().get_name() != Some("file") {
+            continue;
+        }
+        let file_name =
+            sanitize_file_name(field.content_disposition().get_filename().unwrap_or("file"));
+        let storage_key = storage_key(course_id, &file_name);
+        let mut writer = material_store.writer(&storage_key).await?;
+        let mut hasher = Sha256::new();
+        let mut size_bytes = 0;
+        while let Some(chunk) = field.try_next().await.map_err(multipart_error)? {
+            size_bytes += chunk.len() as u64;
+            if size_bytes > max_bytes {
+                return Err(MyError::PayloadTooLarge(too_large.into()));
+            }
+            hasher.update(&chunk);
+            writer.write(chunk).await?;
+        }
+        writer.finish().await?;
+        return Ok(UploadedFile {
+            file_name,
+            storage_key,
+            size_bytes: size_bytes as i64,
+            checksum: format!("{:x}", hasher.finalize()),
+        });
+    }
+    Err(MyError::InvalidInput(
+        "Please provide the material in a `file` field".into(),
+    ))
+}
+
+// Parses a single `bytes` range into the first and last byte offsets. Other units,
+// multiple ranges and malformed headers are ignored, and the whole file is served.
+fn parse_range(range: &str, size: u64) -> Result<Option<(u64, u64)>, MyError> {
+    let unsatisfiable = || MyError::RangeNotSatisfiable("Requested range not satisfiable".into());
+    let spec = match range.strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+    let bounds = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+        (Ok(start), Err(_)) if end.is_empty() => (start, size.saturating_sub(1)),
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        (Ok(_), Ok(_)) => return Err(unsatisfiable()),
+        _ => return Ok(None),
+    };
+    if size == 0 || bounds.0 >= size {
+        return Err(unsatisfiable());
+    }
+    Ok(Some(bounds))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/materials",
+    tag = "material",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    responses(
+        (status = 200, description = "The materials of the course", body = [CourseMaterial]),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_materials_for_course(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    viewer: Viewer,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    app_state
+        .read_cache
+        .visible_course(&app_state.db, viewer, course_id)
+        .await?;
+    get_materials_for_course_db(&app_state.db, course_id)
+        .await
+        .map(|materials| HttpResponse::Ok().json(materials))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/courses/{course_id}/materials",
+    tag = "material",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    request_body(
+        content = String,
+        content_type = "multipart/form-data",
+        description = "The material in a `file` field"
+    ),
+    responses(
+        (status = 200, description = "The uploaded material", body = CourseMaterial),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 404, description = "Course not found", body = MyErrorResponse),
+        (status = 413, description = "The file is too large or the quota is exceeded", body = MyErrorResponse)
+    )
+)]
+pub async fn upload_one_course_material(
+    app_state: web::Data<AppState>,
+    material_store: web::Data<MaterialStore>,
+    limits: web::Data<MaterialLimits>,
+    params: web::Path<(i32,)>,
+    payload: Multipart,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    let course = get_one_course_by_id_db(&app_state.db, course_id).await?;
+    // Checked again when the material is created, but this keeps an upload over the
+    // remaining quota from being written at all.
+    let remaining =
+        limits.quota_bytes - get_material_usage_db(&app_state.db, course.teacher_id).await?;
+    let (max_bytes, too_large) = if remaining < limits.max_file_bytes as i64 {
+        (remaining, "The storage quota of the teacher is exceeded")
+    } else {
+        (limits.max_file_bytes as i64, "The file is too large")
+    };
+    let file = write_file_field(
+        payload,
+        &material_store,
+        course_id,
+        max_bytes.max(0) as u64,
+        too_large,
+    )
+    .await?;
+    let new_material = CourseMaterialCreation {
+        course_id,
+        teacher_id: course.teacher_id,
+        file_name: file.file_name,
+        content_type: content_type(&file.storage_key).to_owned(),
+        size_bytes: file.size_bytes,
+        checksum: file.checksum,
+        storage_key: file.storage_key.clone(),
+    };
+    match create_one_material_db(&app_state.db, new_material, limits.quota_bytes, &audit).await {
+        Ok(material) => Ok(HttpResponse::Ok().json(material)),
+        Err(err) => {
+            material_store.delete(&file.storage_key).await?;
+            Err(err)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/materials/{material_id}",
+    tag = "material",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("material_id" = i32, Path, description = "Id of the material"),
+        ("Range" = Option<String>, Header, description = "A single byte range, e.g. `bytes=0-1023`")
+    ),
+    responses(
+        (status = 200, description = "The whole file"),
+        (status = 206, description = "The requested range of the file"),
+        (status = 404, description = "Course material not found", body = MyErrorResponse),
+        (status = 416, description = "Requested range not satisfiable", body = MyErrorResponse)
+    )
+)]
+pub async fn download_one_course_material(
+    app_state: web::Data<AppState>,
+    material_store: web::Data<MaterialStore>,
+    req: HttpRequest,
+    params: web::Path<(i32, i32)>,
+    viewer: Viewer,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, material_id) = params.into_inner();
+    app_state
+        .read_cache
+        .visible_course(&app_state.db, viewer, course_id)
+        .await?;
+    let material = get_one_material_db(&app_state.db, course_id, material_id).await?;
+    let size = material.size_bytes as u64;
+    let range = match req.headers().get(header::RANGE).map(|range| range.to_str()) {
+        Some(Ok(range)) => parse_range(range, size),
+        _ => Ok(None),
+    };
+    // Streamed from the store rather than read in memory first.
+    let body = |bounds: Option<(u64, u64)>| -> Result<SizedStream<_>, MyError> {
+        let (length, chunks) = match bounds {
+            Some((start, end)) => (
+                end + 1 - start,
+                material_store.stream(&material.storage_key, start, end)?,
+            ),
+            None => (0, stream::empty().boxed()),
+        };
+        let chunks = chunks.map_err(|err| io::Error::other(err.to_string()));
+        Ok(SizedStream::new(length, chunks))
+    };
+    let mut resp = match range {
+        Ok(Some((start, end))) => HttpResponse::PartialContent()
+            .insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, size),
+            ))
+            .content_type(material.content_type.as_str())
+            .body(body(Some((start, end)))?),
+        Ok(None) => HttpResponse::Ok()
+            .content_type(material.content_type.as_str())
+            .body(body(size.checked_sub(1).map(|end| (0, end)))?),
+        Err(err) => {
+            let mut resp = err.error_response();
+            resp.headers_mut().insert(
+                header::CONTENT_RANGE,
+                header::HeaderValue::from_str(&format!("bytes */{}", size))
+                    .map_err(|err| MyError::ActixError(err.to_string()))?,
+            );
+            return Ok(resp);
+        }
+    };
+    let headers = resp.headers_mut();
+    headers.insert(
+        header::ACCEPT_RANGES,
+        header::HeaderValue::from_static("bytes"),
+    );
+    // The ranges are those of the file as it is stored.
+    headers.insert(
+        header::CONTENT_ENCODING,
+        header::HeaderValue::from_static("identity"),
+    );
+    let pairs = [
+        (header::ETAG, format!("\"{}\"", material.checksum)),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", material.file_name),
+        ),
+    ];
+    for (name, value) in pairs {
+        let value = header::HeaderValue::from_str(&value)
+            .map_err(|err| MyError::ActixError(err.to_string()))?;
+        headers.insert(name, value);
+    }
+    Ok(resp)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/courses/{course_id}/materials/{material_id}",
+    tag = "material",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("material_id" = i32, Path, description = "Id of the material")
+    ),
+    responses(
+        (status = 200, description = "The deleted material", body = CourseMaterial),
+        (status = 404, description = "Course material not found", body = MyErrorResponse)
+    )
+)]
+pub async fn delete_one_course_material(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, material_id) = params.into_inner();
+    // The blob is removed by the cleanup worker, as are those of the materials deleted
+    // along with their course.
+    let material = delete_one_material_db(&app_state.db, course_id, material_id, &audit).await?;
+    Ok(HttpResponse::Ok().json(material))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_range_should_resolve_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000).unwrap(), Some((0, 99)));
+        assert_eq!(parse_range("bytes=900-", 1000).unwrap(), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000).unwrap(), Some((900, 999)));
+        assert_eq!(
+            parse_range("bytes=500-5000", 1000).unwrap(),
+            Some((500, 999))
+        );
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000).unwrap(), None);
+        assert_eq!(parse_range("items=0-1", 1000).unwrap(), None);
+        assert!(parse_range("bytes=1000-", 1000).is_err());
+        assert!(parse_range("bytes=9-5", 1000).is_err());
+    }
+
+    #[test]
+    fn sanitize_file_name_should_strip_paths_and_quotes() {
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(
+            sanitize_file_name("C:\\slides\\week \"1\".pptx"),
+            "week _1_.pptx"
+        );
+        assert_eq!(sanitize_file_name(".."), "file");
+    }
+}
@@ -0,0 +1,25 @@
+use crate::errors::MyError;
+use crate::storage::BlobStore;
+
+// Only the teacher pictures are public.
+const PUBLIC_PREFIXES: &[&str] = &["teachers/"];
+use actix_web::http::header;
+use actix_web::{web, HttpResponse};
+
+// Media keys embed a timestamp and are never overwritten, so they can be cached for good.
+pub async fn get_media(
+    blob_store: web::Data<dyn BlobStore>,
+    params: web::Path<(String,)>,
+) -> Result<HttpResponse, MyError> {
+    let (key,) = params.into_inner();
+    if !PUBLIC_PREFIXES.iter().any(|prefix| key.starts_with(prefix)) {
+        return Err(MyError::NotFound("Media not found".into()));
+    }
+    let blob = blob_store.get(&key).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(blob.content_type)
+        .insert_header((header::CACHE_CONTROL, "public, max-age=31536000, immutable"))
+        // Pictures are compressed already.
+        .insert_header(header::ContentEncoding::Identity)
+        .body(blob.data))
+}
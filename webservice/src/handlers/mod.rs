@@ -1,3 +1,13 @@
+pub mod audit;
 pub mod course;
+pub mod curriculum;
+pub mod event;
 pub mod general;
+pub mod graphql;
+pub mod material;
+pub mod media;
+pub mod review;
+pub mod revision;
+pub mod session;
 pub mod teacher;
+pub mod webhook;
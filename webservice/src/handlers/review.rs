@@ -0,0 +1,322 @@
+use crate::audit::{AuditContext, Viewer};
+use crate::dbaccesses::review::*;
+use crate::errors::MyError;
+use crate::idempotency::{idempotent, IdempotencyKey};
+use crate::models::review::*;
+use crate::negotiation::Accepted;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/reviews",
+    tag = "review",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("include_hidden" = Option<bool>, Query, description = "Lists the hidden reviews too, for moderation")
+    ),
+    responses(
+        (status = 200, description = "The reviews of the course", body = [Review]),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_reviews_for_course(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    query: web::Query<ReviewListQuery>,
+    viewer: Viewer,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    app_state
+        .read_cache
+        .visible_course(&app_state.db, viewer, course_id)
+        .await?;
+    get_reviews_for_course_db(&app_state.db, course_id, query.include_hidden)
+        .await
+        .map(|reviews| HttpResponse::Ok().json(reviews))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/courses/{course_id}/reviews",
+    tag = "review",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Replays the response of a former request with the same key")
+    ),
+    request_body = ReviewCreation,
+    responses(
+        (status = 200, description = "The created review", body = Review),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 404, description = "Course not found", body = MyErrorResponse),
+        (status = 409, description = "The student already reviewed the course", body = MyErrorResponse),
+        (status = 422, description = "The key was used for another request", body = MyErrorResponse)
+    )
+)]
+pub async fn create_one_review(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    idempotency_key: IdempotencyKey,
+    new_review: web::Json<ReviewCreation>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    let new_review: ReviewCreation = new_review.try_into()?;
+    // The course is only found in the path, so it has to be part of the operation name.
+    idempotent(
+        &app_state.db,
+        idempotency_key,
+        &Accepted::default(),
+        &format!("create_one_review/{}", course_id),
+        &new_review,
+        create_one_review_db(&app_state.db, course_id, new_review.clone(), &audit),
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/reviews/{review_id}",
+    tag = "review",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("review_id" = i32, Path, description = "Id of the review")
+    ),
+    responses(
+        (status = 200, description = "The review", body = Review),
+        (status = 404, description = "Review not found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_one_review(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, review_id) = params.into_inner();
+    get_one_review_db(&app_state.db, course_id, review_id)
+        .await
+        .map(|review| HttpResponse::Ok().json(review))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/courses/{course_id}/reviews/{review_id}",
+    tag = "review",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("review_id" = i32, Path, description = "Id of the review")
+    ),
+    request_body = ReviewUpdate,
+    responses(
+        (status = 200, description = "The updated review", body = Review),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 404, description = "Review not found", body = MyErrorResponse)
+    )
+)]
+pub async fn update_one_review(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    review_update: web::Json<ReviewUpdate>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, review_id) = params.into_inner();
+    let review_update: ReviewUpdate = review_update.try_into()?;
+    update_one_review_db(&app_state.db, course_id, review_id, review_update, &audit)
+        .await
+        .map(|review| HttpResponse::Ok().json(review))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/courses/{course_id}/reviews/{review_id}/moderation",
+    tag = "review",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("review_id" = i32, Path, description = "Id of the review")
+    ),
+    request_body = ReviewModeration,
+    responses(
+        (status = 200, description = "The moderated review", body = Review),
+        (status = 404, description = "Review not found", body = MyErrorResponse)
+    )
+)]
+pub async fn moderate_one_review(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    moderation: web::Json<ReviewModeration>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, review_id) = params.into_inner();
+    moderate_one_review_db(
+        &app_state.db,
+        course_id,
+        review_id,
+        moderation.into(),
+        &audit,
+    )
+    .await
+    .map(|review| HttpResponse::Ok().json(review))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/courses/{course_id}/reviews/{review_id}",
+    tag = "review",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("review_id" = i32, Path, description = "Id of the review")
+    ),
+    responses(
+        (status = 200, description = "The deleted review", body = Review),
+        (status = 404, description = "Review not found", body = MyErrorResponse)
+    )
+)]
+pub async fn delete_one_review(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, review_id) = params.into_inner();
+    delete_one_review_db(&app_state.db, course_id, review_id, &audit)
+        .await
+        .map(|review| HttpResponse::Ok().json(review))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::AuditContext;
+    use crate::dbaccesses::course::{
+        create_one_course_db, delete_one_course_db, get_one_course_by_id_db,
+    };
+    use crate::models::course::CourseCreation;
+    use crate::read_cache::ReadCache;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::env;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+
+    #[actix_rt::test]
+    async fn review_writes_should_keep_the_course_rating_up_to_date() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let course = create_one_course_db(
+            &app_state.db,
+            CourseCreation {
+                teacher_id: 1,
+                name: "Reviewed course".into(),
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: None,
+                language: None,
+                level: None,
+            },
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(course.rating_average, None);
+        let mut reviews = Vec::new();
+        for (student_id, rating) in [(1, 5), (2, 2)] {
+            let review = ReviewCreation {
+                student_id,
+                rating,
+                text: None,
+            };
+            reviews.push(
+                create_one_review_db(&app_state.db, course.id, review, &AuditContext::default())
+                    .await
+                    .unwrap(),
+            );
+        }
+        let course = get_one_course_by_id_db(&app_state.db, course.id)
+            .await
+            .unwrap();
+        assert_eq!(course.rating_count, 2);
+        assert_eq!(course.rating_average, Some(3.5));
+
+        let params: web::Path<(i32,)> = web::Path::from((course.id,));
+        let review = web::Json(ReviewCreation {
+            student_id: 1,
+            rating: 1,
+            text: None,
+        });
+        let err = create_one_review(
+            app_state.clone(),
+            params,
+            IdempotencyKey::default(),
+            review,
+            AuditContext::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, MyError::Conflict(_)));
+
+        let moderation = ReviewModeration {
+            is_hidden: Some(true),
+            is_flagged: None,
+        };
+        moderate_one_review_db(
+            &app_state.db,
+            course.id,
+            reviews[1].id,
+            moderation,
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+        let course = get_one_course_by_id_db(&app_state.db, course.id)
+            .await
+            .unwrap();
+        assert_eq!(course.rating_count, 1);
+        assert_eq!(course.rating_average, Some(5.0));
+        delete_one_course_db(
+            &app_state.db,
+            course.teacher_id,
+            course.id,
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn create_one_review_should_fail_on_invalid_rating() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let params: web::Path<(i32,)> = web::Path::from((1,));
+        let review = web::Json(ReviewCreation {
+            student_id: 1,
+            rating: 6,
+            text: None,
+        });
+        let err = create_one_review(
+            app_state,
+            params,
+            IdempotencyKey::default(),
+            review,
+            AuditContext::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, MyError::InvalidInput(_)));
+    }
+}
@@ -0,0 +1,339 @@
+use crate::audit::{diff, AuditContext, Viewer};
+use crate::dbaccesses::course::{get_one_course_by_id_db, restore_one_course_db};
+use crate::dbaccesses::revision::*;
+use crate::errors::MyError;
+use crate::models::revision::{CourseSnapshot, RevisionDiffQuery, RevisionRestoreQuery};
+use crate::security::{owner_or_admin, Admin};
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/revisions",
+    tag = "revision",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    responses(
+        (status = 200, description = "The revisions of the course, newest first", body = [CourseRevision]),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_revisions_for_course(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    viewer: Viewer,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    app_state
+        .read_cache
+        .visible_course(&app_state.db, viewer, course_id)
+        .await?;
+    get_revisions_for_course_db(&app_state.db, course_id)
+        .await
+        .map(|revisions| HttpResponse::Ok().json(revisions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/revisions/{revision}",
+    tag = "revision",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("revision" = i32, Path, description = "Number of the revision")
+    ),
+    responses(
+        (status = 200, description = "The revision", body = CourseRevision),
+        (status = 404, description = "Revision not found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_one_revision(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    viewer: Viewer,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, revision) = params.into_inner();
+    app_state
+        .read_cache
+        .visible_course(&app_state.db, viewer, course_id)
+        .await?;
+    get_one_revision_db(&app_state.db, course_id, revision)
+        .await
+        .map(|revision| HttpResponse::Ok().json(revision))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/revisions/diff",
+    tag = "revision",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("from" = i32, Query, description = "Number of the older revision"),
+        ("to" = Option<i32>, Query, description = "Number of the newer revision, the current course if missing")
+    ),
+    responses(
+        (status = 200, description = "The changed fields as `{field: {before, after}}`"),
+        (status = 404, description = "Revision not found", body = MyErrorResponse)
+    )
+)]
+pub async fn diff_revisions(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    query: web::Query<RevisionDiffQuery>,
+    viewer: Viewer,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    let course = app_state
+        .read_cache
+        .visible_course(&app_state.db, viewer, course_id)
+        .await?;
+    let from = get_one_revision_db(&app_state.db, course_id, query.from).await?;
+    let to = if let Some(to) = query.to {
+        get_one_revision_db(&app_state.db, course_id, to)
+            .await?
+            .snapshot
+    } else {
+        serde_json::to_value(course).map_err(|err| MyError::ActixError(err.to_string()))?
+    };
+    diff(Some(&from.snapshot), Some(&to)).map(|changes| HttpResponse::Ok().json(changes))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/courses/{course_id}/revisions/{revision}/restore",
+    tag = "revision",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("revision" = i32, Path, description = "Number of the revision"),
+        ("expected_revision" = i32, Query, description = "Number of the newest revision known to the client"),
+        ("X-Actor" = String, Header, description = "`teacher:<teacher_id>` of the course or one of the administrators")
+    ),
+    responses(
+        (status = 200, description = "The course with the fields of the revision, saved as a new update", body = Course),
+        (status = 403, description = "The actor is neither the teacher of the course nor an administrator", body = MyErrorResponse),
+        (status = 404, description = "Revision not found", body = MyErrorResponse),
+        (status = 409, description = "The course was updated since `expected_revision`", body = MyErrorResponse)
+    )
+)]
+pub async fn restore_one_revision(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    query: web::Query<RevisionRestoreQuery>,
+    audit: AuditContext,
+    viewer: Viewer,
+    admin: Option<Admin>,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, revision) = params.into_inner();
+    let course = get_one_course_by_id_db(&app_state.db, course_id).await?;
+    owner_or_admin(viewer, admin.as_ref(), course.teacher_id)?;
+    let revision = get_one_revision_db(&app_state.db, course_id, revision).await?;
+    let snapshot = CourseSnapshot::try_from(&revision)?;
+    let course = restore_one_course_db(
+        &app_state.db,
+        course_id,
+        snapshot,
+        query.expected_revision,
+        &audit,
+    )
+    .await?;
+    app_state.read_cache.invalidate_course(course_id);
+    Ok(HttpResponse::Ok().json(course))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dbaccesses::course::{
+        create_one_course_db, delete_one_course_db, update_one_course_detail_db,
+    };
+    use crate::models::course::{CourseCreation, CourseUpdate};
+    use crate::read_cache::ReadCache;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::env;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+
+    #[actix_rt::test]
+    async fn restore_should_bring_back_the_revision() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let audit = AuditContext::default();
+        let owner = Viewer::from_actor("teacher:1");
+        let course = create_one_course_db(
+            &app_state.db,
+            CourseCreation {
+                teacher_id: 1,
+                name: "First name".into(),
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: Some(100),
+                language: None,
+                level: None,
+            },
+            &audit,
+        )
+        .await
+        .unwrap();
+        for (name, price) in [("Second name", 200), ("Third name", 300)] {
+            let course_update = CourseUpdate {
+                name: Some(name.into()),
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: Some(price),
+                language: None,
+                level: None,
+            };
+            update_one_course_detail_db(
+                &app_state.db,
+                course.teacher_id,
+                course.id,
+                course_update,
+                &audit,
+            )
+            .await
+            .unwrap();
+        }
+        let revisions = get_revisions_for_course_db(&app_state.db, course.id)
+            .await
+            .unwrap();
+        assert_eq!(
+            revisions.iter().map(|r| r.revision).collect::<Vec<i32>>(),
+            vec![2, 1]
+        );
+        assert_eq!(revisions[1].snapshot["name"], "First name");
+
+        let params: web::Path<(i32,)> = web::Path::from((course.id,));
+        let query = web::Query(RevisionDiffQuery { from: 1, to: None });
+        let resp = diff_revisions(app_state.clone(), params, query, owner)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let params: web::Path<(i32, i32)> = web::Path::from((course.id, 1));
+        let query = web::Query(RevisionRestoreQuery {
+            expected_revision: 2,
+        });
+        let err = restore_one_revision(
+            app_state.clone(),
+            params,
+            query,
+            audit.clone(),
+            Viewer::default(),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, MyError::Forbidden(_)));
+        let params: web::Path<(i32, i32)> = web::Path::from((course.id, 1));
+        let query = web::Query(RevisionRestoreQuery {
+            expected_revision: 2,
+        });
+        restore_one_revision(app_state.clone(), params, query, audit.clone(), owner, None)
+            .await
+            .unwrap();
+        let restored = get_one_course_by_id_db(&app_state.db, course.id)
+            .await
+            .unwrap();
+        assert_eq!(restored.name, "First name");
+        assert_eq!(restored.price, Some(100));
+        // The restore is itself an update, so the state it replaced is kept too.
+        let revisions = get_revisions_for_course_db(&app_state.db, course.id)
+            .await
+            .unwrap();
+        assert_eq!(revisions[0].revision, 3);
+        assert_eq!(revisions[0].snapshot["name"], "Third name");
+
+        // The restore added revision 3, so a client that only knows revision 2 is stale.
+        let params: web::Path<(i32, i32)> = web::Path::from((course.id, 2));
+        let query = web::Query(RevisionRestoreQuery {
+            expected_revision: 2,
+        });
+        let err =
+            restore_one_revision(app_state.clone(), params, query, audit.clone(), owner, None)
+                .await
+                .unwrap_err();
+        assert!(matches!(err, MyError::Conflict(_)));
+        delete_one_course_db(&app_state.db, course.teacher_id, course.id, &audit)
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn restore_should_bring_back_null_fields() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let audit = AuditContext::default();
+        let owner = Viewer::from_actor("teacher:1");
+        let course = create_one_course_db(
+            &app_state.db,
+            CourseCreation {
+                teacher_id: 1,
+                name: "Free course".into(),
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: None,
+                language: None,
+                level: None,
+            },
+            &audit,
+        )
+        .await
+        .unwrap();
+        let course_update = CourseUpdate {
+            name: None,
+            description: Some("Now paid".into()),
+            format: None,
+            structure: None,
+            duration: None,
+            price: Some(200),
+            language: None,
+            level: None,
+        };
+        update_one_course_detail_db(
+            &app_state.db,
+            course.teacher_id,
+            course.id,
+            course_update,
+            &audit,
+        )
+        .await
+        .unwrap();
+
+        let params: web::Path<(i32, i32)> = web::Path::from((course.id, 1));
+        let query = web::Query(RevisionRestoreQuery {
+            expected_revision: 1,
+        });
+        restore_one_revision(app_state.clone(), params, query, audit.clone(), owner, None)
+            .await
+            .unwrap();
+        let restored = get_one_course_by_id_db(&app_state.db, course.id)
+            .await
+            .unwrap();
+        assert_eq!(restored.price, None);
+        assert_eq!(restored.description, None);
+        delete_one_course_db(&app_state.db, course.teacher_id, course.id, &audit)
+            .await
+            .unwrap();
+    }
+}
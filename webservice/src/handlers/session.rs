@@ -0,0 +1,254 @@
+use crate::audit::{AuditContext, Viewer};
+use crate::dbaccesses::course::get_one_course_by_id_db;
+use crate::dbaccesses::session::*;
+use crate::errors::MyError;
+use crate::models::session::*;
+use crate::schedule::{calendar, occurrences, parse_time_zone, Recurrence};
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, Utc};
+
+const DEFAULT_RANGE_DAYS: i64 = 30;
+const MAX_RANGE_DAYS: i64 = 366;
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/sessions",
+    tag = "session",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    responses(
+        (status = 200, description = "The sessions of the course", body = [CourseSession]),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_sessions_for_course(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    viewer: Viewer,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    app_state
+        .read_cache
+        .visible_course(&app_state.db, viewer, course_id)
+        .await?;
+    get_sessions_for_course_db(&app_state.db, course_id)
+        .await
+        .map(|sessions| HttpResponse::Ok().json(sessions))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/courses/{course_id}/sessions",
+    tag = "session",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    request_body = CourseSessionCreation,
+    responses(
+        (status = 200, description = "The created session", body = CourseSession),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn create_one_session(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+    new_session: web::Json<CourseSessionCreation>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    let new_session: NewCourseSession = new_session.try_into()?;
+    get_one_course_by_id_db(&app_state.db, course_id).await?;
+    create_one_session_db(&app_state.db, course_id, new_session, &audit)
+        .await
+        .map(|session| HttpResponse::Ok().json(session))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/courses/{course_id}/sessions/{session_id}",
+    tag = "session",
+    params(
+        ("course_id" = i32, Path, description = "Id of the course"),
+        ("session_id" = i32, Path, description = "Id of the session")
+    ),
+    responses(
+        (status = 200, description = "The deleted session", body = CourseSession),
+        (status = 404, description = "Session not found", body = MyErrorResponse)
+    )
+)]
+pub async fn delete_one_session(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    audit: AuditContext,
+) -> Result<HttpResponse, MyError> {
+    let (course_id, session_id) = params.into_inner();
+    delete_one_session_db(&app_state.db, course_id, session_id, &audit)
+        .await
+        .map(|session| HttpResponse::Ok().json(session))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/sessions",
+    tag = "session",
+    params(
+        ("from" = Option<String>, Query, description = "RFC 3339 start of the range, now by default"),
+        ("to" = Option<String>, Query, description = "RFC 3339 end of the range, 30 days after `from` by default")
+    ),
+    responses(
+        (status = 200, description = "The occurrences of the sessions of the published courses within the range", body = [SessionOccurrence]),
+        (status = 400, description = "Invalid range", body = MyErrorResponse)
+    )
+)]
+pub async fn get_upcoming_sessions(
+    app_state: web::Data<AppState>,
+    query: web::Query<SessionRangeQuery>,
+) -> Result<HttpResponse, MyError> {
+    let from = query.from.unwrap_or_else(Utc::now);
+    let to = query
+        .to
+        .unwrap_or_else(|| from + Duration::days(DEFAULT_RANGE_DAYS));
+    if to <= from || to - from > Duration::days(MAX_RANGE_DAYS) {
+        return Err(MyError::InvalidInput(format!(
+            "The range must end after it starts and span at most {} days",
+            MAX_RANGE_DAYS
+        )));
+    }
+    let sessions = get_sessions_between_db(&app_state.db, from, to).await?;
+    let mut found = Vec::new();
+    for session in sessions {
+        let recurrence = match &session.recurrence {
+            Some(recurrence) => Some(recurrence.parse::<Recurrence>()?),
+            None => None,
+        };
+        let time_zone = parse_time_zone(&session.time_zone)?;
+        for (starts_at, ends_at) in occurrences(
+            session.starts_at,
+            session.ends_at,
+            time_zone,
+            recurrence.as_ref(),
+            from,
+            to,
+        ) {
+            found.push(SessionOccurrence {
+                session_id: session.id,
+                course_id: session.course_id,
+                teacher_id: session.teacher_id,
+                course_name: session.course_name.clone(),
+                starts_at,
+                ends_at,
+                time_zone: session.time_zone.clone(),
+                capacity: session.capacity,
+            });
+        }
+    }
+    found.sort_by_key(|occurrence| (occurrence.starts_at, occurrence.session_id));
+    Ok(HttpResponse::Ok().json(found))
+}
+
+fn calendar_response(name: &str, sessions: &[ScheduledSession]) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(calendar(name, sessions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/courses/{course_id}/sessions.ics",
+    tag = "session",
+    params(("course_id" = i32, Path, description = "Id of the course")),
+    responses(
+        (status = 200, description = "An iCalendar feed of the sessions of the course", content_type = "text/calendar", body = String),
+        (status = 404, description = "Course not found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_course_calendar(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+) -> Result<HttpResponse, MyError> {
+    let (course_id,) = params.into_inner();
+    let course = app_state
+        .read_cache
+        .course(&app_state.db, course_id)
+        .await?;
+    let sessions = get_scheduled_sessions_db(&app_state.db, None, Some(course_id)).await?;
+    Ok(calendar_response(&course.name, &sessions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/teachers/{teacher_id}/sessions.ics",
+    tag = "session",
+    params(("teacher_id" = i32, Path, description = "Id of the teacher")),
+    responses(
+        (status = 200, description = "An iCalendar feed of the sessions of the courses of the teacher", content_type = "text/calendar", body = String)
+    )
+)]
+pub async fn get_teacher_calendar(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32,)>,
+) -> Result<HttpResponse, MyError> {
+    let (teacher_id,) = params.into_inner();
+    let sessions = get_scheduled_sessions_db(&app_state.db, Some(teacher_id), None).await?;
+    Ok(calendar_response(
+        &format!("Courses of teacher {}", teacher_id),
+        &sessions,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::read_cache::ReadCache;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::env;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+
+    #[actix_rt::test]
+    async fn create_one_session_should_fail_on_unknown_time_zone() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let params: web::Path<(i32,)> = web::Path::from((1,));
+        let session = web::Json(CourseSessionCreation {
+            starts_at: chrono::NaiveDate::from_ymd(2022, 6, 1).and_hms(18, 0, 0),
+            ends_at: chrono::NaiveDate::from_ymd(2022, 6, 1).and_hms(19, 0, 0),
+            time_zone: "Mars/Olympus_Mons".into(),
+            recurrence: None,
+            capacity: Some(20),
+        });
+        let err = create_one_session(app_state, params, session, AuditContext::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MyError::InvalidInput(_)));
+    }
+
+    #[actix_rt::test]
+    async fn get_upcoming_sessions_should_reject_long_ranges() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let from = Utc::now();
+        let query = web::Query(SessionRangeQuery {
+            from: Some(from),
+            to: Some(from + Duration::days(MAX_RANGE_DAYS + 1)),
+        });
+        let err = get_upcoming_sessions(app_state, query).await.unwrap_err();
+        assert!(matches!(err, MyError::InvalidInput(_)));
+    }
+}
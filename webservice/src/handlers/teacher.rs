@@ -1,68 +1,269 @@
+use crate::audit::AuditContext;
+use crate::caching::{last_modified, list_version};
 use crate::dbaccesses::teacher::*;
 use crate::errors::MyError;
+use crate::idempotency::{idempotent, IdempotencyKey};
+use crate::images::{process_picture, thumbnail_name, MAX_PICTURE_SIZE, THUMBNAIL_SIZES};
 use crate::models::teacher::{TeacherCreation, TeacherUpdate};
+use crate::negotiation::{Accepted, Body};
 use crate::state::AppState;
+use crate::storage::{media_key, media_url, BlobStore};
 
+use actix_multipart::Multipart;
 use actix_web::{web, HttpResponse};
+use futures_util::TryStreamExt;
 
-pub async fn get_all_teachers(app_state: web::Data<AppState>) -> Result<HttpResponse, MyError> {
-    get_all_teachers_db(&app_state.db)
-        .await
-        .map(|teachers| HttpResponse::Ok().json(teachers))
+#[utoipa::path(
+    get,
+    path = "/api/v1/teachers/",
+    tag = "teacher",
+    responses(
+        (status = 200, description = "All the teachers", body = [Teacher]),
+        (status = 404, description = "No teachers found", body = MyErrorResponse)
+    )
+)]
+pub async fn get_all_teachers(
+    app_state: web::Data<AppState>,
+    accepted: Accepted,
+) -> Result<HttpResponse, MyError> {
+    let version = get_teachers_version_db(&app_state.db).await?;
+    let teachers = stream_all_teachers_db(&app_state.db).await?;
+    Ok(list_version(accepted.stream(teachers).await?, version))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/teachers/{teacher_id}",
+    tag = "teacher",
+    params(("teacher_id" = i32, Path, description = "Id of the teacher")),
+    responses(
+        (status = 200, description = "The teacher", body = Teacher),
+        (status = 404, description = "Teacher ID not found", body = MyErrorResponse)
+    )
+)]
 pub async fn get_one_teacher_detail(
     app_state: web::Data<AppState>,
     params: web::Path<(i32,)>,
+    accepted: Accepted,
 ) -> Result<HttpResponse, MyError> {
     let (teacher_id,) = params.into_inner();
-    get_one_teacher_detail_db(&app_state.db, teacher_id)
-        .await
-        .map(|teacher| HttpResponse::Ok().json(teacher))
+    let teacher = app_state
+        .read_cache
+        .teacher(&app_state.db, teacher_id)
+        .await?;
+    Ok(last_modified(
+        accepted.item(&teacher)?,
+        Some(teacher.updated_at),
+    ))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/teachers/",
+    tag = "teacher",
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Replays the response of a former request with the same key")
+    ),
+    request_body = TeacherCreation,
+    responses(
+        (status = 200, description = "The created teacher", body = Teacher),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 409, description = "A request with the same key is in progress", body = MyErrorResponse),
+        (status = 422, description = "The key was used for another request", body = MyErrorResponse)
+    )
+)]
 pub async fn create_one_teacher(
     app_state: web::Data<AppState>,
-    new_teacher: web::Json<TeacherCreation>,
+    idempotency_key: IdempotencyKey,
+    accepted: Accepted,
+    audit: AuditContext,
+    new_teacher: Body<TeacherCreation>,
 ) -> Result<HttpResponse, MyError> {
-    create_one_teacher_db(&app_state.db, TeacherCreation::from(new_teacher))
-        .await
-        .map(|teacher| HttpResponse::Ok().json(teacher))
+    let new_teacher = TeacherCreation::from(new_teacher.into_json());
+    idempotent(
+        &app_state.db,
+        idempotency_key,
+        &accepted,
+        "create_one_teacher",
+        &new_teacher,
+        create_one_teacher_db(&app_state.db, new_teacher.clone(), &audit),
+    )
+    .await
 }
 
+#[utoipa::path(
+    put,
+    path = "/api/v1/teachers/{teacher_id}",
+    tag = "teacher",
+    params(("teacher_id" = i32, Path, description = "Id of the teacher")),
+    request_body = TeacherUpdate,
+    responses(
+        (status = 200, description = "The updated teacher", body = Teacher),
+        (status = 400, description = "Invalid input", body = MyErrorResponse),
+        (status = 404, description = "Teacher ID not found", body = MyErrorResponse)
+    )
+)]
 pub async fn update_one_teacher_detail(
     app_state: web::Data<AppState>,
     params: web::Path<(i32,)>,
-    teacher_update: web::Json<TeacherUpdate>,
+    accepted: Accepted,
+    audit: AuditContext,
+    teacher_update: Body<TeacherUpdate>,
 ) -> Result<HttpResponse, MyError> {
     let (teacher_id,) = params.into_inner();
-    update_one_teacher_detail_db(
+    let teacher = update_one_teacher_detail_db(
         &app_state.db,
         teacher_id,
-        TeacherUpdate::from(teacher_update),
+        TeacherUpdate::from(teacher_update.into_json()),
+        &audit,
     )
-    .await
-    .map(|teacher| HttpResponse::Ok().json(teacher))
+    .await?;
+    app_state.read_cache.invalidate_teacher(teacher_id);
+    accepted.item(&teacher)
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/v1/teachers/{teacher_id}",
+    tag = "teacher",
+    params(("teacher_id" = i32, Path, description = "Id of the teacher")),
+    responses(
+        (status = 200, description = "The number of deleted records", body = String),
+        (status = 500, description = "Unable to delete the teacher", body = MyErrorResponse)
+    )
+)]
 pub async fn delete_one_teacher(
     app_state: web::Data<AppState>,
     params: web::Path<i32>,
+    audit: AuditContext,
 ) -> Result<HttpResponse, MyError> {
     let teacher_id = params.into_inner();
-    delete_one_teacher_db(&app_state.db, teacher_id)
+    let message = delete_one_teacher_db(&app_state.db, teacher_id, &audit).await?;
+    app_state.read_cache.invalidate_teacher(teacher_id);
+    Ok(HttpResponse::Ok().json(message))
+}
+
+async fn read_picture_field(mut payload: Multipart) -> Result<Vec<u8>, MyError> {
+    let multipart_error =
+        |err: actix_multipart::MultipartError| MyError::InvalidInput(err.to_string());
+    while let Some(mut field) = payload.try_next().await.map_err(multipart_error)? {
+        if field.content_disposition().get_name() != Some("picture") {
+            continue;
+        }
+        let mut data = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(multipart_error)? {
+            if data.len() + chunk.len() > MAX_PICTURE_SIZE {
+                return Err(MyError::PayloadTooLarge("The picture is too large".into()));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        return Ok(data);
+    }
+    Err(MyError::InvalidInput(
+        "Please provide the picture in a `picture` field".into(),
+    ))
+}
+
+// The keys of a former picture of the teacher, none when the URL points anywhere else
+// than the pictures of that teacher.
+fn picture_keys(teacher_id: i32, url_picture: &str) -> Vec<String> {
+    let prefix = format!("teachers/{}/", teacher_id);
+    let key = media_key(url_picture).filter(|key| match key.strip_prefix(&prefix) {
+        Some(rest) => {
+            let segments: Vec<&str> = rest.split('/').collect();
+            segments.len() == 2
+                && segments
+                    .iter()
+                    .all(|segment| !segment.is_empty() && *segment != "..")
+        }
+        None => false,
+    });
+    match key.and_then(|key| key.rsplit_once('/')) {
+        Some((dir, original)) => std::iter::once(original.to_owned())
+            .chain(THUMBNAIL_SIZES.iter().map(|size| thumbnail_name(*size)))
+            .map(|name| format!("{}/{}", dir, name))
+            .collect(),
+        None => vec![],
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/teachers/{teacher_id}/picture",
+    tag = "teacher",
+    params(("teacher_id" = i32, Path, description = "Id of the teacher")),
+    request_body(
+        content = String,
+        content_type = "multipart/form-data",
+        description = "A PNG, JPEG, GIF or WebP image in a `picture` field"
+    ),
+    responses(
+        (status = 200, description = "The teacher with the URL of the new picture", body = Teacher),
+        (status = 400, description = "Invalid picture", body = MyErrorResponse),
+        (status = 404, description = "Teacher ID not found", body = MyErrorResponse),
+        (status = 413, description = "The picture is too large", body = MyErrorResponse)
+    )
+)]
+pub async fn upload_one_teacher_picture(
+    app_state: web::Data<AppState>,
+    blob_store: web::Data<dyn BlobStore>,
+    params: web::Path<(i32,)>,
+    audit: AuditContext,
+    payload: Multipart,
+) -> Result<HttpResponse, MyError> {
+    let (teacher_id,) = params.into_inner();
+    let teacher = get_one_teacher_detail_db(&app_state.db, teacher_id).await?;
+    let data = read_picture_field(payload).await?;
+    let (data, picture) = web::block(move || process_picture(&data).map(|picture| (data, picture)))
         .await
-        .map(|teacher| HttpResponse::Ok().json(teacher))
+        .map_err(|err| MyError::ActixError(err.to_string()))??;
+    let dir = format!(
+        "teachers/{}/{}",
+        teacher_id,
+        chrono::Utc::now().timestamp_nanos()
+    );
+    let original_key = format!("{}/original.{}", dir, picture.extension);
+    blob_store.put(&original_key, data).await?;
+    for thumbnail in picture.thumbnails {
+        blob_store
+            .put(
+                &format!("{}/{}", dir, thumbnail_name(thumbnail.size)),
+                thumbnail.data,
+            )
+            .await?;
+    }
+    let updated_teacher =
+        update_one_teacher_picture_db(&app_state.db, teacher_id, media_url(&original_key), &audit)
+            .await?;
+    app_state.read_cache.invalidate_teacher(teacher_id);
+    for key in picture_keys(teacher_id, &teacher.url_picture) {
+        if let Err(err) = blob_store.delete(&key).await {
+            tracing::warn!("Unable to delete the former picture {}: {:?}", key, err);
+        }
+    }
+    Ok(HttpResponse::Ok().json(updated_teacher))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::read_cache::ReadCache;
     use actix_web::http::StatusCode;
     use dotenv::dotenv;
     use sqlx::postgres::PgPoolOptions;
     use std::env;
+    use std::sync::atomic::AtomicBool;
     use std::sync::Mutex;
+    #[test]
+    fn picture_keys_should_stay_under_the_teacher() {
+        let keys = picture_keys(1, &media_url("teachers/1/42/original.png"));
+        assert_eq!(keys[0], "teachers/1/42/original.png");
+        assert_eq!(keys.len(), 1 + THUMBNAIL_SIZES.len());
+        assert!(picture_keys(1, &media_url("teachers/2/42/original.png")).is_empty());
+        assert!(picture_keys(1, &media_url("teachers/1/../../x/original.png")).is_empty());
+        assert!(picture_keys(1, &media_url("materials/1/original.png")).is_empty());
+        assert!(picture_keys(1, "http://yanglyu.pro").is_empty());
+    }
     #[actix_rt::test]
     async fn get_all_teachers_should_succeed() {
         dotenv().ok();
@@ -71,9 +272,13 @@ mod test {
         let app_state: web::Data<AppState> = web::Data::new(AppState {
             health_check_response: "".to_owned(),
             visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
             db: pool_db,
         });
-        let resp = get_all_teachers(app_state).await.unwrap();
+        let resp = get_all_teachers(app_state, Accepted::default())
+            .await
+            .unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
     }
     #[actix_rt::test]
@@ -84,10 +289,14 @@ mod test {
         let app_state: web::Data<AppState> = web::Data::new(AppState {
             health_check_response: "".to_owned(),
             visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
             db: pool_db,
         });
         let params: web::Path<(i32,)> = web::Path::from((1,));
-        let resp = get_one_teacher_detail(app_state, params).await.unwrap();
+        let resp = get_one_teacher_detail(app_state, params, Accepted::default())
+            .await
+            .unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
     }
     #[actix_rt::test]
@@ -98,15 +307,24 @@ mod test {
         let app_state: web::Data<AppState> = web::Data::new(AppState {
             health_check_response: "".to_owned(),
             visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
             db: pool_db,
         });
         let new_teacher = TeacherCreation {
             name: "Third Teacher".into(),
-            url_picture: "http://yanglyu.pro".into(),
             profile: "A teacher".into(),
         };
-        let params: web::Json<TeacherCreation> = web::Json(new_teacher);
-        let resp = create_one_teacher(app_state, params).await.unwrap();
+        let params: Body<TeacherCreation> = Body(new_teacher);
+        let resp = create_one_teacher(
+            app_state,
+            IdempotencyKey::default(),
+            Accepted::default(),
+            AuditContext::default(),
+            params,
+        )
+        .await
+        .unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
     }
     #[actix_rt::test]
@@ -117,10 +335,67 @@ mod test {
         let app_state: web::Data<AppState> = web::Data::new(AppState {
             health_check_response: "".to_owned(),
             visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
             db: pool_db,
         });
         let params: web::Path<i32> = web::Path::from(1);
-        let resp = delete_one_teacher(app_state, params).await.unwrap();
+        let resp = delete_one_teacher(app_state, params, AuditContext::default())
+            .await
+            .unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
     }
+    #[actix_rt::test]
+    async fn create_one_teacher_should_replay_on_same_idempotency_key() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool_db = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state: web::Data<AppState> = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool_db,
+        });
+        let key = format!("test-{}", chrono::Utc::now().timestamp_nanos());
+        let new_teacher = TeacherCreation {
+            name: "Idempotent Teacher".into(),
+            profile: "A teacher".into(),
+        };
+        let resp = create_one_teacher(
+            app_state.clone(),
+            IdempotencyKey::new(&key),
+            Accepted::default(),
+            AuditContext::default(),
+            Body(new_teacher.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = create_one_teacher(
+            app_state.clone(),
+            IdempotencyKey::new(&key),
+            Accepted::default(),
+            AuditContext::default(),
+            Body(new_teacher.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("Idempotent-Replayed").unwrap(), "true");
+        let other_teacher = TeacherCreation {
+            name: "Another Teacher".into(),
+            ..new_teacher
+        };
+        let err = create_one_teacher(
+            app_state,
+            IdempotencyKey::new(&key),
+            Accepted::default(),
+            AuditContext::default(),
+            Body(other_teacher),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, MyError::UnprocessableEntity(_)));
+    }
 }
@@ -0,0 +1,113 @@
+use crate::dbaccesses::outbox::*;
+use crate::errors::MyError;
+use crate::models::webhook::WebhookCreation;
+use crate::outbox::{resolve_target, OutboxConfig};
+use crate::security::Admin;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/webhooks",
+    tag = "webhook",
+    responses(
+        (status = 200, description = "The registered webhooks, without their secrets", body = [Webhook]),
+        (status = 403, description = "The actor is not an administrator", body = MyErrorResponse)
+    )
+)]
+pub async fn get_all_webhooks(
+    app_state: web::Data<AppState>,
+    _admin: Admin,
+) -> Result<HttpResponse, MyError> {
+    get_all_webhooks_db(&app_state.db)
+        .await
+        .map(|webhooks| HttpResponse::Ok().json(webhooks))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/webhooks",
+    tag = "webhook",
+    request_body = WebhookCreation,
+    responses(
+        (status = 200, description = "The new webhook, which receives the events written from now on", body = Webhook),
+        (status = 400, description = "Invalid input, or a url that does not resolve to public addresses", body = MyErrorResponse),
+        (status = 403, description = "The actor is not an administrator", body = MyErrorResponse)
+    )
+)]
+pub async fn create_one_webhook(
+    app_state: web::Data<AppState>,
+    _admin: Admin,
+    outbox_config: web::Data<OutboxConfig>,
+    new_webhook: web::Json<WebhookCreation>,
+) -> Result<HttpResponse, MyError> {
+    let new_webhook: WebhookCreation = new_webhook.try_into()?;
+    resolve_target(&new_webhook.url, outbox_config.allow_private_targets)
+        .await
+        .map_err(MyError::InvalidInput)?;
+    create_one_webhook_db(&app_state.db, new_webhook)
+        .await
+        .map(|webhook| HttpResponse::Ok().json(webhook))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/webhooks/{webhook_id}",
+    tag = "webhook",
+    params(("webhook_id" = i32, Path, description = "Id of the webhook")),
+    responses(
+        (status = 200, description = "The deleted webhook, its pending deliveries are dropped", body = Webhook),
+        (status = 403, description = "The actor is not an administrator", body = MyErrorResponse),
+        (status = 404, description = "Webhook not found", body = MyErrorResponse)
+    )
+)]
+pub async fn delete_one_webhook(
+    app_state: web::Data<AppState>,
+    _admin: Admin,
+    params: web::Path<(i32,)>,
+) -> Result<HttpResponse, MyError> {
+    let (webhook_id,) = params.into_inner();
+    delete_one_webhook_db(&app_state.db, webhook_id)
+        .await
+        .map(|webhook| HttpResponse::Ok().json(webhook))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/webhook-deliveries/dead",
+    tag = "webhook",
+    responses(
+        (status = 200, description = "The last 1000 deliveries that ran out of attempts, newest first", body = [WebhookDelivery]),
+        (status = 403, description = "The actor is not an administrator", body = MyErrorResponse)
+    )
+)]
+pub async fn get_dead_deliveries(
+    app_state: web::Data<AppState>,
+    _admin: Admin,
+) -> Result<HttpResponse, MyError> {
+    get_dead_deliveries_db(&app_state.db)
+        .await
+        .map(|deliveries| HttpResponse::Ok().json(deliveries))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/webhook-deliveries/{delivery_id}/retry",
+    tag = "webhook",
+    params(("delivery_id" = i64, Path, description = "Id of the dead delivery")),
+    responses(
+        (status = 200, description = "The delivery, queued again with a new series of attempts", body = WebhookDelivery),
+        (status = 403, description = "The actor is not an administrator", body = MyErrorResponse),
+        (status = 404, description = "Dead delivery not found", body = MyErrorResponse)
+    )
+)]
+pub async fn retry_one_delivery(
+    app_state: web::Data<AppState>,
+    _admin: Admin,
+    params: web::Path<(i64,)>,
+) -> Result<HttpResponse, MyError> {
+    let (delivery_id,) = params.into_inner();
+    retry_one_delivery_db(&app_state.db, delivery_id)
+        .await
+        .map(|delivery| HttpResponse::Ok().json(delivery))
+}
@@ -0,0 +1,209 @@
+use crate::audit::{request_actor, AuditContext};
+use crate::dbaccesses::idempotency::*;
+use crate::errors::MyError;
+use crate::negotiation::{Accepted, Format};
+use actix_web::dev::Payload;
+use actix_web::http::header::{self, HeaderName};
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpRequest, HttpResponse};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPool;
+use std::future::{ready, Future, Ready};
+use std::time::Duration;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const MAX_KEY_LENGTH: usize = 255;
+// Well within the 30 seconds after which a key whose lease wasn't renewed is taken over.
+const LEASE_RENEWAL_INTERVAL: Duration = Duration::from_secs(10);
+
+// The optional `Idempotency-Key` header of a request, along with the client it belongs to:
+// the same key sent by two actors stands for two requests.
+#[derive(Clone, Debug)]
+pub struct IdempotencyKey {
+    key: Option<String>,
+    scope: String,
+}
+
+impl Default for IdempotencyKey {
+    fn default() -> Self {
+        IdempotencyKey {
+            key: None,
+            scope: client_scope(&AuditContext::default().actor),
+        }
+    }
+}
+
+#[cfg(test)]
+impl IdempotencyKey {
+    pub fn new(key: &str) -> Self {
+        IdempotencyKey {
+            key: Some(key.to_owned()),
+            ..IdempotencyKey::default()
+        }
+    }
+}
+
+// Anonymous clients share a scope, where their random keys tell them apart: scoped by IP,
+// a retry coming from another address would run the request again.
+fn client_scope(actor: &str) -> String {
+    if actor == AuditContext::default().actor {
+        actor.to_owned()
+    } else {
+        format!("actor:{}", actor)
+    }
+}
+
+impl FromRequest for IdempotencyKey {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+            None => Ok(None),
+            Some(value) => value
+                .to_str()
+                .ok()
+                .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LENGTH)
+                .map(|key| Some(key.to_owned()))
+                .ok_or_else(|| {
+                    MyError::InvalidInput(format!(
+                        "{} must hold between 1 and {} characters",
+                        IDEMPOTENCY_KEY_HEADER, MAX_KEY_LENGTH
+                    ))
+                }),
+        };
+        let key = key.and_then(|key| {
+            let actor = request_actor(req)?;
+            Ok(IdempotencyKey {
+                key,
+                scope: client_scope(&actor),
+            })
+        });
+        ready(key)
+    }
+}
+
+async fn renew_lease(pool: &PgPool, scope: &str, key: &str, lease: NaiveDateTime) {
+    loop {
+        actix_rt::time::sleep(LEASE_RENEWAL_INTERVAL).await;
+        if let Err(err) = renew_idempotency_key_db(pool, scope, key, lease).await {
+            tracing::warn!("Unable to renew the idempotency lease: {:?}", err);
+        }
+    }
+}
+
+fn hash_request(operation: &str, request: &impl Serialize) -> Result<String, MyError> {
+    let body = serde_json::to_vec(request).map_err(|err| MyError::ActixError(err.to_string()))?;
+    let mut hasher = Sha256::new();
+    hasher.update(operation.as_bytes());
+    hasher.update(&body);
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Runs `operation` once per idempotency key: a repeated request gets the stored response back,
+// while reusing the key for another operation or another body is refused with a 422.
+// Failed operations release the key so that the client can retry them. The lease on the key
+// is renewed while the operation runs, so a concurrent retry gets a 409 however long it takes,
+// and only a key whose request was abandoned, e.g. with a crashed instance, is taken over.
+// Responses are stored as JSON, and converted to the accepted format when replayed.
+pub async fn idempotent<T, F>(
+    pool: &PgPool,
+    key: IdempotencyKey,
+    accepted: &Accepted,
+    operation_name: &str,
+    request: &impl Serialize,
+    operation: F,
+) -> Result<HttpResponse, MyError>
+where
+    T: Serialize,
+    F: Future<Output = Result<T, MyError>>,
+{
+    // Refused before running the operation rather than after.
+    let format = accepted.item_format()?;
+    let IdempotencyKey { key, scope } = key;
+    let key = match key {
+        Some(key) => key,
+        None => return accepted.item(&operation.await?),
+    };
+    let request_hash = hash_request(operation_name, request)?;
+    let lease = reserve_idempotency_key_db(pool, &scope, &key, &request_hash).await?;
+    let lease = match lease {
+        Some(lease) => lease,
+        None => {
+            let record = get_idempotency_record_db(pool, &scope, &key).await?;
+            if record.request_hash != request_hash {
+                return Err(MyError::UnprocessableEntity(format!(
+                    "{} was already used for another request",
+                    IDEMPOTENCY_KEY_HEADER
+                )));
+            }
+            return match (record.status_code, record.response_body) {
+                (Some(status_code), Some(response_body)) => {
+                    let status = StatusCode::from_u16(status_code as u16)
+                        .map_err(|err| MyError::ActixError(err.to_string()))?;
+                    let mut resp = if format == Format::Json {
+                        HttpResponse::Ok()
+                            .content_type("application/json")
+                            .insert_header((header::VARY, "Accept"))
+                            .body(response_body)
+                    } else {
+                        let resp: serde_json::Value = serde_json::from_slice(&response_body)
+                            .map_err(|err| MyError::ActixError(err.to_string()))?;
+                        accepted.item(&resp)?
+                    };
+                    *resp.status_mut() = status;
+                    resp.headers_mut().insert(
+                        HeaderName::from_static("idempotent-replayed"),
+                        header::HeaderValue::from_static("true"),
+                    );
+                    Ok(resp)
+                }
+                _ => Err(MyError::Conflict(
+                    "A request with the same idempotency key is still in progress".into(),
+                )),
+            };
+        }
+    };
+    let result = tokio::select! {
+        result = operation => result,
+        _ = renew_lease(pool, &scope, &key, lease) => unreachable!(),
+    };
+    match result {
+        Ok(resp) => {
+            let response_body =
+                serde_json::to_vec(&resp).map_err(|err| MyError::ActixError(err.to_string()))?;
+            // The operation is committed by now: failing the request would only make the
+            // client retry it, while the key gets taken over once its lease runs out.
+            if let Err(err) = save_idempotency_response_db(
+                pool,
+                &scope,
+                &key,
+                lease,
+                StatusCode::OK.as_u16() as i16,
+                &response_body,
+            )
+            .await
+            {
+                tracing::warn!("Unable to save the idempotent response: {:?}", err);
+            }
+            accepted.item(&resp)
+        }
+        Err(err) => {
+            release_idempotency_key_db(pool, &scope, &key, lease).await?;
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys_should_be_scoped_per_client() {
+        assert_eq!(client_scope("alice"), "actor:alice");
+        assert_eq!(client_scope("anonymous"), "anonymous");
+    }
+}
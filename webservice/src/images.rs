@@ -0,0 +1,120 @@
+use crate::errors::MyError;
+use image::io::{Limits, Reader};
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use std::io::Cursor;
+
+pub const MAX_PICTURE_SIZE: usize = 5 * 1024 * 1024;
+// A small compressed file can still claim huge dimensions, so those are checked
+// before any pixel is decoded.
+pub const MAX_PICTURE_DIMENSION: u32 = 8192;
+const MAX_DECODING_ALLOCATION: u64 = 256 * 1024 * 1024;
+pub const THUMBNAIL_SIZES: [u32; 2] = [64, 256];
+
+pub struct Thumbnail {
+    pub size: u32,
+    pub data: Vec<u8>,
+}
+
+pub struct ProcessedPicture {
+    pub extension: &'static str,
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+pub fn thumbnail_name(size: u32) -> String {
+    format!("thumb_{}.jpg", size)
+}
+
+// The format is sniffed from the content, whatever the client claims it to be.
+fn extension(format: ImageFormat) -> Result<&'static str, MyError> {
+    match format {
+        ImageFormat::Png => Ok("png"),
+        ImageFormat::Jpeg => Ok("jpg"),
+        ImageFormat::Gif => Ok("gif"),
+        ImageFormat::WebP => Ok("webp"),
+        _ => Err(MyError::InvalidInput(
+            "Pictures must be PNG, JPEG, GIF or WebP images".into(),
+        )),
+    }
+}
+
+fn encode_thumbnail(picture: &DynamicImage, size: u32) -> Result<Thumbnail, MyError> {
+    let mut data = Vec::new();
+    picture
+        .thumbnail(size, size)
+        .write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Jpeg(85))
+        .map_err(|err| MyError::ActixError(err.to_string()))?;
+    Ok(Thumbnail { size, data })
+}
+
+// Decoding is CPU bound, call this from `web::block`.
+pub fn process_picture(data: &[u8]) -> Result<ProcessedPicture, MyError> {
+    if data.len() > MAX_PICTURE_SIZE {
+        return Err(MyError::PayloadTooLarge("The picture is too large".into()));
+    }
+    let format = image::guess_format(data)
+        .map_err(|_err| MyError::InvalidInput("The picture is not an image".into()))?;
+    let extension = extension(format)?;
+    let undecodable =
+        |_err: image::ImageError| MyError::InvalidInput("The picture can't be decoded".into());
+    let (width, height) = Reader::with_format(Cursor::new(data), format)
+        .into_dimensions()
+        .map_err(undecodable)?;
+    if width > MAX_PICTURE_DIMENSION || height > MAX_PICTURE_DIMENSION {
+        return Err(MyError::InvalidInput(format!(
+            "Pictures must be at most {} pixels wide and high",
+            MAX_PICTURE_DIMENSION
+        )));
+    }
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_PICTURE_DIMENSION);
+    limits.max_image_height = Some(MAX_PICTURE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODING_ALLOCATION);
+    let mut reader = Reader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+    let picture = reader.decode().map_err(undecodable)?;
+    let thumbnails = THUMBNAIL_SIZES
+        .iter()
+        .map(|size| encode_thumbnail(&picture, *size))
+        .collect::<Result<Vec<Thumbnail>, MyError>>()?;
+    Ok(ProcessedPicture {
+        extension,
+        thumbnails,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{GenericImageView, ImageBuffer, Rgb};
+
+    #[test]
+    fn process_picture_should_resize_images() {
+        let picture =
+            DynamicImage::ImageRgb8(ImageBuffer::from_pixel(600, 300, Rgb([0, 128, 255])));
+        let mut data = Vec::new();
+        picture
+            .write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png)
+            .unwrap();
+        let processed = process_picture(&data).unwrap();
+        assert_eq!(processed.extension, "png");
+        assert_eq!(processed.thumbnails.len(), THUMBNAIL_SIZES.len());
+        let thumbnail = image::load_from_memory(&processed.thumbnails[1].data).unwrap();
+        assert_eq!(thumbnail.dimensions(), (256, 128));
+    }
+
+    #[test]
+    fn process_picture_should_reject_huge_dimensions() {
+        let picture = DynamicImage::ImageLuma8(ImageBuffer::new(MAX_PICTURE_DIMENSION + 1, 1));
+        let mut data = Vec::new();
+        picture
+            .write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png)
+            .unwrap();
+        assert!(data.len() < MAX_PICTURE_SIZE);
+        assert!(process_picture(&data).is_err());
+    }
+
+    #[test]
+    fn process_picture_should_reject_other_files() {
+        assert!(process_picture(b"%PDF-1.4 not a picture").is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    pub entity_type: String,
+    pub entity_id: i32,
+    // The changed fields, as `{"field": {"before": ..., "after": ...}}`.
+    #[schema(value_type = Object)]
+    pub diff: Value,
+    pub request_id: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditEventCreation {
+    pub action: &'static str,
+    pub entity_type: &'static str,
+    pub entity_id: i32,
+    pub diff: Value,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AuditEventQuery {
+    pub entity_type: Option<String>,
+    pub entity_id: Option<i32>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
@@ -1,11 +1,14 @@
 use actix_web::web;
-use chrono::NaiveDateTime;
+use async_graphql::{Enum, InputObject, SimpleObject};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use utoipa::ToSchema;
 
 use crate::errors::MyError;
 
-#[derive(sqlx::FromRow, Clone, Debug, Serialize)]
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema, SimpleObject)]
+#[graphql(complex)]
 pub struct Course {
     pub teacher_id: i32,
     pub id: i32,
@@ -14,31 +17,115 @@ pub struct Course {
     pub description: Option<String>,
     pub format: Option<String>,
     pub structure: Option<String>,
+    // Deprecated: the free-form duration of the v1 API, superseded by `duration_minutes`.
+    #[graphql(deprecation = "Use durationMinutes")]
     pub duration: Option<String>,
     pub price: Option<i32>,
     pub language: Option<String>,
     pub level: Option<String>,
+    // The sum of the durations of the lessons of the course.
+    pub duration_minutes: i64,
+    // Computed over the reviews that aren't hidden, `None` until the first one.
+    pub rating_average: Option<f64>,
+    pub rating_count: i32,
+    // Only published courses are public, the others are seen by their teacher only.
+    pub status: CourseStatus,
+    // Bumped by the changes of the course, of its lessons and of its rating.
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, ToSchema, sqlx::Type, Enum)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum CourseStatus {
+    Draft,
+    InReview,
+    Published,
+    Archived,
+}
+
+impl CourseStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CourseStatus::Draft => "draft",
+            CourseStatus::InReview => "in_review",
+            CourseStatus::Published => "published",
+            CourseStatus::Archived => "archived",
+        }
+    }
+
+    // A course in review goes back to draft when it is rejected.
+    pub fn can_move_to(&self, to: CourseStatus) -> bool {
+        matches!(
+            (self, to),
+            (CourseStatus::Draft, CourseStatus::InReview)
+                | (CourseStatus::InReview, CourseStatus::Draft)
+                | (CourseStatus::InReview, CourseStatus::Published)
+                | (CourseStatus::Published, CourseStatus::Archived)
+        )
+    }
+}
+
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct CourseTransition {
+    pub id: i32,
+    pub course_id: i32,
+    pub from_status: String,
+    pub to_status: String,
+    pub actor: String,
+    pub time: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct CourseTransitionCreation {
+    pub to_status: CourseStatus,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CourseSort {
+    #[default]
+    Id,
+    Rating,
 }
 
 #[derive(Clone, Debug, Deserialize)]
+pub struct CourseListQuery {
+    #[serde(default)]
+    pub sort: CourseSort,
+}
+
+// Filters of the published courses listed by GraphQL.
+#[derive(Clone, Debug, Default, InputObject)]
+pub struct CourseFilter {
+    pub teacher_id: Option<i32>,
+    pub language: Option<String>,
+    pub level: Option<String>,
+    pub max_price: Option<i32>,
+    pub min_rating: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, InputObject)]
 pub struct CourseCreation {
     pub teacher_id: i32,
     pub name: String,
     pub description: Option<String>,
     pub format: Option<String>,
     pub structure: Option<String>,
+    // Deprecated, see `Course::duration`.
     pub duration: Option<String>,
     pub price: Option<i32>,
     pub language: Option<String>,
     pub level: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, ToSchema, InputObject)]
 pub struct CourseUpdate {
     pub name: Option<String>,
     pub description: Option<String>,
     pub format: Option<String>,
     pub structure: Option<String>,
+    // Deprecated, see `Course::duration`.
     pub duration: Option<String>,
     pub price: Option<i32>,
     pub language: Option<String>,
@@ -92,3 +179,9 @@ impl From<web::Json<CourseUpdate>> for CourseUpdate {
         }
     }
 }
+
+impl From<web::Json<CourseTransitionCreation>> for CourseTransitionCreation {
+    fn from(transition: web::Json<CourseTransitionCreation>) -> Self {
+        transition.into_inner()
+    }
+}
@@ -0,0 +1,141 @@
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use utoipa::ToSchema;
+
+use crate::errors::MyError;
+
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct Section {
+    pub id: i32,
+    pub course_id: i32,
+    pub title: String,
+    pub position: i32,
+}
+
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct Lesson {
+    pub id: i32,
+    pub section_id: i32,
+    pub course_id: i32,
+    pub title: String,
+    pub position: i32,
+    pub duration_minutes: i32,
+    pub is_preview: bool,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SectionWithLessons {
+    pub id: i32,
+    pub course_id: i32,
+    pub title: String,
+    pub position: i32,
+    pub duration_minutes: i64,
+    pub lessons: Vec<Lesson>,
+}
+
+impl SectionWithLessons {
+    pub fn new(section: Section, lessons: Vec<Lesson>) -> Self {
+        SectionWithLessons {
+            id: section.id,
+            course_id: section.course_id,
+            title: section.title,
+            position: section.position,
+            duration_minutes: lessons
+                .iter()
+                .map(|lesson| lesson.duration_minutes as i64)
+                .sum(),
+            lessons,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct SectionCreation {
+    pub title: String,
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct SectionUpdate {
+    pub title: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct LessonCreation {
+    pub title: String,
+    pub duration_minutes: i32,
+    pub is_preview: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct LessonUpdate {
+    pub title: Option<String>,
+    pub duration_minutes: Option<i32>,
+    pub is_preview: Option<bool>,
+}
+
+// The ids of all the sections of a course, or of all the lessons of a section, in their new order.
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct Reorder {
+    pub ids: Vec<i32>,
+}
+
+fn validate_title(title: &Option<String>) -> Result<(), MyError> {
+    match title {
+        Some(title) if title.trim().is_empty() => {
+            Err(MyError::InvalidInput("The title can't be empty".into()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_duration(duration_minutes: Option<i32>) -> Result<(), MyError> {
+    match duration_minutes {
+        Some(duration_minutes) if duration_minutes < 0 => Err(MyError::InvalidInput(
+            "The duration can't be negative".into(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl TryFrom<web::Json<SectionCreation>> for SectionCreation {
+    type Error = MyError;
+    fn try_from(section: web::Json<SectionCreation>) -> Result<Self, Self::Error> {
+        validate_title(&Some(section.title.clone()))?;
+        Ok(section.into_inner())
+    }
+}
+
+impl TryFrom<web::Json<SectionUpdate>> for SectionUpdate {
+    type Error = MyError;
+    fn try_from(section: web::Json<SectionUpdate>) -> Result<Self, Self::Error> {
+        validate_title(&section.title)?;
+        Ok(section.into_inner())
+    }
+}
+
+impl TryFrom<web::Json<LessonCreation>> for LessonCreation {
+    type Error = MyError;
+    fn try_from(lesson: web::Json<LessonCreation>) -> Result<Self, Self::Error> {
+        validate_title(&Some(lesson.title.clone()))?;
+        validate_duration(Some(lesson.duration_minutes))?;
+        Ok(lesson.into_inner())
+    }
+}
+
+impl TryFrom<web::Json<LessonUpdate>> for LessonUpdate {
+    type Error = MyError;
+    fn try_from(lesson: web::Json<LessonUpdate>) -> Result<Self, Self::Error> {
+        validate_title(&lesson.title)?;
+        validate_duration(lesson.duration_minutes)?;
+        Ok(lesson.into_inner())
+    }
+}
+
+impl From<web::Json<Reorder>> for Reorder {
+    fn from(reorder: web::Json<Reorder>) -> Self {
+        Reorder {
+            ids: reorder.ids.clone(),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CatalogEventQuery {
+    // Only the changes of this teacher and of their courses.
+    pub teacher_id: Option<i32>,
+}
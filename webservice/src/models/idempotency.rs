@@ -0,0 +1,6 @@
+#[derive(Clone, Debug)]
+pub struct IdempotencyRecord {
+    pub request_hash: String,
+    pub status_code: Option<i16>,
+    pub response_body: Option<Vec<u8>>,
+}
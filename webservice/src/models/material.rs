@@ -0,0 +1,28 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct CourseMaterial {
+    pub id: i32,
+    pub course_id: i32,
+    pub teacher_id: i32,
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub checksum: String,
+    #[serde(skip)]
+    pub storage_key: String,
+    pub time: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CourseMaterialCreation {
+    pub course_id: i32,
+    pub teacher_id: i32,
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub checksum: String,
+    pub storage_key: String,
+}
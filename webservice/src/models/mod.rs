@@ -1,2 +1,11 @@
+pub mod audit;
 pub mod course;
+pub mod curriculum;
+pub mod event;
+pub mod idempotency;
+pub mod material;
+pub mod review;
+pub mod revision;
+pub mod session;
 pub mod teacher;
+pub mod webhook;
@@ -0,0 +1,76 @@
+use actix_web::web;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use utoipa::ToSchema;
+
+use crate::errors::MyError;
+
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct Review {
+    pub id: i32,
+    pub course_id: i32,
+    pub student_id: i32,
+    pub rating: i16,
+    pub text: Option<String>,
+    pub is_hidden: bool,
+    pub is_flagged: bool,
+    pub time: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct ReviewCreation {
+    pub student_id: i32,
+    pub rating: i16,
+    pub text: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct ReviewUpdate {
+    pub rating: Option<i16>,
+    pub text: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct ReviewModeration {
+    pub is_hidden: Option<bool>,
+    pub is_flagged: Option<bool>,
+}
+
+fn validate_rating(rating: Option<i16>) -> Result<(), MyError> {
+    match rating {
+        Some(rating) if !(1..=5).contains(&rating) => Err(MyError::InvalidInput(
+            "The rating must be between 1 and 5".into(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl TryFrom<web::Json<ReviewCreation>> for ReviewCreation {
+    type Error = MyError;
+    fn try_from(review: web::Json<ReviewCreation>) -> Result<Self, Self::Error> {
+        validate_rating(Some(review.rating))?;
+        Ok(review.into_inner())
+    }
+}
+
+impl TryFrom<web::Json<ReviewUpdate>> for ReviewUpdate {
+    type Error = MyError;
+    fn try_from(review: web::Json<ReviewUpdate>) -> Result<Self, Self::Error> {
+        validate_rating(review.rating)?;
+        Ok(review.into_inner())
+    }
+}
+
+impl From<web::Json<ReviewModeration>> for ReviewModeration {
+    fn from(moderation: web::Json<ReviewModeration>) -> Self {
+        moderation.into_inner()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReviewListQuery {
+    #[serde(default)]
+    pub include_hidden: bool,
+}
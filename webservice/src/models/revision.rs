@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::errors::MyError;
+
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct CourseRevision {
+    pub id: i32,
+    pub course_id: i32,
+    pub revision: i32,
+    // The course as it was before the update.
+    #[schema(value_type = Object)]
+    pub snapshot: Value,
+    pub actor: String,
+    pub time: DateTime<Utc>,
+}
+
+// Compares revision `from` with revision `to`, or with the current course when `to` is missing.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RevisionDiffQuery {
+    pub from: i32,
+    pub to: Option<i32>,
+}
+
+// Restores a revision only if `expected_revision` is still the newest one, so that the
+// updates made since it was listed are not overwritten.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RevisionRestoreQuery {
+    pub expected_revision: i32,
+}
+
+// The editable fields of a snapshot, NULLs included.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CourseSnapshot {
+    pub name: String,
+    pub description: Option<String>,
+    pub format: Option<String>,
+    pub structure: Option<String>,
+    #[serde(default)]
+    pub duration: Option<String>,
+    pub price: Option<i32>,
+    pub language: Option<String>,
+    pub level: Option<String>,
+}
+
+impl TryFrom<&CourseRevision> for CourseSnapshot {
+    type Error = MyError;
+    fn try_from(revision: &CourseRevision) -> Result<Self, Self::Error> {
+        serde_json::from_value(revision.snapshot.clone())
+            .map_err(|err| MyError::ActixError(err.to_string()))
+    }
+}
@@ -0,0 +1,102 @@
+use actix_web::web;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use utoipa::ToSchema;
+
+use crate::errors::MyError;
+use crate::schedule::{localize, parse_time_zone, Recurrence};
+
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct CourseSession {
+    pub id: i32,
+    pub course_id: i32,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub time_zone: String,
+    pub recurrence: Option<String>,
+    pub capacity: Option<i32>,
+}
+
+// `starts_at` and `ends_at` are wall-clock times in `time_zone`.
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct CourseSessionCreation {
+    pub starts_at: NaiveDateTime,
+    pub ends_at: NaiveDateTime,
+    pub time_zone: String,
+    pub recurrence: Option<String>,
+    pub capacity: Option<i32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewCourseSession {
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub time_zone: String,
+    pub recurrence: Option<String>,
+    pub capacity: Option<i32>,
+}
+
+// A session along with the course it belongs to, for the listings and the calendars.
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct ScheduledSession {
+    pub id: i32,
+    pub course_id: i32,
+    pub teacher_id: i32,
+    pub course_name: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub time_zone: String,
+    pub recurrence: Option<String>,
+    pub capacity: Option<i32>,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SessionOccurrence {
+    pub session_id: i32,
+    pub course_id: i32,
+    pub teacher_id: i32,
+    pub course_name: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub time_zone: String,
+    pub capacity: Option<i32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SessionRangeQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<web::Json<CourseSessionCreation>> for NewCourseSession {
+    type Error = MyError;
+    fn try_from(session: web::Json<CourseSessionCreation>) -> Result<Self, Self::Error> {
+        let time_zone = parse_time_zone(&session.time_zone)?;
+        let localize = |local| {
+            localize(local, time_zone).ok_or_else(|| {
+                MyError::InvalidInput(format!("{} doesn't exist in {}", local, time_zone))
+            })
+        };
+        let starts_at = localize(session.starts_at)?;
+        let ends_at = localize(session.ends_at)?;
+        if ends_at <= starts_at {
+            return Err(MyError::InvalidInput(
+                "A session must end after it starts".into(),
+            ));
+        }
+        if let Some(recurrence) = &session.recurrence {
+            recurrence.parse::<Recurrence>()?;
+        }
+        if session.capacity.is_some_and(|capacity| capacity <= 0) {
+            return Err(MyError::InvalidInput("The capacity must be positive".into()));
+        }
+        Ok(NewCourseSession {
+            starts_at,
+            ends_at,
+            time_zone: session.time_zone.clone(),
+            recurrence: session.recurrence.clone(),
+            capacity: session.capacity,
+        })
+    }
+}
@@ -1,25 +1,29 @@
 use actix_web::web;
+use async_graphql::{InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, SimpleObject)]
+#[graphql(complex)]
 pub struct Teacher {
     pub id: i32,
     pub name: String,
     pub url_picture: String,
     pub profile: String,
+    pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+// The picture is only set by uploading it, see `upload_one_teacher_picture`.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, InputObject)]
 pub struct TeacherCreation {
     pub name: String,
-    pub url_picture: String,
     pub profile: String,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, ToSchema, InputObject)]
 pub struct TeacherUpdate {
     pub name: Option<String>,
-    pub url_picture: Option<String>,
     pub profile: Option<String>,
 }
 
@@ -27,7 +31,6 @@ impl From<web::Json<TeacherCreation>> for TeacherCreation {
     fn from(new_teacher: web::Json<TeacherCreation>) -> Self {
         TeacherCreation {
             name: new_teacher.name.clone(),
-            url_picture: new_teacher.url_picture.clone(),
             profile: new_teacher.profile.clone(),
         }
     }
@@ -37,7 +40,6 @@ impl From<web::Json<TeacherUpdate>> for TeacherUpdate {
     fn from(teacher_update: web::Json<TeacherUpdate>) -> Self {
         TeacherUpdate {
             name: teacher_update.name.clone(),
-            url_picture: teacher_update.url_picture.clone(),
             profile: teacher_update.profile.clone(),
         }
     }
@@ -0,0 +1,130 @@
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::TryFrom;
+use utoipa::ToSchema;
+
+use crate::errors::MyError;
+
+const MAX_URL_LENGTH: usize = 2000;
+const MIN_SECRET_LENGTH: usize = 16;
+const MAX_SECRET_LENGTH: usize = 200;
+
+// The secret is never sent back once registered.
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct WebhookCreation {
+    pub url: String,
+    pub secret: String,
+}
+
+impl TryFrom<web::Json<WebhookCreation>> for WebhookCreation {
+    type Error = MyError;
+    fn try_from(webhook: web::Json<WebhookCreation>) -> Result<Self, Self::Error> {
+        if !(webhook.url.starts_with("https://") || webhook.url.starts_with("http://"))
+            || webhook.url.len() > MAX_URL_LENGTH
+        {
+            return Err(MyError::InvalidInput(format!(
+                "The url must be an http or https url of at most {} characters",
+                MAX_URL_LENGTH
+            )));
+        }
+        if !(MIN_SECRET_LENGTH..=MAX_SECRET_LENGTH).contains(&webhook.secret.len()) {
+            return Err(MyError::InvalidInput(format!(
+                "The secret must hold between {} and {} characters",
+                MIN_SECRET_LENGTH, MAX_SECRET_LENGTH
+            )));
+        }
+        Ok(webhook.into_inner())
+    }
+}
+
+// The body posted to the webhooks and streamed to `/events`.
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct OutboxEvent {
+    // Deliveries are retried, so receivers should skip the ids they already handled.
+    pub id: i64,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub teacher_id: Option<i32>,
+    pub entity_id: i32,
+    // The entity after the change, or before it for deletions.
+    #[schema(value_type = Object)]
+    pub data: Value,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct OutboxEventCreation {
+    pub event_type: &'static str,
+    // The teacher itself, or the teacher of the course.
+    pub teacher_id: i32,
+    pub entity_id: i32,
+    pub data: Value,
+}
+
+impl OutboxEventCreation {
+    pub fn new(
+        event_type: &'static str,
+        teacher_id: i32,
+        entity_id: i32,
+        entity: &impl Serialize,
+    ) -> Result<Self, MyError> {
+        let data =
+            serde_json::to_value(entity).map_err(|err| MyError::ActixError(err.to_string()))?;
+        Ok(OutboxEventCreation {
+            event_type,
+            teacher_id,
+            entity_id,
+            data,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow, Clone, Debug, Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub event_id: i64,
+    pub event_type: String,
+    pub webhook_id: i32,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+// A delivery claimed by the worker, with what it needs to send it.
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct DueDelivery {
+    pub id: i64,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+    pub event_id: i64,
+    pub event_type: String,
+    pub teacher_id: Option<i32>,
+    pub entity_id: i32,
+    pub data: Value,
+    pub time: DateTime<Utc>,
+}
+
+impl From<&DueDelivery> for OutboxEvent {
+    fn from(delivery: &DueDelivery) -> Self {
+        OutboxEvent {
+            id: delivery.event_id,
+            event_type: delivery.event_type.clone(),
+            teacher_id: delivery.teacher_id,
+            entity_id: delivery.entity_id,
+            data: delivery.data.clone(),
+            time: delivery.time,
+        }
+    }
+}
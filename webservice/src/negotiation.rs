@@ -0,0 +1,536 @@
+use crate::errors::MyError;
+use crate::security::{BodyLimit, DEFAULT_JSON_LIMIT};
+use actix_web::dev::Payload;
+use actix_web::http::header::{self, Header};
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::{ready, Ready};
+use std::io;
+
+// The items written to a streamed list at once, rather than one by one.
+const ROWS_PER_CHUNK: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+    // Lists only, one row per item under a header row.
+    Csv,
+    // Lists only, one JSON item per line.
+    NdJson,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::NdJson => "application/x-ndjson",
+        }
+    }
+
+    // `*/*` and `application/*` get JSON.
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MessagePack)
+            }
+            "application/cbor" => Some(Format::Cbor),
+            "text/csv" => Some(Format::Csv),
+            "application/x-ndjson" => Some(Format::NdJson),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::MessagePack => "MessagePack",
+            Format::Cbor => "CBOR",
+            Format::Csv => "CSV",
+            Format::NdJson => "NDJSON",
+        }
+    }
+
+    fn lists_only(self) -> bool {
+        matches!(self, Format::Csv | Format::NdJson)
+    }
+
+    // The formats whose lists are written item by item, rather than as a whole.
+    fn streamed(self) -> bool {
+        matches!(self, Format::Json | Format::Csv | Format::NdJson)
+    }
+
+    fn encode(self, value: &impl Serialize) -> Result<Vec<u8>, MyError> {
+        let body = match self {
+            Format::Json => serde_json::to_vec(value).map_err(|err| err.to_string()),
+            // Structs are encoded as maps, as in JSON, rather than as arrays.
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|err| err.to_string()),
+            Format::Cbor => {
+                let mut body = Vec::new();
+                ciborium::ser::into_writer(value, &mut body)
+                    .map(|_| body)
+                    .map_err(|err| err.to_string())
+            }
+            Format::Csv | Format::NdJson => {
+                return Err(MyError::NotAcceptable(format!(
+                    "{} is only available for lists",
+                    self.name()
+                )))
+            }
+        };
+        body.map_err(MyError::ActixError)
+    }
+
+    fn encode_list<T: Serialize>(self, values: &[T]) -> Result<Vec<u8>, MyError> {
+        if !self.lists_only() {
+            return self.encode(&values);
+        }
+        let mut writer = ListWriter::new(self);
+        let mut body = writer.write(values)?;
+        body.extend(writer.finish());
+        Ok(body)
+    }
+
+    fn decode<T: DeserializeOwned>(self, body: &[u8]) -> Result<T, MyError> {
+        let decoded = match self {
+            // A single line.
+            Format::Json | Format::NdJson => serde_json::from_slice(body).ok(),
+            Format::MessagePack => rmp_serde::from_slice(body).ok(),
+            Format::Cbor => ciborium::de::from_reader(body).ok(),
+            // A header row and a single record.
+            Format::Csv => {
+                let mut records = csv::Reader::from_reader(body).into_deserialize();
+                match (records.next(), records.next()) {
+                    (Some(Ok(record)), None) => Some(record),
+                    _ => None,
+                }
+            }
+        };
+        decoded.ok_or_else(|| {
+            MyError::InvalidInput(format!("Please provide valid {} input", self.name()))
+        })
+    }
+}
+
+// Writes a list in slices: a JSON array, NDJSON lines or CSV rows under a header row.
+struct ListWriter {
+    format: Format,
+    written: usize,
+}
+
+impl ListWriter {
+    fn new(format: Format) -> Self {
+        ListWriter { format, written: 0 }
+    }
+
+    fn write<T: Serialize>(&mut self, values: &[T]) -> Result<Vec<u8>, MyError> {
+        let mut body = Vec::new();
+        if self.format == Format::Csv {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(self.written == 0)
+                .from_writer(body);
+            for value in values {
+                writer
+                    .serialize(value)
+                    .map_err(|err| MyError::ActixError(err.to_string()))?;
+            }
+            body = writer
+                .into_inner()
+                .map_err(|err| MyError::ActixError(err.to_string()))?;
+        } else {
+            for (i, value) in values.iter().enumerate() {
+                if self.format == Format::Json {
+                    body.push(if self.written + i == 0 { b'[' } else { b',' });
+                }
+                serde_json::to_writer(&mut body, value)
+                    .map_err(|err| MyError::ActixError(err.to_string()))?;
+                if self.format == Format::NdJson {
+                    body.push(b'\n');
+                }
+            }
+        }
+        self.written += values.len();
+        Ok(body)
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match (self.format, self.written) {
+            (Format::Json, 0) => b"[]".to_vec(),
+            (Format::Json, _) => b"]".to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// The formats of the `Accept` header that we support, most preferred first.
+// A request without `Accept` gets JSON.
+#[derive(Clone, Debug)]
+pub struct Accepted(Vec<Format>);
+
+impl Default for Accepted {
+    fn default() -> Self {
+        Accepted(vec![Format::Json])
+    }
+}
+
+impl Accepted {
+    fn respond(format: Format, body: Vec<u8>) -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type(format.content_type())
+            .insert_header((header::VARY, "Accept"))
+            .body(body)
+    }
+
+    // The preferred format able to hold a single item, so neither CSV nor NDJSON.
+    pub fn item_format(&self) -> Result<Format, MyError> {
+        self.0
+            .iter()
+            .copied()
+            .find(|format| !format.lists_only())
+            .ok_or_else(|| {
+                MyError::NotAcceptable(
+                    "Please accept JSON, MessagePack or CBOR for this resource".into(),
+                )
+            })
+    }
+
+    pub fn item(&self, value: &impl Serialize) -> Result<HttpResponse, MyError> {
+        let format = self.item_format()?;
+        Ok(Accepted::respond(format, format.encode(value)?))
+    }
+
+    pub fn list<T: Serialize>(&self, values: &[T]) -> Result<HttpResponse, MyError> {
+        let format = self.0[0];
+        Ok(Accepted::respond(format, format.encode_list(values)?))
+    }
+
+    // Writes the items as they come, `ROWS_PER_CHUNK` at most at a time, so that the
+    // lists of any size take no more memory than that. MessagePack and CBOR lists are
+    // still encoded as a whole. An error past the first chunk aborts the response.
+    pub async fn stream<T, S>(&self, values: S) -> Result<HttpResponse, MyError>
+    where
+        T: Serialize + 'static,
+        S: Stream<Item = Result<T, MyError>> + 'static,
+    {
+        let format = self.0[0];
+        if !format.streamed() {
+            let values: Vec<T> = values.try_collect().await?;
+            return self.list(&values);
+        }
+        let chunks = Box::pin(values).ready_chunks(ROWS_PER_CHUNK);
+        let body = stream::unfold(
+            Some((ListWriter::new(format), chunks)),
+            |state| async move {
+                let (mut writer, mut chunks) = match state {
+                    Some(state) => state,
+                    None => return None,
+                };
+                match chunks.next().await {
+                    Some(values) => {
+                        let chunk = values
+                            .into_iter()
+                            .collect::<Result<Vec<T>, MyError>>()
+                            .and_then(|values| writer.write(&values));
+                        // The response ends with its first error.
+                        let state = match chunk {
+                            Ok(_) => Some((writer, chunks)),
+                            Err(_) => None,
+                        };
+                        Some((chunk, state))
+                    }
+                    None => Some((Ok(writer.finish()), None)),
+                }
+            },
+        )
+        .map(|chunk| match chunk {
+            Ok(chunk) => Ok(web::Bytes::from(chunk)),
+            Err(err) => {
+                tracing::error!("Unable to stream the list: {:?}", err);
+                Err(io::Error::other(err.to_string()))
+            }
+        });
+        Ok(HttpResponse::Ok()
+            .content_type(format.content_type())
+            .insert_header((header::VARY, "Accept"))
+            .streaming(body))
+    }
+}
+
+impl FromRequest for Accepted {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let accept = match header::Accept::parse(req) {
+            Ok(accept) if !accept.is_empty() => accept,
+            _ => return ready(Ok(Accepted::default())),
+        };
+        let mut formats: Vec<Format> = Vec::new();
+        for media_type in accept.ranked() {
+            if let Some(format) = Format::from_media_type(media_type.essence_str()) {
+                if !formats.contains(&format) {
+                    formats.push(format);
+                }
+            }
+        }
+        if formats.is_empty() {
+            return ready(Err(MyError::NotAcceptable(
+                "Please accept JSON, MessagePack, CBOR, CSV or NDJSON".into(),
+            )));
+        }
+        ready(Ok(Accepted(formats)))
+    }
+}
+
+// A request body in the format of its `Content-Type`. JSON goes through `web::Json`, and
+// its `JsonConfig`; the other formats are limited to the `BodyLimit` of the route.
+#[derive(Clone, Debug)]
+pub struct Body<T>(pub T);
+
+impl<T> Body<T> {
+    // The handlers validate their bodies as they did when those were all JSON.
+    pub fn into_json(self) -> web::Json<T> {
+        web::Json(self.0)
+    }
+}
+
+impl<T> std::ops::Deref for Body<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+fn body_format(req: &HttpRequest) -> Result<Format, MyError> {
+    let content_type = match req.mime_type() {
+        Ok(Some(content_type)) => content_type,
+        // Left to `web::Json`, which refuses it as it did before.
+        Ok(None) | Err(_) => return Ok(Format::Json),
+    };
+    if content_type.suffix().map(|suffix| suffix.as_str()) == Some("json") {
+        return Ok(Format::Json);
+    }
+    match Format::from_media_type(content_type.essence_str()) {
+        Some(format) if !content_type.essence_str().contains('*') => Ok(format),
+        _ => Err(MyError::UnsupportedMediaType(
+            "Please send JSON, MessagePack, CBOR, CSV or NDJSON".into(),
+        )),
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Body<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let format = match body_format(req) {
+            Ok(format) => format,
+            Err(err) => return Box::pin(ready(Err(err.into()))),
+        };
+        if format == Format::Json {
+            let json = web::Json::<T>::from_request(req, payload);
+            return Box::pin(async move { json.await.map(|json| Body(json.into_inner())) });
+        }
+        let limit = req
+            .app_data::<BodyLimit>()
+            .map_or(DEFAULT_JSON_LIMIT, |limit| limit.0);
+        let mut payload = payload.take();
+        Box::pin(async move {
+            let mut body = web::BytesMut::new();
+            while let Some(chunk) = payload.next().await {
+                let chunk = chunk.map_err(|err| MyError::InvalidInput(err.to_string()))?;
+                if body.len() + chunk.len() > limit {
+                    return Err(
+                        MyError::PayloadTooLarge("The request body is too large".into()).into(),
+                    );
+                }
+                body.extend_from_slice(&chunk);
+            }
+            Ok(Body(format.decode(&body)?))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::course::CourseCreation;
+    use crate::models::teacher::Teacher;
+    use actix_web::body::{BodySize, MessageBody};
+    use actix_web::http::StatusCode;
+    use actix_web::{test, ResponseError};
+    use chrono::{TimeZone, Utc};
+
+    fn teachers() -> Vec<Teacher> {
+        vec![
+            Teacher {
+                id: 1,
+                name: "Ada".into(),
+                url_picture: "https://example.com/ada.png".into(),
+                profile: "Mathematician, \"first programmer\"".into(),
+                updated_at: Utc.ymd(2022, 8, 15).and_hms(12, 0, 0),
+            },
+            Teacher {
+                id: 2,
+                name: "Alan".into(),
+                url_picture: "".into(),
+                profile: "Logician".into(),
+                updated_at: Utc.ymd(2022, 8, 15).and_hms(12, 0, 0),
+            },
+        ]
+    }
+
+    async fn accepted(accept: &str) -> Result<Accepted, MyError> {
+        let (req, mut payload) = test::TestRequest::default()
+            .insert_header((header::ACCEPT, accept))
+            .to_http_parts();
+        Accepted::from_request(&req, &mut payload).await
+    }
+
+    #[actix_rt::test]
+    async fn accept_header_should_pick_the_preferred_format() {
+        let accepted_formats = accepted("text/html, application/cbor;q=0.5, application/msgpack")
+            .await
+            .unwrap();
+        assert_eq!(accepted_formats.0, vec![Format::MessagePack, Format::Cbor]);
+        let (req, mut payload) = test::TestRequest::default().to_http_parts();
+        let accepted_formats = Accepted::from_request(&req, &mut payload).await.unwrap();
+        assert_eq!(accepted_formats.0, vec![Format::Json]);
+        let err = accepted("text/html").await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[actix_rt::test]
+    async fn lists_should_be_served_as_csv() {
+        let accepted_formats = accepted("text/csv").await.unwrap();
+        let resp = accepted_formats.list(&teachers()).unwrap();
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/csv; charset=utf-8"
+        );
+        let body = test::read_body(test::TestRequest::default().to_srv_response(resp)).await;
+        assert_eq!(
+            body,
+            "id,name,url_picture,profile,updated_at\n\
+             1,Ada,https://example.com/ada.png,\"Mathematician, \"\"first programmer\"\"\",2022-08-15T12:00:00Z\n\
+             2,Alan,,Logician,2022-08-15T12:00:00Z\n"
+        );
+        let err = accepted_formats.item(&teachers()[0]).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_ACCEPTABLE);
+        // A single item falls back to the next accepted format.
+        let accepted_formats = accepted("text/csv, application/json;q=0.1").await.unwrap();
+        let resp = accepted_formats.item(&teachers()[0]).unwrap();
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn lists_should_be_streamed_in_chunks() {
+        let many = || (0..100).map(|_| Ok(teachers()[1].clone()));
+        let resp = Accepted::default()
+            .stream(stream::iter(many()))
+            .await
+            .unwrap();
+        assert_eq!(resp.body().size(), BodySize::Stream);
+        let body = test::read_body(test::TestRequest::default().to_srv_response(resp)).await;
+        let decoded: Vec<Teacher> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(decoded.len(), 100);
+        let resp = Accepted::default()
+            .stream(stream::iter(Vec::<Result<Teacher, MyError>>::new()))
+            .await
+            .unwrap();
+        let body = test::read_body(test::TestRequest::default().to_srv_response(resp)).await;
+        assert_eq!(body, "[]");
+
+        let accepted_formats = accepted("text/csv").await.unwrap();
+        let resp = accepted_formats.stream(stream::iter(many())).await.unwrap();
+        let body = test::read_body(test::TestRequest::default().to_srv_response(resp)).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        // A single header row, whatever the number of chunks.
+        assert_eq!(body.lines().count(), 101);
+        assert_eq!(body.matches("id,name").count(), 1);
+
+        let accepted_formats = accepted("application/x-ndjson").await.unwrap();
+        let resp = accepted_formats
+            .stream(stream::iter(teachers().into_iter().map(Ok)))
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/x-ndjson"
+        );
+        let body = test::read_body(test::TestRequest::default().to_srv_response(resp)).await;
+        let lines: Vec<Teacher> = body
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines[1].name, "Alan");
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn binary_formats_should_round_trip() {
+        for format in [Format::MessagePack, Format::Cbor] {
+            let body = format.encode(&teachers()).unwrap();
+            let decoded: Vec<Teacher> = format.decode(&body).unwrap();
+            assert_eq!(decoded[0].profile, teachers()[0].profile);
+            assert_eq!(decoded.len(), 2);
+        }
+    }
+
+    async fn body(content_type: &str, body: Vec<u8>) -> Result<Body<CourseCreation>, StatusCode> {
+        let (req, mut payload) = test::TestRequest::post()
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .set_payload(body)
+            .to_http_parts();
+        Body::<CourseCreation>::from_request(&req, &mut payload)
+            .await
+            .map_err(|err| err.as_response_error().status_code())
+    }
+
+    #[actix_rt::test]
+    async fn bodies_should_be_read_in_their_content_type() {
+        let course = serde_json::json!({ "teacher_id": 1, "name": "Binary course", "price": 10 });
+        let msgpack = body(
+            "application/msgpack",
+            rmp_serde::to_vec_named(&course).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(msgpack.name, "Binary course");
+        let mut cbor = Vec::new();
+        ciborium::ser::into_writer(&course, &mut cbor).unwrap();
+        let cbor = body("application/cbor", cbor).await.unwrap();
+        assert_eq!(cbor.price, Some(10));
+        let csv = body(
+            "text/csv",
+            b"teacher_id,name,description,format,structure,price,language,level\n1,CSV course,,,,,English,\n"
+                .to_vec(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(csv.name, "CSV course");
+        assert_eq!(csv.description, None);
+        assert_eq!(csv.language, Some("English".into()));
+        let status = body("application/xml", b"<course/>".to_vec())
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let status = body("application/cbor", b"not cbor".to_vec())
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::errors::MyErrorResponse;
+use crate::handlers::{
+    audit, course, curriculum, event, material, review, revision, session, teacher, webhook,
+};
+use crate::models::audit::AuditEvent;
+use crate::models::course::{
+    Course, CourseCreation, CourseSort, CourseStatus, CourseTransition, CourseTransitionCreation,
+    CourseUpdate,
+};
+use crate::models::curriculum::{
+    Lesson, LessonCreation, LessonUpdate, Reorder, Section, SectionCreation, SectionUpdate,
+    SectionWithLessons,
+};
+use crate::models::material::CourseMaterial;
+use crate::models::review::{Review, ReviewCreation, ReviewModeration, ReviewUpdate};
+use crate::models::revision::CourseRevision;
+use crate::models::session::{CourseSession, CourseSessionCreation, SessionOccurrence};
+use crate::models::teacher::{Teacher, TeacherCreation, TeacherUpdate};
+use crate::models::webhook::{OutboxEvent, Webhook, WebhookCreation, WebhookDelivery};
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        teacher::get_all_teachers,
+        teacher::get_one_teacher_detail,
+        teacher::create_one_teacher,
+        teacher::update_one_teacher_detail,
+        teacher::delete_one_teacher,
+        teacher::upload_one_teacher_picture,
+        course::create_one_course_for_teacher,
+        course::get_courses_for_teacher,
+        course::get_unpublished_courses_for_teacher,
+        course::get_one_course_by_id,
+        course::update_one_course_by_id,
+        course::delete_one_course_by_id,
+        course::get_transitions_for_course,
+        course::transition_one_course,
+        curriculum::get_curriculum_for_course,
+        curriculum::create_one_section,
+        curriculum::reorder_sections,
+        curriculum::update_one_section,
+        curriculum::delete_one_section,
+        curriculum::create_one_lesson,
+        curriculum::reorder_lessons,
+        curriculum::update_one_lesson,
+        curriculum::delete_one_lesson,
+        material::get_materials_for_course,
+        material::upload_one_course_material,
+        material::download_one_course_material,
+        material::delete_one_course_material,
+        review::get_reviews_for_course,
+        review::create_one_review,
+        review::get_one_review,
+        review::update_one_review,
+        review::moderate_one_review,
+        review::delete_one_review,
+        revision::get_revisions_for_course,
+        revision::get_one_revision,
+        revision::diff_revisions,
+        revision::restore_one_revision,
+        session::get_sessions_for_course,
+        session::create_one_session,
+        session::delete_one_session,
+        session::get_upcoming_sessions,
+        session::get_course_calendar,
+        session::get_teacher_calendar,
+        audit::get_audit_events,
+        webhook::get_all_webhooks,
+        webhook::create_one_webhook,
+        webhook::delete_one_webhook,
+        webhook::get_dead_deliveries,
+        webhook::retry_one_delivery,
+        event::get_catalog_events,
+    ),
+    components(schemas(
+        Teacher,
+        TeacherCreation,
+        TeacherUpdate,
+        Course,
+        CourseCreation,
+        CourseUpdate,
+        CourseSort,
+        CourseStatus,
+        CourseTransition,
+        CourseTransitionCreation,
+        Section,
+        SectionWithLessons,
+        SectionCreation,
+        SectionUpdate,
+        Lesson,
+        LessonCreation,
+        LessonUpdate,
+        Reorder,
+        CourseMaterial,
+        Review,
+        ReviewCreation,
+        ReviewUpdate,
+        ReviewModeration,
+        CourseRevision,
+        CourseSession,
+        CourseSessionCreation,
+        SessionOccurrence,
+        AuditEvent,
+        Webhook,
+        WebhookCreation,
+        WebhookDelivery,
+        OutboxEvent,
+        MyErrorResponse,
+    )),
+    tags(
+        (name = "teacher", description = "Teachers"),
+        (name = "course", description = "Courses given by the teachers"),
+        (name = "curriculum", description = "Ordered sections and lessons of the courses"),
+        (name = "material", description = "Files attached to the courses"),
+        (name = "review", description = "Ratings of the courses by their students"),
+        (name = "revision", description = "Revision history of the courses"),
+        (name = "session", description = "Scheduled sessions of the courses"),
+        (name = "audit", description = "Log of the changes made to the teachers and the courses"),
+        (name = "webhook", description = "Delivery of the domain events to other systems"),
+        (name = "event", description = "Live stream of the catalog changes")
+    )
+)]
+pub struct ApiDoc;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::routers::V1_ROUTES;
+    use crate::versioning::api_path;
+    use std::collections::BTreeSet;
+    use utoipa::openapi::PathItemType;
+
+    fn method_name(item_type: &PathItemType) -> &'static str {
+        match item_type {
+            PathItemType::Get => "get",
+            PathItemType::Post => "post",
+            PathItemType::Put => "put",
+            PathItemType::Delete => "delete",
+            PathItemType::Options => "options",
+            PathItemType::Head => "head",
+            PathItemType::Patch => "patch",
+            PathItemType::Trace => "trace",
+            PathItemType::Connect => "connect",
+        }
+    }
+
+    // Path parameters, whatever their name or pattern, compare as "{}".
+    fn normalize(path: &str) -> String {
+        path.split('/')
+            .map(|segment| {
+                if segment.starts_with('{') {
+                    "{}"
+                } else {
+                    segment
+                }
+            })
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+
+    // The route table and the spec have to match both ways: every route registered by
+    // `routes_v1` has to be documented by its handler, and every documented operation has
+    // to be routed to the handler documenting it.
+    #[test]
+    fn routes_and_documented_operations_should_match() {
+        let routed: BTreeSet<(String, String, String)> = V1_ROUTES
+            .iter()
+            .flat_map(|routes| routes.iter())
+            .map(|route| {
+                (
+                    route.method.to_owned(),
+                    normalize(&format!("{}{}", api_path("v1"), route.path)),
+                    route.handler.to_owned(),
+                )
+            })
+            .collect();
+        assert!(!routed.is_empty());
+
+        let openapi = ApiDoc::openapi();
+        let documented: BTreeSet<(String, String, String)> = openapi
+            .paths
+            .paths
+            .iter()
+            .flat_map(|(path, item)| {
+                item.operations.iter().map(move |(item_type, operation)| {
+                    (
+                        method_name(item_type).to_owned(),
+                        normalize(path),
+                        operation.operation_id.clone().unwrap_or_default(),
+                    )
+                })
+            })
+            .collect();
+        assert!(!documented.is_empty());
+        let undocumented: Vec<_> = routed.difference(&documented).collect();
+        assert!(
+            undocumented.is_empty(),
+            "routed but not documented: {:?}",
+            undocumented
+        );
+        let unrouted: Vec<_> = documented.difference(&routed).collect();
+        assert!(
+            unrouted.is_empty(),
+            "documented but not routed: {:?}",
+            unrouted
+        );
+    }
+}
@@ -0,0 +1,357 @@
+use crate::dbaccesses::outbox::*;
+use crate::errors::MyError;
+use crate::models::webhook::{DueDelivery, OutboxEvent};
+use crate::security::is_public_address;
+use crate::shutdown::sleep_unless_shutting_down;
+use crate::state::AppState;
+use actix_web::web;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::postgres::PgPool;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Clone, Copy, Debug)]
+pub struct OutboxConfig {
+    // How long the worker waits when no delivery is due.
+    pub poll_interval: Duration,
+    pub batch_size: i64,
+    // Attempts before a delivery becomes a dead letter.
+    pub max_attempts: i32,
+    // Timeout of a single attempt.
+    pub timeout: Duration,
+    // Delay before the second attempt, doubled for each following one.
+    pub backoff: Duration,
+    // Lets the webhooks target loopback and private addresses, for local development.
+    pub allow_private_targets: bool,
+}
+
+impl OutboxConfig {
+    pub fn from_env() -> Self {
+        let number = |name: &str, default: u64| {
+            env::var(name)
+                .map(|value| value.parse().expect("Outbox settings must be integers"))
+                .unwrap_or(default)
+        };
+        OutboxConfig {
+            poll_interval: Duration::from_secs(number("OUTBOX_POLL_INTERVAL_SECS", 5)),
+            batch_size: number("OUTBOX_BATCH_SIZE", 50) as i64,
+            max_attempts: number("WEBHOOK_MAX_ATTEMPTS", 8) as i32,
+            timeout: Duration::from_secs(number("WEBHOOK_TIMEOUT_SECS", 10)),
+            backoff: Duration::from_secs(number("WEBHOOK_BACKOFF_SECS", 30)),
+            allow_private_targets: env::var("WEBHOOK_ALLOW_PRIVATE_TARGETS")
+                .is_ok_and(|value| value == "true"),
+        }
+    }
+
+    // The delay before the next attempt, after `attempts` failed ones.
+    fn backoff_after(&self, attempts: i32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1).max(0) as u32);
+        self.backoff
+            .checked_mul(factor)
+            .map_or(MAX_BACKOFF, |delay| delay.min(MAX_BACKOFF))
+    }
+}
+
+// `t=<unix time>,v1=<hex HMAC-SHA256 of "<unix time>.<body>">`; receivers should recompute it
+// and reject old timestamps to prevent replays.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("t={},v1={:x}", timestamp, mac.finalize().into_bytes())
+}
+
+// Resolves the host of a webhook url, and refuses it when one of its addresses is not public.
+// Checked when the webhook is registered and again before each attempt, since the name may
+// resolve elsewhere by then.
+pub async fn resolve_target(
+    url: &str,
+    allow_private_targets: bool,
+) -> Result<(String, SocketAddr), String> {
+    let url = reqwest::Url::parse(url).map_err(|err| format!("Invalid url: {}", err))?;
+    let host = url
+        .host_str()
+        .ok_or("The url has no host")?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_owned();
+    let port = url.port_or_known_default().ok_or("The url has no port")?;
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .map_err(|err| format!("Unable to resolve {}: {}", host, err))?
+        .collect();
+    if !allow_private_targets {
+        if let Some(address) = addresses
+            .iter()
+            .find(|address| !is_public_address(address.ip()))
+        {
+            return Err(format!(
+                "{} resolves to the non-public address {}",
+                host,
+                address.ip()
+            ));
+        }
+    }
+    match addresses.first() {
+        Some(address) => Ok((host, *address)),
+        None => Err(format!("{} has no address", host)),
+    }
+}
+
+async fn send(config: &OutboxConfig, delivery: &DueDelivery) -> Result<(), String> {
+    let (host, address) = resolve_target(&delivery.url, config.allow_private_targets).await?;
+    // Pinned to the checked address, and without redirects, which would skip the check.
+    let client = reqwest::Client::builder()
+        .timeout(config.timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve(&host, address)
+        .build()
+        .map_err(|err| err.to_string())?;
+    let body = serde_json::to_vec(&OutboxEvent::from(delivery)).map_err(|err| err.to_string())?;
+    let signature = sign(&delivery.secret, Utc::now().timestamp(), &body);
+    let resp = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header(EVENT_HEADER, &delivery.event_type)
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    if resp.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("The webhook answered {}", resp.status()))
+    }
+}
+
+// Sends the due deliveries once, and returns how many were attempted.
+pub async fn deliver_due_webhooks(pool: &PgPool, config: &OutboxConfig) -> Result<usize, MyError> {
+    // The lease outlives the attempts of the batch, which are made one after the other.
+    let lease = config.timeout.as_secs_f64() * (config.batch_size as f64 + 1.0);
+    let deliveries = claim_due_deliveries_db(pool, config.batch_size, lease).await?;
+    for delivery in &deliveries {
+        match send(config, delivery).await {
+            Ok(()) => mark_delivery_delivered_db(pool, delivery.id).await?,
+            Err(error) => {
+                let attempts = delivery.attempts + 1;
+                let next_attempt_at = if attempts < config.max_attempts {
+                    chrono::Duration::from_std(config.backoff_after(attempts))
+                        .ok()
+                        .map(|delay| Utc::now() + delay)
+                } else {
+                    None
+                };
+                tracing::warn!(
+                    delivery_id = delivery.id,
+                    attempts,
+                    dead = next_attempt_at.is_none(),
+                    "Webhook delivery failed: {}",
+                    error
+                );
+                mark_delivery_failed_db(pool, delivery.id, &error, next_attempt_at).await?;
+            }
+        }
+    }
+    Ok(deliveries.len())
+}
+
+// Delivers the outbox until shutdown, and goes on right away while full batches come back.
+pub async fn run_outbox_worker(app_state: web::Data<AppState>, config: OutboxConfig) {
+    while !app_state.shutting_down.load(Ordering::SeqCst) {
+        match deliver_due_webhooks(&app_state.db, &config).await {
+            Ok(count) if count as i64 == config.batch_size => continue,
+            Ok(_) => {}
+            Err(err) => tracing::error!("Unable to deliver the outbox: {}", err),
+        }
+        sleep_unless_shutting_down(&app_state, config.poll_interval).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::AuditContext;
+    use crate::dbaccesses::course::{create_one_course_db, delete_one_course_db};
+    use crate::models::course::CourseCreation;
+    use crate::models::webhook::WebhookCreation;
+    use actix_web::{App, HttpRequest, HttpResponse, HttpServer};
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::sync::{Arc, Mutex};
+
+    fn test_config() -> OutboxConfig {
+        OutboxConfig {
+            poll_interval: Duration::from_secs(1),
+            batch_size: 100,
+            max_attempts: 2,
+            timeout: Duration::from_secs(5),
+            backoff: Duration::from_secs(0),
+            allow_private_targets: true,
+        }
+    }
+
+    #[test]
+    fn signature_should_cover_the_timestamp_and_the_body() {
+        let signature = sign("0123456789abcdef", 1656633600, b"{}");
+        assert!(signature.starts_with("t=1656633600,v1="));
+        assert_eq!(signature.len(), "t=1656633600,v1=".len() + 64);
+        assert_ne!(signature, sign("0123456789abcdef", 1656633601, b"{}"));
+        assert_ne!(signature, sign("0123456789abcdef", 1656633600, b"[]"));
+    }
+
+    #[actix_rt::test]
+    async fn private_targets_should_be_refused() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://localhost/hook",
+        ] {
+            assert!(resolve_target(url, false).await.is_err(), "{}", url);
+        }
+        assert!(resolve_target("http://127.0.0.1:8080/hook", true)
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn backoff_should_double_up_to_the_maximum() {
+        let config = OutboxConfig {
+            backoff: Duration::from_secs(30),
+            ..test_config()
+        };
+        assert_eq!(config.backoff_after(1), Duration::from_secs(30));
+        assert_eq!(config.backoff_after(3), Duration::from_secs(120));
+        assert_eq!(config.backoff_after(40), MAX_BACKOFF);
+    }
+
+    type Received = Arc<Mutex<Vec<(String, String, web::Bytes)>>>;
+
+    // Records the requests, and fails those sent to `/failing`.
+    async fn stub(
+        req: HttpRequest,
+        body: web::Bytes,
+        received: web::Data<Received>,
+    ) -> HttpResponse {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_owned()
+        };
+        received
+            .lock()
+            .unwrap()
+            .push((header(EVENT_HEADER), header(SIGNATURE_HEADER), body));
+        if req.path() == "/failing" {
+            HttpResponse::InternalServerError().finish()
+        } else {
+            HttpResponse::Ok().finish()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn course_creation_should_be_delivered_signed() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let received: Received = Arc::new(Mutex::new(Vec::new()));
+        let data = web::Data::new(received.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .default_service(web::to(stub))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_rt::spawn(server.run());
+
+        let secret = "0123456789abcdef".to_owned();
+        let webhook = create_one_webhook_db(
+            &pool,
+            WebhookCreation {
+                url: format!("http://{}/hook", address),
+                secret: secret.clone(),
+            },
+        )
+        .await
+        .unwrap();
+        let failing = create_one_webhook_db(
+            &pool,
+            WebhookCreation {
+                url: format!("http://{}/failing", address),
+                secret: secret.clone(),
+            },
+        )
+        .await
+        .unwrap();
+        let course = create_one_course_db(
+            &pool,
+            CourseCreation {
+                teacher_id: 1,
+                name: "Announced course".into(),
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: None,
+                language: None,
+                level: None,
+            },
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+
+        let config = test_config();
+        deliver_due_webhooks(&pool, &config).await.unwrap();
+        {
+            let received = received.lock().unwrap();
+            let (_, signature, body) = received
+                .iter()
+                .find(|(event_type, _, body)| {
+                    let event: serde_json::Value = serde_json::from_slice(body).unwrap();
+                    event_type == "CourseCreated" && event["entity_id"] == course.id
+                })
+                .expect("CourseCreated was not delivered");
+            let timestamp: i64 = signature[2..signature.find(',').unwrap()].parse().unwrap();
+            assert_eq!(signature, &sign(&secret, timestamp, body));
+        }
+
+        // The failing webhook runs out of attempts and ends in the dead letters.
+        deliver_due_webhooks(&pool, &config).await.unwrap();
+        let dead = get_dead_deliveries_db(&pool).await.unwrap();
+        let dead = dead
+            .iter()
+            .find(|delivery| delivery.webhook_id == failing.id)
+            .expect("The failing delivery is not dead");
+        assert_eq!(dead.attempts, 2);
+        let retried = retry_one_delivery_db(&pool, dead.id).await.unwrap();
+        assert_eq!(retried.status, "pending");
+
+        delete_one_webhook_db(&pool, webhook.id).await.unwrap();
+        delete_one_webhook_db(&pool, failing.id).await.unwrap();
+        delete_one_course_db(
+            &pool,
+            course.teacher_id,
+            course.id,
+            &AuditContext::default(),
+        )
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,406 @@
+use crate::audit::{client_ip, request_actor, AuditContext};
+use crate::errors::MyError;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::{Error, ResponseError};
+use async_trait::async_trait;
+use futures_util::future::LocalBoxFuture;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MAX_IN_MEMORY_KEYS: usize = 10_000;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    pub capacity: u32,
+    pub refill_per_sec: f64,
+}
+
+impl Quota {
+    // A quota of 0 would never refill, so it is refused.
+    pub fn per_minute(requests: u32) -> Self {
+        assert!(requests > 0, "A quota must allow at least one request");
+        Quota {
+            capacity: requests,
+            refill_per_sec: requests as f64 / 60.0,
+        }
+    }
+
+    fn time_to_refill(&self, tokens: f64) -> Duration {
+        Duration::from_secs_f64((tokens / self.refill_per_sec).max(0.0))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset: Duration,
+    pub retry_after: Duration,
+}
+
+// Takes one token out of the bucket of `key`. Implementations backed by a shared
+// store let several instances of the service enforce the same limits.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    async fn acquire(&self, key: &str, quota: Quota) -> Result<Decision, MyError>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    // The quota the bucket fills up to, which depends on the class of its key.
+    quota: Quota,
+    // The position of the bucket in `Buckets::recency`.
+    used: u64,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        self.tokens = (self.tokens
+            + now.duration_since(self.updated).as_secs_f64() * self.quota.refill_per_sec)
+            .min(self.quota.capacity as f64);
+        self.updated = now;
+    }
+
+    // A full bucket holds no state worth keeping.
+    fn is_full(&self, now: Instant) -> bool {
+        self.tokens + now.duration_since(self.updated).as_secs_f64() * self.quota.refill_per_sec
+            >= self.quota.capacity as f64
+    }
+}
+
+// The buckets with the keys from the least to the most recently used, so that the
+// least recently used one goes first once `max_keys` are held.
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    recency: BTreeMap<u64, String>,
+    next_use: u64,
+    last_sweep: Instant,
+}
+
+pub struct InMemoryStore {
+    buckets: Mutex<Buckets>,
+    max_keys: usize,
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        InMemoryStore::with_max_keys(MAX_IN_MEMORY_KEYS)
+    }
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        InMemoryStore::default()
+    }
+
+    pub fn with_max_keys(max_keys: usize) -> Self {
+        InMemoryStore {
+            buckets: Mutex::new(Buckets {
+                by_key: HashMap::new(),
+                recency: BTreeMap::new(),
+                next_use: 0,
+                last_sweep: Instant::now(),
+            }),
+            max_keys,
+        }
+    }
+}
+
+impl Buckets {
+    // Drops the full buckets, at most once per `SWEEP_INTERVAL`.
+    fn sweep(&mut self, now: Instant) {
+        if now.duration_since(self.last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        self.last_sweep = now;
+        let recency = &mut self.recency;
+        self.by_key.retain(|_, bucket| {
+            let keep = !bucket.is_full(now);
+            if !keep {
+                recency.remove(&bucket.used);
+            }
+            keep
+        });
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self.recency.keys().next().copied();
+        if let Some(key) = oldest.and_then(|used| self.recency.remove(&used)) {
+            self.by_key.remove(&key);
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn acquire(&self, key: &str, quota: Quota) -> Result<Decision, MyError> {
+        let now = Instant::now();
+        let capacity = quota.capacity as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.sweep(now);
+        if !buckets.by_key.contains_key(key) && buckets.by_key.len() >= self.max_keys {
+            buckets.evict_least_recently_used();
+        }
+        let used = buckets.next_use;
+        buckets.next_use += 1;
+        buckets.recency.insert(used, key.to_owned());
+        let previous = buckets.by_key.get(key).map(|bucket| bucket.used);
+        if let Some(previous) = previous {
+            buckets.recency.remove(&previous);
+        }
+        let bucket = buckets.by_key.entry(key.to_owned()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            quota,
+            used,
+        });
+        bucket.quota = quota;
+        bucket.used = used;
+        bucket.refill(now);
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        Ok(Decision {
+            allowed,
+            limit: quota.capacity,
+            remaining: bucket.tokens.floor() as u32,
+            reset: quota.time_to_refill(capacity - bucket.tokens),
+            retry_after: quota.time_to_refill(1.0 - bucket.tokens),
+        })
+    }
+}
+
+// The actor named by a trusted proxy, else the address of the client, so that the
+// clients behind a proxy don't share its buckets.
+fn client_key(req: &ServiceRequest) -> String {
+    match request_actor(req.request()) {
+        Ok(actor) if actor != AuditContext::default().actor => format!("actor:{}", actor),
+        _ => format!("ip:{}", client_ip(req.request())),
+    }
+}
+
+fn insert_headers(headers: &mut HeaderMap, decision: &Decision) {
+    let pairs = [
+        ("ratelimit-limit", decision.limit as u64),
+        ("ratelimit-remaining", decision.remaining as u64),
+        ("ratelimit-reset", decision.reset.as_secs_f64().ceil() as u64),
+    ];
+    for (name, value) in pairs {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+}
+
+// Token bucket limiter with separate quotas for reads (safe methods) and writes.
+// Clients are told apart by their actor or their IP.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    read_quota: Quota,
+    write_quota: Quota,
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>, read_quota: Quota, write_quota: Quota) -> Self {
+        RateLimiter {
+            store,
+            read_quota,
+            write_quota,
+        }
+    }
+
+    pub fn from_env(store: Arc<dyn RateLimitStore>) -> Self {
+        let per_minute = |name: &str, default: u32| {
+            env::var(name)
+                .map(|value| match value.parse() {
+                    Ok(requests) if requests > 0 => requests,
+                    _ => panic!("{} must be a positive integer", name),
+                })
+                .unwrap_or(default)
+        };
+        RateLimiter::new(
+            store,
+            Quota::per_minute(per_minute("RATE_LIMIT_READS_PER_MINUTE", 600)),
+            Quota::per_minute(per_minute("RATE_LIMIT_WRITES_PER_MINUTE", 60)),
+        )
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            limiter: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limiter = self.limiter.clone();
+        Box::pin(async move {
+            let (class, quota) = if req.method().is_safe() {
+                ("read", limiter.read_quota)
+            } else {
+                ("write", limiter.write_quota)
+            };
+            let key = format!("{}:{}", class, client_key(&req));
+            let decision = limiter.store.acquire(&key, quota).await?;
+            if decision.allowed {
+                let mut res = service.call(req).await?;
+                insert_headers(res.headers_mut(), &decision);
+                return Ok(res.map_into_left_body());
+            }
+            let mut resp =
+                MyError::TooManyRequests("Too many requests, please retry later".into())
+                    .error_response();
+            insert_headers(resp.headers_mut(), &decision);
+            resp.headers_mut().insert(
+                RETRY_AFTER,
+                HeaderValue::from(decision.retry_after.as_secs_f64().ceil() as u64),
+            );
+            Ok(req.into_response(resp).map_into_right_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::security::AccessConfig;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[actix_rt::test]
+    async fn writes_over_quota_should_be_throttled() {
+        let limiter = RateLimiter::new(
+            Arc::new(InMemoryStore::new()),
+            Quota::per_minute(10),
+            Quota::per_minute(1),
+        );
+        let app = test::init_service(
+            App::new().wrap(limiter).route(
+                "/",
+                web::route().to(|| async { HttpResponse::Ok().finish() }),
+            ),
+        )
+        .await;
+        let peer = "10.0.0.1:4000".parse().unwrap();
+
+        let req = test::TestRequest::post().uri("/").peer_addr(peer).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("ratelimit-remaining").unwrap(), "0");
+
+        let req = test::TestRequest::post().uri("/").peer_addr(peer).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get(RETRY_AFTER).unwrap(), "60");
+
+        let req = test::TestRequest::get().uri("/").peer_addr(peer).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let other_peer = "10.0.0.2:4000".parse().unwrap();
+        let req = test::TestRequest::post()
+            .uri("/")
+            .peer_addr(other_peer)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn clients_behind_a_trusted_proxy_should_be_throttled_apart() {
+        let limiter = RateLimiter::new(
+            Arc::new(InMemoryStore::new()),
+            Quota::per_minute(10),
+            Quota::per_minute(1),
+        );
+        let access = AccessConfig {
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+            ..AccessConfig::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(access))
+                .wrap(limiter)
+                .route(
+                    "/",
+                    web::route().to(|| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+        let proxy = "10.0.0.1:4000".parse().unwrap();
+        for (client, status) in [
+            ("203.0.113.1", StatusCode::OK),
+            ("203.0.113.2", StatusCode::OK),
+            ("203.0.113.1", StatusCode::TOO_MANY_REQUESTS),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/")
+                .peer_addr(proxy)
+                .insert_header(("X-Forwarded-For", client))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status);
+        }
+    }
+
+    #[actix_rt::test]
+    #[should_panic(expected = "at least one request")]
+    async fn empty_quotas_should_be_refused() {
+        Quota::per_minute(0);
+    }
+
+    #[actix_rt::test]
+    async fn least_recently_used_keys_should_be_evicted_first() {
+        let store = InMemoryStore::with_max_keys(2);
+        let quota = Quota::per_minute(1);
+        assert!(store.acquire("a", quota).await.unwrap().allowed);
+        assert!(store.acquire("b", quota).await.unwrap().allowed);
+        assert!(!store.acquire("a", quota).await.unwrap().allowed);
+        // "b" is now the least recently used key and makes room for "c".
+        assert!(store.acquire("c", quota).await.unwrap().allowed);
+        assert!(!store.acquire("a", quota).await.unwrap().allowed);
+        assert!(store.acquire("b", quota).await.unwrap().allowed);
+        let buckets = store.buckets.lock().unwrap();
+        assert_eq!(buckets.by_key.len(), 2);
+        assert_eq!(buckets.recency.len(), 2);
+    }
+}
@@ -0,0 +1,433 @@
+use crate::audit::Viewer;
+use crate::dbaccesses::course::get_one_visible_course_db;
+use crate::dbaccesses::teacher::get_one_teacher_detail_db;
+use crate::errors::MyError;
+use crate::models::course::Course;
+use crate::models::teacher::Teacher;
+use crate::shutdown::{sleep_unless_shutting_down, wait_for_shutdown};
+use crate::state::AppState;
+use actix_web::web;
+use sqlx::postgres::{PgListener, PgPool};
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Notified by the `teacher` and `course` triggers with `teacher:<id>` or `course:<id>`.
+pub const READ_CACHE_CHANNEL: &str = "read_cache";
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+struct Entry<V> {
+    value: V,
+    expires_at: Instant,
+}
+
+struct Entries<V> {
+    entries: HashMap<i32, Entry<V>>,
+    // Bumped by every invalidation, so that a load which started before it is not kept.
+    generation: u64,
+}
+
+struct Stats {
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    entries: u64,
+}
+
+// The name and the type of a metric, along with the stat it reports.
+type MetricFamily = (&'static str, &'static str, fn(&Stats) -> u64);
+
+// The rows found by id, kept until they expire or are invalidated. Missing rows are not kept.
+pub struct LookupCache<V> {
+    entries: Mutex<Entries<V>>,
+    max_entries: usize,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<V: Clone> LookupCache<V> {
+    pub fn new(max_entries: usize, ttl: Duration) -> Self {
+        LookupCache {
+            entries: Mutex::new(Entries {
+                entries: HashMap::new(),
+                generation: 0,
+            }),
+            max_entries,
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn get_or_load<F, Fut>(&self, id: i32, load: F) -> Result<V, MyError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, MyError>>,
+    {
+        let generation = {
+            let entries = self.entries.lock().unwrap();
+            if let Some(entry) = entries.entries.get(&id) {
+                if entry.expires_at > Instant::now() {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(entry.value.clone());
+                }
+            }
+            entries.generation
+        };
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let value = load().await?;
+        if self.max_entries > 0 {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.generation == generation {
+                self.insert(&mut entries, id, value.clone());
+            }
+        }
+        Ok(value)
+    }
+
+    fn insert(&self, entries: &mut Entries<V>, id: i32, value: V) {
+        let now = Instant::now();
+        if entries.entries.len() >= self.max_entries && !entries.entries.contains_key(&id) {
+            let before = entries.entries.len();
+            entries.entries.retain(|_, entry| entry.expires_at > now);
+            // Still full of live entries, the one expiring first goes.
+            if entries.entries.len() >= self.max_entries {
+                let oldest = entries
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires_at)
+                    .map(|(id, _)| *id);
+                if let Some(oldest) = oldest {
+                    entries.entries.remove(&oldest);
+                }
+            }
+            let evicted = before - entries.entries.len();
+            self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+        }
+        let expires_at = now + self.ttl;
+        entries.entries.insert(id, Entry { value, expires_at });
+    }
+
+    pub fn invalidate(&self, id: i32) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.entries.remove(&id);
+        entries.generation += 1;
+    }
+
+    pub fn invalidate_all(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.entries.clear();
+        entries.generation += 1;
+    }
+
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().entries.len()
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: self.len() as u64,
+        }
+    }
+}
+
+// The teachers and courses looked up by id, shared by the requests of an instance.
+pub struct ReadCache {
+    teachers: LookupCache<Teacher>,
+    courses: LookupCache<Course>,
+}
+
+impl Default for ReadCache {
+    fn default() -> Self {
+        ReadCache::new(DEFAULT_MAX_ENTRIES, DEFAULT_TTL)
+    }
+}
+
+impl ReadCache {
+    // Holds up to `max_entries` teachers and as many courses, none when it is 0.
+    pub fn new(max_entries: usize, ttl: Duration) -> Self {
+        ReadCache {
+            teachers: LookupCache::new(max_entries, ttl),
+            courses: LookupCache::new(max_entries, ttl),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let max_entries = env::var("READ_CACHE_MAX_ENTRIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_ENTRIES);
+        let ttl = env::var("READ_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TTL);
+        ReadCache::new(max_entries, ttl)
+    }
+
+    pub async fn teacher(&self, pool: &PgPool, teacher_id: i32) -> Result<Teacher, MyError> {
+        self.teachers
+            .get_or_load(teacher_id, || get_one_teacher_detail_db(pool, teacher_id))
+            .await
+    }
+
+    // Only the published courses are cached, the others are `NotFound`.
+    pub async fn course(&self, pool: &PgPool, course_id: i32) -> Result<Course, MyError> {
+        self.courses
+            .get_or_load(course_id, || {
+                get_one_visible_course_db(pool, course_id, None)
+            })
+            .await
+    }
+
+    // A teacher also sees their own courses that aren't published, past the cache.
+    pub async fn visible_course(
+        &self,
+        pool: &PgPool,
+        viewer: Viewer,
+        course_id: i32,
+    ) -> Result<Course, MyError> {
+        match viewer.teacher_id {
+            Some(teacher_id) => get_one_visible_course_db(pool, course_id, Some(teacher_id)).await,
+            None => self.course(pool, course_id).await,
+        }
+    }
+
+    // The course of `teacher_id` only, as `visible_course` finds it.
+    pub async fn course_of_teacher(
+        &self,
+        pool: &PgPool,
+        viewer: Viewer,
+        teacher_id: i32,
+        course_id: i32,
+    ) -> Result<Course, MyError> {
+        match self.visible_course(pool, viewer, course_id).await {
+            Ok(course) if course.teacher_id != teacher_id => {
+                Err(MyError::NotFound("Course not found".into()))
+            }
+            result => result,
+        }
+    }
+
+    pub fn invalidate_teacher(&self, teacher_id: i32) {
+        self.teachers.invalidate(teacher_id);
+    }
+
+    pub fn invalidate_course(&self, course_id: i32) {
+        self.courses.invalidate(course_id);
+    }
+
+    pub fn invalidate_all(&self) {
+        self.teachers.invalidate_all();
+        self.courses.invalidate_all();
+    }
+
+    // Applies a `teacher:<id>` or `course:<id>` notification.
+    fn invalidate_notified(&self, payload: &str) -> Result<(), MyError> {
+        let invalid = || MyError::DBError(format!("Invalid notification {}", payload));
+        let (table, id) = payload.split_once(':').ok_or_else(invalid)?;
+        let id = id.parse().map_err(|_| invalid())?;
+        match table {
+            "teacher" => self.invalidate_teacher(id),
+            "course" => self.invalidate_course(id),
+            _ => return Err(invalid()),
+        }
+        Ok(())
+    }
+
+    // The hit and miss counters and the sizes of the caches, in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        let caches = [
+            ("teacher", self.teachers.stats()),
+            ("course", self.courses.stats()),
+        ];
+        let families: [MetricFamily; 4] = [
+            ("read_cache_hits_total", "counter", |stats| stats.hits),
+            ("read_cache_misses_total", "counter", |stats| stats.misses),
+            ("read_cache_evictions_total", "counter", |stats| {
+                stats.evictions
+            }),
+            ("read_cache_entries", "gauge", |stats| stats.entries),
+        ];
+        let mut metrics = String::new();
+        for (family, kind, value) in families {
+            let _ = writeln!(metrics, "# TYPE {} {}", family, kind);
+            for (cache, stats) in &caches {
+                let _ = writeln!(
+                    metrics,
+                    "{}{{cache=\"{}\"}} {}",
+                    family,
+                    cache,
+                    value(stats)
+                );
+            }
+        }
+        metrics
+    }
+}
+
+// Drops the teachers and courses changed by any instance from the cache of this one.
+pub struct ReadCacheListener {
+    listener: PgListener,
+}
+
+impl ReadCacheListener {
+    pub async fn connect(pool: &PgPool) -> Result<Self, MyError> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(READ_CACHE_CHANNEL).await?;
+        Ok(ReadCacheListener { listener })
+    }
+
+    // Returns on shutdown, which gives its connection back to the pool.
+    pub async fn run(mut self, app_state: web::Data<AppState>) {
+        while !app_state.shutting_down.load(Ordering::SeqCst) {
+            tokio::select! {
+                result = self.receive(&app_state.read_cache) => if let Err(err) = result {
+                    tracing::error!("Unable to receive the read cache invalidations: {}", err);
+                    sleep_unless_shutting_down(&app_state, RECONNECT_DELAY).await;
+                },
+                _ = wait_for_shutdown(&app_state) => {}
+            }
+        }
+    }
+
+    async fn receive(&mut self, read_cache: &ReadCache) -> Result<(), MyError> {
+        match self.listener.try_recv().await? {
+            Some(notification) => read_cache.invalidate_notified(notification.payload()),
+            // The connection was lost, which `PgListener` restores by itself, and with it
+            // the notifications sent in the meantime.
+            None => {
+                read_cache.invalidate_all();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::AuditContext;
+    use crate::dbaccesses::teacher::update_one_teacher_detail_db;
+    use crate::models::teacher::TeacherUpdate;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::sync::atomic::AtomicBool;
+
+    async fn load(loads: &AtomicU64, value: &str) -> Result<String, MyError> {
+        loads.fetch_add(1, Ordering::SeqCst);
+        Ok(value.to_owned())
+    }
+
+    #[actix_rt::test]
+    async fn lookups_should_be_cached_until_invalidated() {
+        let cache = LookupCache::new(2, Duration::from_secs(60));
+        let loads = AtomicU64::new(0);
+        assert_eq!(
+            cache.get_or_load(1, || load(&loads, "a")).await.unwrap(),
+            "a"
+        );
+        assert_eq!(
+            cache.get_or_load(1, || load(&loads, "b")).await.unwrap(),
+            "a"
+        );
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        cache.invalidate(1);
+        assert_eq!(
+            cache.get_or_load(1, || load(&loads, "b")).await.unwrap(),
+            "b"
+        );
+        // Full, the entry expiring first is evicted.
+        cache.get_or_load(2, || load(&loads, "c")).await.unwrap();
+        cache.get_or_load(3, || load(&loads, "d")).await.unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.get_or_load(1, || load(&loads, "e")).await.unwrap(),
+            "e"
+        );
+        assert_eq!(cache.hits.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.misses.load(Ordering::SeqCst), 5);
+        assert_eq!(cache.evictions.load(Ordering::SeqCst), 2);
+        let err = cache
+            .get_or_load(4, || async { Err(MyError::NotFound("Not found".into())) })
+            .await;
+        assert!(err.is_err());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn loads_racing_an_invalidation_should_not_be_kept() {
+        let cache = LookupCache::new(10, Duration::from_secs(60));
+        let value = cache
+            .get_or_load(1, || async {
+                cache.invalidate(1);
+                Ok("stale".to_owned())
+            })
+            .await
+            .unwrap();
+        assert_eq!(value, "stale");
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn expired_entries_should_be_reloaded() {
+        let cache = LookupCache::new(10, Duration::ZERO);
+        let loads = AtomicU64::new(0);
+        cache.get_or_load(1, || load(&loads, "a")).await.unwrap();
+        cache.get_or_load(1, || load(&loads, "a")).await.unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn notified_changes_should_be_invalidated() {
+        dotenv().ok();
+        let url_db = env::var("DATABASE_URL").expect("DATABASE_URL is missing");
+        let pool = PgPoolOptions::new().connect(&url_db).await.unwrap();
+        let app_state = web::Data::new(AppState {
+            health_check_response: "".to_owned(),
+            visit_count: Mutex::new(0),
+            shutting_down: AtomicBool::new(false),
+            read_cache: ReadCache::default(),
+            db: pool.clone(),
+        });
+        let listener = ReadCacheListener::connect(&pool).await.unwrap();
+        actix_rt::spawn(listener.run(app_state.clone()));
+        let teacher = app_state.read_cache.teacher(&pool, 1).await.unwrap();
+        // Written as another instance would, without invalidating this cache.
+        let teacher_update = TeacherUpdate {
+            name: None,
+            profile: Some(teacher.profile.clone()),
+        };
+        update_one_teacher_detail_db(&pool, 1, teacher_update, &AuditContext::default())
+            .await
+            .unwrap();
+        let updated = actix_rt::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let cached = app_state.read_cache.teacher(&pool, 1).await.unwrap();
+                if cached.updated_at != teacher.updated_at {
+                    return cached;
+                }
+                actix_rt::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("The teacher was not invalidated");
+        assert_eq!(updated.profile, teacher.profile);
+        assert!(app_state
+            .read_cache
+            .metrics()
+            .contains("read_cache_hits_total{cache=\"teacher\"}"));
+    }
+}
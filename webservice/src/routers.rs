@@ -1,14 +1,178 @@
-use crate::handlers::{course::*, general::*, teacher::*};
+use crate::handlers::{
+    audit::*, course::*, curriculum::*, event::*, general::*, graphql::*, material::*, media::*,
+    review::*, revision::*, session::*, teacher::*, webhook::*,
+};
+use crate::openapi::ApiDoc;
+use crate::security::{json_config, BodyLimit};
+use crate::versioning::{api_scope, Deprecation};
 
 use actix_web::web;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+const TEACHER_JSON_LIMIT: usize = 8 * 1024;
+const COURSE_JSON_LIMIT: usize = 16 * 1024;
+
+// A route of the versioned API: its method, its path under the version prefix and the
+// name of its handler, which is also the operation id of the handler in the API spec.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug)]
+pub struct Route {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub handler: &'static str,
+}
+
+// Declares the routes under `$prefix` once, both as the table `$table` and as the function
+// `$resources` registering them, so the table can't drift from what is served.
+macro_rules! routes {
+    ($table:ident, $resources:ident, $prefix:literal, [
+        $($method:ident $path:literal => $handler:ident,)*
+    ]) => {
+        #[cfg(test)]
+        pub const $table: &[Route] = &[$(Route {
+            method: stringify!($method),
+            path: concat!($prefix, $path),
+            handler: stringify!($handler),
+        },)*];
+
+        fn $resources(cfg: &mut web::ServiceConfig) {
+            $(cfg.route($path, web::$method().to($handler));)*
+        }
+    };
+}
 
 pub fn routes_general(cfg: &mut web::ServiceConfig) {
-    cfg.route("/health", web::get().to(health_check_handler));
+    cfg.route("/health", web::get().to(health_check_handler))
+        .route("/ready", web::get().to(readiness_check_handler))
+        .route("/metrics", web::get().to(metrics_handler))
+        .route("/media/{key:.*}", web::get().to(get_media));
+}
+
+pub fn routes_openapi(cfg: &mut web::ServiceConfig) {
+    cfg.service(SwaggerUi::new("/swagger-ui/{_:.*}").url("/openapi.json", ApiDoc::openapi()));
+}
+
+// GraphQL has a single unversioned endpoint, its schema evolves by deprecating fields.
+pub fn routes_graphql(cfg: &mut web::ServiceConfig, graphiql_enabled: bool) {
+    cfg.route("/graphql", web::post().to(graphql));
+    if graphiql_enabled {
+        cfg.route("/graphql", web::get().to(graphiql));
+    }
+}
+
+pub fn routes_v1(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        api_scope("v1")
+            .configure(routes_admin)
+            .configure(routes_course)
+            .configure(routes_event)
+            .configure(routes_session)
+            .configure(routes_teacher),
+    );
+}
+
+// Every route of `routes_v1`, which the API spec has to document.
+#[cfg(test)]
+pub const V1_ROUTES: &[&[Route]] = &[
+    ADMIN_ROUTES,
+    COURSE_ROUTES,
+    EVENT_ROUTES,
+    SESSION_ROUTES,
+    TEACHER_ROUTES,
+];
+
+// The unversioned routes predate `/api/v1` and are kept as deprecated aliases,
+// so they have to be registered after every other scope.
+pub fn routes_legacy(cfg: &mut web::ServiceConfig, deprecation: &Deprecation) {
+    cfg.service(
+        web::scope("")
+            .wrap(deprecation.headers())
+            .configure(routes_course_legacy)
+            .configure(routes_teacher),
+    );
 }
 
 pub fn routes_course(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/courses")
+            .app_data(json_config(COURSE_JSON_LIMIT))
+            .app_data(BodyLimit(COURSE_JSON_LIMIT))
+            .configure(course_resources),
+    );
+}
+
+routes!(COURSE_ROUTES, course_resources, "/courses", [
+    get "/{course_id}" => get_one_course_by_id,
+    put "/{course_id}" => update_one_course_by_id,
+    delete "/{course_id}" => delete_one_course_by_id,
+    get "/{course_id}/transitions" => get_transitions_for_course,
+    post "/{course_id}/transitions" => transition_one_course,
+    get "/{course_id}/revisions" => get_revisions_for_course,
+    get "/{course_id}/revisions/diff" => diff_revisions,
+    get "/{course_id}/revisions/{revision}" => get_one_revision,
+    post "/{course_id}/revisions/{revision}/restore" => restore_one_revision,
+    post "/{course_id}/materials" => upload_one_course_material,
+    get "/{course_id}/materials" => get_materials_for_course,
+    get "/{course_id}/materials/{material_id}" => download_one_course_material,
+    delete "/{course_id}/materials/{material_id}" => delete_one_course_material,
+    get "/{course_id}/sections" => get_curriculum_for_course,
+    post "/{course_id}/sections" => create_one_section,
+    // Registered before `{section_id}`, which would match `order` too.
+    put "/{course_id}/sections/order" => reorder_sections,
+    put "/{course_id}/sections/{section_id}" => update_one_section,
+    delete "/{course_id}/sections/{section_id}" => delete_one_section,
+    post "/{course_id}/sections/{section_id}/lessons" => create_one_lesson,
+    put "/{course_id}/sections/{section_id}/lessons/order" => reorder_lessons,
+    put "/{course_id}/sections/{section_id}/lessons/{lesson_id}" => update_one_lesson,
+    delete "/{course_id}/sections/{section_id}/lessons/{lesson_id}" => delete_one_lesson,
+    get "/{course_id}/reviews" => get_reviews_for_course,
+    post "/{course_id}/reviews" => create_one_review,
+    get "/{course_id}/reviews/{review_id}" => get_one_review,
+    put "/{course_id}/reviews/{review_id}" => update_one_review,
+    delete "/{course_id}/reviews/{review_id}" => delete_one_review,
+    put "/{course_id}/reviews/{review_id}/moderation" => moderate_one_review,
+    get "/{course_id}/sessions" => get_sessions_for_course,
+    post "/{course_id}/sessions" => create_one_session,
+    get "/{course_id}/sessions.ics" => get_course_calendar,
+    delete "/{course_id}/sessions/{session_id}" => delete_one_session,
+]);
+
+pub fn routes_admin(cfg: &mut web::ServiceConfig) {
+    admin_resources(cfg);
+}
+
+routes!(ADMIN_ROUTES, admin_resources, "", [
+    get "/admin/audit-events" => get_audit_events,
+    get "/admin/webhooks" => get_all_webhooks,
+    post "/admin/webhooks" => create_one_webhook,
+    delete "/admin/webhooks/{webhook_id}" => delete_one_webhook,
+    get "/admin/webhook-deliveries/dead" => get_dead_deliveries,
+    post "/admin/webhook-deliveries/{delivery_id}/retry" => retry_one_delivery,
+]);
+
+pub fn routes_event(cfg: &mut web::ServiceConfig) {
+    event_resources(cfg);
+}
+
+routes!(EVENT_ROUTES, event_resources, "", [
+    get "/events" => get_catalog_events,
+]);
+
+pub fn routes_session(cfg: &mut web::ServiceConfig) {
+    session_resources(cfg);
+}
+
+routes!(SESSION_ROUTES, session_resources, "", [
+    get "/sessions" => get_upcoming_sessions,
+]);
+
+// `teacher_id` used to be taken from the body on creation and from the path otherwise.
+pub fn routes_course_legacy(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/courses")
+            .app_data(json_config(COURSE_JSON_LIMIT))
+            .app_data(BodyLimit(COURSE_JSON_LIMIT))
             .route("/", web::post().to(create_one_course))
             .route("/{teacher_id}", web::get().to(get_courses_for_teacher))
             .route(
@@ -29,10 +193,21 @@ pub fn routes_course(cfg: &mut web::ServiceConfig) {
 pub fn routes_teacher(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/teachers")
-            .route("/", web::post().to(create_one_teacher))
-            .route("/", web::get().to(get_all_teachers))
-            .route("/{teacher_id}", web::get().to(get_one_teacher_detail))
-            .route("/{teacher_id}", web::put().to(update_one_teacher_detail))
-            .route("/{teacher_id}", web::delete().to(delete_one_teacher)),
+            .app_data(json_config(TEACHER_JSON_LIMIT))
+            .app_data(BodyLimit(TEACHER_JSON_LIMIT))
+            .configure(teacher_resources),
     );
 }
+
+routes!(TEACHER_ROUTES, teacher_resources, "/teachers", [
+    post "/" => create_one_teacher,
+    get "/" => get_all_teachers,
+    get "/{teacher_id}" => get_one_teacher_detail,
+    put "/{teacher_id}" => update_one_teacher_detail,
+    delete "/{teacher_id}" => delete_one_teacher,
+    post "/{teacher_id}/picture" => upload_one_teacher_picture,
+    post "/{teacher_id}/courses" => create_one_course_for_teacher,
+    get "/{teacher_id}/courses" => get_courses_for_teacher,
+    get "/{teacher_id}/drafts" => get_unpublished_courses_for_teacher,
+    get "/{teacher_id}/sessions.ics" => get_teacher_calendar,
+]);
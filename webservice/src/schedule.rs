@@ -0,0 +1,445 @@
+use crate::errors::MyError;
+use crate::models::session::ScheduledSession;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, Tz};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+}
+
+// The subset of the iCalendar RRULE we expand ourselves: FREQ, INTERVAL, COUNT and UNTIL.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl FromStr for Recurrence {
+    type Err = MyError;
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let invalid = |msg: &str| MyError::InvalidInput(format!("Invalid recurrence: {}", msg));
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut count = None;
+        let mut until = None;
+        for part in rule.trim().trim_start_matches("RRULE:").split(';') {
+            let (name, value) = part.split_once('=').ok_or_else(|| invalid(part))?;
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Some(Frequency::Daily),
+                        "WEEKLY" => Some(Frequency::Weekly),
+                        _ => return Err(invalid("FREQ must be DAILY or WEEKLY")),
+                    }
+                }
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(|| invalid("INTERVAL must be a positive number"))?
+                }
+                "COUNT" => {
+                    count = Some(
+                        value
+                            .parse()
+                            .map_err(|_err| invalid("COUNT must be a number"))?,
+                    )
+                }
+                "UNTIL" => {
+                    let until_time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ")
+                        .map_err(|_err| invalid("UNTIL must be a UTC time, e.g. 20221231T235959Z"))?;
+                    until = Some(DateTime::from_utc(until_time, Utc))
+                }
+                _ => return Err(invalid(&format!("{} is not supported", name))),
+            }
+        }
+        if count.is_some() && until.is_some() {
+            return Err(invalid("COUNT and UNTIL can't be used together"));
+        }
+        Ok(Recurrence {
+            frequency: frequency.ok_or_else(|| invalid("FREQ is missing"))?,
+            interval,
+            count,
+            until,
+        })
+    }
+}
+
+impl Recurrence {
+    fn period(&self) -> Duration {
+        match self.frequency {
+            Frequency::Daily => Duration::days(self.interval as i64),
+            Frequency::Weekly => Duration::weeks(self.interval as i64),
+        }
+    }
+}
+
+pub fn parse_time_zone(name: &str) -> Result<Tz, MyError> {
+    name.parse()
+        .map_err(|_err| MyError::InvalidInput(format!("Unknown time zone {}", name)))
+}
+
+// Wall-clock times skipped by a daylight saving time change don't exist,
+// and the earliest of the two is used for the ones repeated by it.
+pub fn localize(local: NaiveDateTime, time_zone: Tz) -> Option<DateTime<Utc>> {
+    time_zone
+        .from_local_datetime(&local)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// Like `localize`, but a wall-clock time skipped by a DST change is read with the
+// offset in effect before the change, moving it forward as RFC 5545 does.
+pub fn localize_occurrence(local: NaiveDateTime, time_zone: Tz) -> DateTime<Utc> {
+    localize(local, time_zone).unwrap_or_else(|| {
+        let before = time_zone
+            .offset_from_utc_datetime(&(local - Duration::days(1)))
+            .fix();
+        Utc.from_utc_datetime(&(local - Duration::seconds(before.local_minus_utc() as i64)))
+    })
+}
+
+// Returns the start and end of the occurrences overlapping `from..to`. Recurring
+// sessions keep their wall-clock time in their time zone, across DST changes.
+pub fn occurrences(
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+    time_zone: Tz,
+    recurrence: Option<&Recurrence>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let length = ends_at - starts_at;
+    let recurrence = match recurrence {
+        Some(recurrence) => recurrence,
+        None if starts_at < to && ends_at > from => return vec![(starts_at, ends_at)],
+        None => return Vec::new(),
+    };
+    let period = recurrence.period();
+    let first_local = starts_at.with_timezone(&time_zone).naive_local();
+    // Skips the occurrences that end before `from`, minus one for DST shifts.
+    let skipped = ((from - length - starts_at).num_seconds() / period.num_seconds() - 1).max(0);
+    let mut found = Vec::new();
+    for index in skipped.. {
+        if recurrence.count.is_some_and(|count| index >= count as i64) {
+            break;
+        }
+        let start = localize_occurrence(first_local + period * index as i32, time_zone);
+        if start >= to || recurrence.until.is_some_and(|until| start > until) {
+            break;
+        }
+        if start + length > from {
+            found.push((start, start + length));
+        }
+    }
+    found
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// Lines longer than 75 octets are folded, without splitting a character.
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+// Open-ended recurrences get the offset changes of the next few years.
+const TIME_ZONE_YEARS_AHEAD: i32 = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+struct UtcOffset {
+    seconds: i32,
+    name: String,
+    daylight: bool,
+}
+
+impl UtcOffset {
+    fn at(time_zone: Tz, time: DateTime<Utc>) -> Self {
+        let offset = time_zone.offset_from_utc_datetime(&time.naive_utc());
+        UtcOffset {
+            seconds: offset.fix().local_minus_utc(),
+            name: offset.to_string(),
+            daylight: !offset.dst_offset().is_zero(),
+        }
+    }
+
+    fn format(&self) -> String {
+        let sign = if self.seconds < 0 { '-' } else { '+' };
+        let seconds = self.seconds.abs();
+        let formatted = format!("{}{:02}{:02}", sign, seconds / 3600, seconds % 3600 / 60);
+        match seconds % 60 {
+            0 => formatted,
+            rest => format!("{}{:02}", formatted, rest),
+        }
+    }
+}
+
+// The VTIMEZONE component that RFC 5545 requires for each TZID, with the offset in
+// effect at `from` followed by every change until `to`.
+fn time_zone_component(time_zone: Tz, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<String> {
+    let mut current = UtcOffset::at(time_zone, from);
+    let mut observances = vec![(from, current.clone(), current.clone())];
+    let mut day = from;
+    while day < to {
+        let next_day = day + Duration::days(1);
+        let next = UtcOffset::at(time_zone, next_day);
+        if next != current {
+            let (mut before, mut after) = (day, next_day);
+            while after - before > Duration::seconds(1) {
+                let middle = before + (after - before) / 2;
+                if UtcOffset::at(time_zone, middle) == current {
+                    before = middle;
+                } else {
+                    after = middle;
+                }
+            }
+            observances.push((after, current, next.clone()));
+            current = next;
+        }
+        day = next_day;
+    }
+    let mut lines = vec![
+        "BEGIN:VTIMEZONE".to_owned(),
+        format!("TZID:{}", time_zone.name()),
+    ];
+    for (start, offset_from, offset_to) in observances {
+        let kind = if offset_to.daylight {
+            "DAYLIGHT"
+        } else {
+            "STANDARD"
+        };
+        // The start of an observance is a local time of the offset it replaces.
+        let local_start = start.naive_utc() + Duration::seconds(offset_from.seconds as i64);
+        lines.push(format!("BEGIN:{}", kind));
+        lines.push(format!("DTSTART:{}", local_start.format("%Y%m%dT%H%M%S")));
+        lines.push(format!("TZOFFSETFROM:{}", offset_from.format()));
+        lines.push(format!("TZOFFSETTO:{}", offset_to.format()));
+        lines.push(format!("TZNAME:{}", escape_text(&offset_to.name)));
+        lines.push(format!("END:{}", kind));
+    }
+    lines.push("END:VTIMEZONE".to_owned());
+    lines
+}
+
+// The span of the occurrences of a session, recurrences being covered at most
+// `TIME_ZONE_YEARS_AHEAD` years from now.
+fn session_span(session: &ScheduledSession) -> (DateTime<Utc>, DateTime<Utc>) {
+    let horizon = Utc::now() + Duration::days(366 * TIME_ZONE_YEARS_AHEAD as i64);
+    let recurrence = session
+        .recurrence
+        .as_deref()
+        .and_then(|rule| rule.parse::<Recurrence>().ok());
+    let end = match recurrence {
+        None => session.ends_at,
+        Some(recurrence) => match (recurrence.until, recurrence.count) {
+            (Some(until), _) => until.min(horizon),
+            // A day more per occurrence makes up for the DST shifts.
+            (None, Some(count)) => {
+                let seconds = (recurrence.period().num_seconds() + 86_400) * count as i64;
+                let remaining = (horizon - session.ends_at).num_seconds().max(0);
+                session.ends_at + Duration::seconds(seconds.min(remaining))
+            }
+            (None, None) => horizon,
+        },
+    };
+    (session.starts_at, end.max(session.ends_at))
+}
+
+fn start_of_year(year: i32) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&NaiveDate::from_ymd(year, 1, 1).and_hms(0, 0, 0))
+}
+
+pub fn calendar(name: &str, sessions: &[ScheduledSession]) -> String {
+    let local_time = |time: DateTime<Utc>, time_zone: &str| match parse_time_zone(time_zone) {
+        Ok(time_zone) => time.with_timezone(&time_zone).format("%Y%m%dT%H%M%S").to_string(),
+        Err(_) => time.format("%Y%m%dT%H%M%SZ").to_string(),
+    };
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_owned(),
+        "VERSION:2.0".to_owned(),
+        "PRODID:-//ezytutors//teacher-service//EN".to_owned(),
+        "CALSCALE:GREGORIAN".to_owned(),
+        format!("X-WR-CALNAME:{}", escape_text(name)),
+    ];
+    let mut spans: BTreeMap<&str, (DateTime<Utc>, DateTime<Utc>)> = BTreeMap::new();
+    for session in sessions {
+        let (start, end) = session_span(session);
+        spans
+            .entry(&session.time_zone)
+            .and_modify(|span| *span = (span.0.min(start), span.1.max(end)))
+            .or_insert((start, end));
+    }
+    for (time_zone, (start, end)) in spans {
+        if let Ok(time_zone) = parse_time_zone(time_zone) {
+            lines.extend(time_zone_component(
+                time_zone,
+                start_of_year(start.year()),
+                start_of_year(end.year() + 1),
+            ));
+        }
+    }
+    for session in sessions {
+        lines.push("BEGIN:VEVENT".to_owned());
+        lines.push(format!("UID:course-session-{}@teacher-service", session.id));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        lines.push(format!(
+            "DTSTART;TZID={}:{}",
+            session.time_zone,
+            local_time(session.starts_at, &session.time_zone)
+        ));
+        lines.push(format!(
+            "DTEND;TZID={}:{}",
+            session.time_zone,
+            local_time(session.ends_at, &session.time_zone)
+        ));
+        if let Some(recurrence) = &session.recurrence {
+            lines.push(format!("RRULE:{}", recurrence.trim_start_matches("RRULE:")));
+        }
+        lines.push(format!("SUMMARY:{}", escape_text(&session.course_name)));
+        lines.push("END:VEVENT".to_owned());
+    }
+    lines.push("END:VCALENDAR".to_owned());
+    lines.iter().map(|line| fold_line(line)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn recurrence_should_parse_supported_rules() {
+        let recurrence: Recurrence = "FREQ=WEEKLY;INTERVAL=2;COUNT=4".parse().unwrap();
+        assert_eq!(recurrence.frequency, Frequency::Weekly);
+        assert_eq!(recurrence.interval, 2);
+        assert_eq!(recurrence.count, Some(4));
+        assert!("FREQ=MONTHLY".parse::<Recurrence>().is_err());
+        assert!("FREQ=DAILY;BYDAY=MO".parse::<Recurrence>().is_err());
+        assert!("COUNT=3".parse::<Recurrence>().is_err());
+    }
+
+    #[test]
+    fn occurrences_should_keep_the_local_time_across_dst() {
+        let paris = parse_time_zone("Europe/Paris").unwrap();
+        let recurrence: Recurrence = "FREQ=WEEKLY;COUNT=3".parse().unwrap();
+        // 18:00 in Paris, the clocks move forward on March 27, 2022.
+        let found = occurrences(
+            utc("2022-03-20T17:00:00Z"),
+            utc("2022-03-20T18:00:00Z"),
+            paris,
+            Some(&recurrence),
+            utc("2022-03-01T00:00:00Z"),
+            utc("2022-05-01T00:00:00Z"),
+        );
+        let starts: Vec<DateTime<Utc>> = found.iter().map(|(start, _end)| *start).collect();
+        assert_eq!(
+            starts,
+            vec![
+                utc("2022-03-20T17:00:00Z"),
+                utc("2022-03-27T16:00:00Z"),
+                utc("2022-04-03T16:00:00Z"),
+            ]
+        );
+        let later = occurrences(
+            utc("2022-03-20T17:00:00Z"),
+            utc("2022-03-20T18:00:00Z"),
+            paris,
+            Some(&recurrence),
+            utc("2022-03-30T00:00:00Z"),
+            utc("2022-05-01T00:00:00Z"),
+        );
+        assert_eq!(later.len(), 1);
+    }
+
+    #[test]
+    fn occurrences_in_a_dst_gap_should_move_forward() {
+        let paris = parse_time_zone("Europe/Paris").unwrap();
+        let recurrence: Recurrence = "FREQ=WEEKLY;COUNT=2".parse().unwrap();
+        // 02:30 doesn't exist in Paris on March 27, 2022, it becomes 03:30.
+        let found = occurrences(
+            utc("2022-03-20T01:30:00Z"),
+            utc("2022-03-20T02:30:00Z"),
+            paris,
+            Some(&recurrence),
+            utc("2022-03-01T00:00:00Z"),
+            utc("2022-05-01T00:00:00Z"),
+        );
+        assert_eq!(
+            found,
+            vec![
+                (utc("2022-03-20T01:30:00Z"), utc("2022-03-20T02:30:00Z")),
+                (utc("2022-03-27T01:30:00Z"), utc("2022-03-27T02:30:00Z")),
+            ]
+        );
+    }
+
+    #[test]
+    fn calendar_should_define_the_time_zones() {
+        let session = ScheduledSession {
+            id: 1,
+            course_id: 1,
+            teacher_id: 1,
+            course_name: "Rust".into(),
+            starts_at: utc("2022-03-20T17:00:00Z"),
+            ends_at: utc("2022-03-20T18:00:00Z"),
+            time_zone: "Europe/Paris".into(),
+            recurrence: Some("FREQ=WEEKLY;COUNT=3".into()),
+            capacity: None,
+        };
+        let ics = calendar("Rust", &[session]);
+        let lines: Vec<&str> = ics.split("\r\n").collect();
+        let component = lines
+            .iter()
+            .position(|line| *line == "BEGIN:VTIMEZONE")
+            .unwrap();
+        assert_eq!(lines[component + 1], "TZID:Europe/Paris");
+        assert!(component < lines.iter().position(|line| *line == "BEGIN:VEVENT").unwrap());
+        let daylight = lines
+            .iter()
+            .position(|line| *line == "BEGIN:DAYLIGHT")
+            .unwrap();
+        assert_eq!(
+            &lines[daylight + 1..daylight + 5],
+            &[
+                "DTSTART:20220327T020000",
+                "TZOFFSETFROM:+0100",
+                "TZOFFSETTO:+0200",
+                "TZNAME:CEST"
+            ]
+        );
+        assert!(lines.contains(&"DTSTART:20221030T030000"));
+    }
+
+    #[test]
+    fn fold_line_should_keep_lines_short() {
+        let folded = fold_line(&format!("SUMMARY:{}", "é".repeat(50)));
+        assert!(folded.split("\r\n").all(|line| line.len() <= 75));
+        assert_eq!(folded.replace("\r\n ", "").trim_end(), format!("SUMMARY:{}", "é".repeat(50)));
+    }
+}
@@ -0,0 +1,443 @@
+use crate::audit::{request_actor, AuditContext, Viewer};
+use crate::errors::MyError;
+use actix_cors::Cors;
+use actix_web::dev::Payload;
+use actix_web::error::JsonPayloadError;
+use actix_web::http::header::{self, HeaderName};
+use actix_web::middleware::DefaultHeaders;
+use actix_web::{web, FromRequest, HttpRequest};
+use std::env;
+use std::future::{ready, Ready};
+use std::net::{IpAddr, Ipv4Addr};
+
+pub const DEFAULT_JSON_LIMIT: usize = 32 * 1024;
+
+// The size limit of the bodies sent in the formats other than JSON, set along with `json_config`.
+#[derive(Clone, Copy, Debug)]
+pub struct BodyLimit(pub usize);
+
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec![],
+            allowed_methods: vec!["GET", "POST", "PUT", "DELETE"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            allow_credentials: false,
+        }
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim().to_owned())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+impl CorsConfig {
+    // No origin is allowed unless `CORS_ALLOWED_ORIGINS` is set, `*` allows any of them.
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = CorsConfig::default();
+        if let Ok(origins) = env::var("CORS_ALLOWED_ORIGINS") {
+            config.allowed_origins = split_list(&origins);
+        }
+        if let Ok(methods) = env::var("CORS_ALLOWED_METHODS") {
+            config.allowed_methods = split_list(&methods);
+        }
+        if let Ok(credentials) = env::var("CORS_ALLOW_CREDENTIALS") {
+            config.allow_credentials = credentials == "true";
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Any origin sending credentials would let every site act as the signed-in user.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allow_credentials && self.allowed_origins.iter().any(|origin| origin == "*") {
+            return Err(
+                "CORS_ALLOW_CREDENTIALS=true cannot be used with CORS_ALLOWED_ORIGINS=*".into(),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn cors(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.allowed_methods.iter().map(String::as_str))
+            .allowed_headers(vec![
+                header::ACCEPT,
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::IF_NONE_MATCH,
+                header::IF_MODIFIED_SINCE,
+                HeaderName::from_static("idempotency-key"),
+                HeaderName::from_static("last-event-id"),
+                HeaderName::from_static("x-actor"),
+            ])
+            // Without these, a browser hides the caching, deprecation and replay headers
+            // from the scripts of other origins.
+            .expose_headers(vec![
+                "etag",
+                "last-modified",
+                "deprecation",
+                "sunset",
+                "link",
+                "idempotent-replayed",
+                "retry-after",
+                "ratelimit-limit",
+                "ratelimit-remaining",
+                "ratelimit-reset",
+            ])
+            .max_age(3600);
+        for origin in self.allowed_origins.iter() {
+            cors = if origin == "*" {
+                cors.allow_any_origin()
+            } else {
+                cors.allowed_origin(origin)
+            };
+        }
+        if self.allow_credentials {
+            cors = cors.supports_credentials();
+        }
+        cors
+    }
+}
+
+// Who can name the actor of a request, and which actors are administrators.
+#[derive(Clone, Debug, Default)]
+pub struct AccessConfig {
+    // The gateways that authenticate the callers and set `X-Actor` for them.
+    pub trusted_proxies: Vec<IpAddr>,
+    pub admin_actors: Vec<String>,
+}
+
+impl AccessConfig {
+    // Without `TRUSTED_PROXIES` every request is anonymous, and without `ADMIN_ACTORS`
+    // the admin routes are refused to everyone.
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = AccessConfig::default();
+        if let Ok(proxies) = env::var("TRUSTED_PROXIES") {
+            config.trusted_proxies = split_list(&proxies)
+                .iter()
+                .map(|proxy| {
+                    proxy.parse().map_err(|_err| {
+                        format!("TRUSTED_PROXIES holds an invalid address {}", proxy)
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        if let Ok(actors) = env::var("ADMIN_ACTORS") {
+            config.admin_actors = split_list(&actors);
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Any caller is anonymous, so it can't be an administrator.
+    pub fn validate(&self) -> Result<(), String> {
+        let anonymous = AuditContext::default().actor;
+        if self.admin_actors.contains(&anonymous) {
+            return Err(format!("ADMIN_ACTORS cannot hold {}", anonymous));
+        }
+        Ok(())
+    }
+
+    pub fn trusts(&self, peer: Option<IpAddr>) -> bool {
+        peer.is_some_and(|peer| self.trusted_proxies.contains(&peer))
+    }
+}
+
+// Whether an address is reachable from the internet, as opposed to the loopback, private,
+// link-local and other special-purpose ranges.
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (b == 18 || b == 19))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let [0, 0, 0, 0, 0, 0xffff, high, low] = ip.segments() {
+                let [a, b] = high.to_be_bytes();
+                let [c, d] = low.to_be_bytes();
+                return is_public_address(IpAddr::V4(Ipv4Addr::new(a, b, c, d)));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+// A request to an admin route, refused with a 403 unless its actor is one of
+// the `ADMIN_ACTORS`.
+#[derive(Clone, Copy, Debug)]
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let admin = request_actor(req).and_then(|actor| {
+            let config = req.app_data::<web::Data<AccessConfig>>();
+            if config.is_some_and(|config| config.admin_actors.contains(&actor)) {
+                Ok(Admin)
+            } else {
+                Err(MyError::Forbidden(
+                    "Only the administrators can use this route".into(),
+                ))
+            }
+        });
+        ready(admin)
+    }
+}
+
+// Refuses with a 403 a change to a course of `owner_id` unless the viewer is that teacher
+// or an administrator makes it, `admin` being extracted as an `Option<Admin>`.
+pub fn owner_or_admin(viewer: Viewer, admin: Option<&Admin>, owner_id: i32) -> Result<(), MyError> {
+    if viewer.teacher_id == Some(owner_id) || admin.is_some() {
+        Ok(())
+    } else {
+        Err(MyError::Forbidden(
+            "Only the teacher of the course or an administrator can change it".into(),
+        ))
+    }
+}
+
+pub fn security_headers() -> DefaultHeaders {
+    DefaultHeaders::new()
+        .add((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .add((header::X_FRAME_OPTIONS, "DENY"))
+        .add((header::REFERRER_POLICY, "no-referrer"))
+        .add((header::CONTENT_SECURITY_POLICY, "frame-ancestors 'none'"))
+}
+
+// Bodies over `limit` bytes are refused with a 413, any other invalid body with a 400.
+pub fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(|err, _req| match err {
+            JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+                MyError::PayloadTooLarge("The request body is too large".to_owned()).into()
+            }
+            _ => MyError::InvalidInput("Please provide valid JSON input".to_owned()).into(),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::ACTOR_HEADER;
+    use actix_web::dev::Service;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::{test, App, HttpResponse};
+    use std::net::SocketAddr;
+
+    async fn echo(body: web::Json<serde_json::Value>) -> HttpResponse {
+        HttpResponse::Ok().json(body.into_inner())
+    }
+
+    #[actix_rt::test]
+    async fn oversized_body_should_be_refused() {
+        let app = test::init_service(
+            App::new()
+                .app_data(json_config(DEFAULT_JSON_LIMIT))
+                .service(
+                    web::scope("/small")
+                        .app_data(json_config(16))
+                        .route("", web::post().to(echo)),
+                )
+                .route("/large", web::post().to(echo)),
+        )
+        .await;
+        let body = serde_json::json!({ "name": "A name longer than sixteen bytes" });
+
+        let req = test::TestRequest::post()
+            .uri("/small")
+            .set_json(&body)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let resp_body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(resp_body["error_message"], "The request body is too large");
+
+        let req = test::TestRequest::post()
+            .uri("/large")
+            .set_json(&body)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn preflight_should_only_succeed_for_allowed_origins() {
+        let config = CorsConfig {
+            allowed_origins: vec!["http://localhost:8080".to_owned()],
+            ..CorsConfig::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .wrap(security_headers())
+                .wrap(config.cors())
+                .route("/", web::get().to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .insert_header((header::ORIGIN, "http://localhost:8080"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "http://localhost:8080"
+        );
+
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .insert_header((header::ORIGIN, "http://localhost:8080"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .insert_header((
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "idempotency-key, if-none-match, x-actor",
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header((header::ORIGIN, "http://evil.example"))
+            .to_request();
+        let resp = app.call(req).await;
+        assert!(resp
+            .map(|resp| resp.status().is_client_error())
+            .unwrap_or(true));
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+            "nosniff"
+        );
+    }
+
+    async fn admin_only(_admin: Admin) -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_rt::test]
+    async fn only_admins_behind_a_trusted_proxy_should_pass() {
+        let proxy: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let config = AccessConfig {
+            trusted_proxies: vec![proxy.ip()],
+            admin_actors: vec!["alice".to_owned()],
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .route("/admin", web::get().to(admin_only)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/admin")
+            .peer_addr(proxy)
+            .insert_header((ACTOR_HEADER, "alice"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get()
+            .uri("/admin")
+            .peer_addr(proxy)
+            .insert_header((ACTOR_HEADER, "bob"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // The same header straight from a client isn't believed.
+        let req = test::TestRequest::get()
+            .uri("/admin")
+            .peer_addr("192.0.2.7:4000".parse().unwrap())
+            .insert_header((ACTOR_HEADER, "alice"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn anonymous_should_not_be_an_admin() {
+        let config = AccessConfig {
+            admin_actors: vec!["anonymous".to_owned()],
+            ..AccessConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[actix_rt::test]
+    async fn only_public_addresses_should_be_public() {
+        for address in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public_address(address.parse().unwrap()), "{}", address);
+        }
+        for address in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public_address(address.parse().unwrap()), "{}", address);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn credentials_should_be_refused_for_any_origin() {
+        let config = CorsConfig {
+            allowed_origins: vec!["http://localhost:8080".to_owned(), "*".to_owned()],
+            allow_credentials: true,
+            ..CorsConfig::default()
+        };
+        assert!(config.validate().is_err());
+        let config = CorsConfig {
+            allowed_origins: vec!["http://localhost:8080".to_owned()],
+            allow_credentials: true,
+            ..CorsConfig::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::state::AppState;
+use actix_web::dev::ServerHandle;
+use actix_web::web;
+use std::env;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShutdownConfig {
+    // How long readiness fails before the server stops accepting connections.
+    pub readiness_delay: Duration,
+    // How long in-flight requests are given to complete.
+    pub timeout: Duration,
+}
+
+impl ShutdownConfig {
+    pub fn from_env() -> Self {
+        let seconds = |name: &str, default: u64| {
+            env::var(name)
+                .map(|value| value.parse().expect("Shutdown delays must be integers"))
+                .unwrap_or(default)
+        };
+        ShutdownConfig {
+            readiness_delay: Duration::from_secs(seconds("SHUTDOWN_READINESS_DELAY_SECS", 5)),
+            timeout: Duration::from_secs(seconds("SHUTDOWN_TIMEOUT_SECS", 30)),
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use actix_rt::signal::unix::{signal, SignalKind};
+    use futures_util::future::select;
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Unable to listen to SIGTERM");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("Unable to listen to SIGINT");
+    select(Box::pin(terminate.recv()), Box::pin(interrupt.recv())).await;
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    actix_rt::signal::ctrl_c()
+        .await
+        .expect("Unable to listen to Ctrl-C");
+}
+
+// Returns once shutdown starts, for the tasks that wait on something else in the meantime.
+pub async fn wait_for_shutdown(app_state: &AppState) {
+    while !app_state.shutting_down.load(Ordering::SeqCst) {
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+// Sleeps for `duration`, or until shutdown starts, so that the background tasks stop soon.
+pub async fn sleep_unless_shutting_down(app_state: &AppState, duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while !app_state.shutting_down.load(Ordering::SeqCst) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return;
+        }
+        actix_rt::time::sleep(left.min(Duration::from_secs(1))).await;
+    }
+}
+
+// Waits for SIGTERM or SIGINT, flips readiness to failing, then stops the server,
+// which drains in-flight requests within its shutdown timeout.
+pub async fn shutdown_on_signal(
+    server: ServerHandle,
+    app_state: web::Data<AppState>,
+    config: ShutdownConfig,
+) {
+    wait_for_signal().await;
+    tracing::info!("Shutdown requested, draining connections");
+    app_state.shutting_down.store(true, Ordering::SeqCst);
+    actix_rt::time::sleep(config.readiness_delay).await;
+    server.stop(true).await;
+}
@@ -1,8 +1,12 @@
+use crate::read_cache::ReadCache;
 use sqlx::postgres::PgPool;
+use std::sync::atomic::AtomicBool;
 use std::sync::Mutex;
 
 pub struct AppState {
     pub health_check_response: String,
     pub visit_count: Mutex<u32>,
+    pub shutting_down: AtomicBool,
+    pub read_cache: ReadCache,
     pub db: PgPool,
 }
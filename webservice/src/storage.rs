@@ -0,0 +1,333 @@
+use crate::errors::MyError;
+use actix_web::web::{self, Bytes};
+use async_trait::async_trait;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use std::env;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub const MEDIA_PREFIX: &str = "/media/";
+// The size of the chunks blobs are read and written by.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub type BlobStream = BoxStream<'static, Result<Bytes, MyError>>;
+
+pub struct Blob {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+// Keys are relative paths such as `teachers/1/1650000000/original.png`.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), MyError>;
+    async fn get(&self, key: &str) -> Result<Blob, MyError>;
+    async fn delete(&self, key: &str) -> Result<(), MyError>;
+    // Writes a blob chunk by chunk, which only shows up under its key once finished.
+    async fn writer(&self, key: &str) -> Result<Box<dyn BlobWriter>, MyError>;
+    // Streams the bytes from `start` to `end` included.
+    fn stream(&self, key: &str, start: u64, end: u64) -> Result<BlobStream, MyError>;
+}
+
+// Dropping a writer before it is finished discards what it wrote.
+#[async_trait]
+pub trait BlobWriter: Send {
+    async fn write(&mut self, chunk: Bytes) -> Result<(), MyError>;
+    async fn finish(self: Box<Self>) -> Result<(), MyError>;
+}
+
+// The store of the course materials, which are only served through their own routes:
+// it must not share its blobs with the public `/media/` store.
+pub struct MaterialStore(pub Arc<dyn BlobStore>);
+
+impl Deref for MaterialStore {
+    type Target = dyn BlobStore;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+pub fn media_url(key: &str) -> String {
+    format!("{}{}", MEDIA_PREFIX, key)
+}
+
+// Returns the key of a blob served by `media_url`, `None` for any other URL.
+pub fn media_key(url: &str) -> Option<&str> {
+    url.strip_prefix(MEDIA_PREFIX)
+}
+
+pub fn content_type(key: &str) -> &'static str {
+    match key.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("pdf") => "application/pdf",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mp3") => "audio/mpeg",
+        Some("txt") => "text/plain",
+        Some("md") => "text/markdown",
+        Some("zip") => "application/zip",
+        Some("pptx") => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        Some("docx") => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        _ => "application/octet-stream",
+    }
+}
+
+fn validate_key(key: &str) -> Result<(), MyError> {
+    let valid = !key.is_empty()
+        && !key.starts_with('/')
+        && key
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "..")
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-/".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(MyError::InvalidInput("Invalid media key".into()))
+    }
+}
+
+pub struct LocalFsStore {
+    root: PathBuf,
+}
+
+impl LocalFsStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalFsStore { root: root.into() }
+    }
+
+    pub fn from_env() -> Self {
+        LocalFsStore::new(env::var("MEDIA_ROOT").unwrap_or_else(|_| "./media".to_owned()))
+    }
+
+    pub fn materials_from_env() -> Self {
+        LocalFsStore::new(env::var("MATERIAL_ROOT").unwrap_or_else(|_| "./materials".to_owned()))
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, MyError> {
+        validate_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+fn blocking_error(err: impl ToString) -> MyError {
+    MyError::ActixError(err.to_string())
+}
+
+fn read_error(err: std::io::Error) -> MyError {
+    match err.kind() {
+        ErrorKind::NotFound => MyError::NotFound("Media not found".into()),
+        _ => blocking_error(err),
+    }
+}
+
+// Writes to a temporary file next to the blob, renamed once finished.
+struct LocalFsWriter {
+    file: Option<File>,
+    buffer: Vec<u8>,
+    partial_path: PathBuf,
+    path: PathBuf,
+}
+
+impl LocalFsWriter {
+    async fn flush(&mut self) -> Result<(), MyError> {
+        let mut file = self
+            .file
+            .take()
+            .ok_or_else(|| blocking_error("The blob was closed by a failed write"))?;
+        let buffer = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        let file = web::block(move || file.write_all(&buffer).map(|()| file))
+            .await
+            .map_err(blocking_error)?
+            .map_err(blocking_error)?;
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlobWriter for LocalFsWriter {
+    async fn write(&mut self, chunk: Bytes) -> Result<(), MyError> {
+        self.buffer.extend_from_slice(&chunk);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(mut self: Box<Self>) -> Result<(), MyError> {
+        self.flush().await?;
+        let file = self.file.take();
+        let (partial_path, path) = (self.partial_path.clone(), self.path.clone());
+        web::block(move || {
+            if let Some(file) = file {
+                file.sync_all()?;
+            }
+            fs::rename(partial_path, path)
+        })
+        .await
+        .map_err(blocking_error)?
+        .map_err(blocking_error)
+    }
+}
+
+impl Drop for LocalFsWriter {
+    fn drop(&mut self) {
+        // Fails once the file is renamed, which is fine.
+        let _ = fs::remove_file(&self.partial_path);
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalFsStore {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), MyError> {
+        let path = self.path(key)?;
+        web::block(move || {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, data)
+        })
+        .await
+        .map_err(blocking_error)?
+        .map_err(blocking_error)
+    }
+
+    async fn get(&self, key: &str) -> Result<Blob, MyError> {
+        let path = self.path(key)?;
+        let content_type = content_type(key).to_owned();
+        let data = web::block(move || fs::read(path))
+            .await
+            .map_err(blocking_error)?
+            .map_err(read_error)?;
+        Ok(Blob { content_type, data })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), MyError> {
+        let path = self.path(key)?;
+        web::block(move || match fs::remove_file(path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        })
+        .await
+        .map_err(blocking_error)?
+        .map_err(blocking_error)
+    }
+
+    async fn writer(&self, key: &str) -> Result<Box<dyn BlobWriter>, MyError> {
+        let path = self.path(key)?;
+        let partial_path = path.with_file_name(format!(
+            ".{}.partial",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        let file = {
+            let partial_path = partial_path.clone();
+            web::block(move || {
+                if let Some(parent) = partial_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                File::create(partial_path)
+            })
+            .await
+            .map_err(blocking_error)?
+            .map_err(blocking_error)?
+        };
+        Ok(Box::new(LocalFsWriter {
+            file: Some(file),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            partial_path,
+            path,
+        }))
+    }
+
+    fn stream(&self, key: &str, start: u64, end: u64) -> Result<BlobStream, MyError> {
+        let path = self.path(key)?;
+        // The file is opened by the first read, and handed from one read to the next.
+        let chunks = stream::try_unfold((None, start), move |(file, offset)| {
+            let path = path.clone();
+            async move {
+                if offset > end {
+                    return Ok(None);
+                }
+                let length = (end - offset + 1).min(CHUNK_SIZE as u64) as usize;
+                let (file, data) = web::block(move || {
+                    let mut file = match file {
+                        Some(file) => file,
+                        None => {
+                            let mut file = File::open(path)?;
+                            file.seek(SeekFrom::Start(offset))?;
+                            file
+                        }
+                    };
+                    let mut data = vec![0; length];
+                    file.read_exact(&mut data)?;
+                    Ok::<_, std::io::Error>((file, data))
+                })
+                .await
+                .map_err(blocking_error)?
+                .map_err(read_error)?;
+                Ok(Some((
+                    Bytes::from(data),
+                    (Some(file), offset + length as u64),
+                )))
+            }
+        });
+        Ok(chunks.boxed())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn local_store_should_round_trip_blobs() {
+        let root = env::temp_dir().join(format!("blobs-{}", chrono::Utc::now().timestamp_nanos()));
+        let store = LocalFsStore::new(&root);
+        store
+            .put("teachers/1/original.png", vec![1, 2, 3])
+            .await
+            .unwrap();
+        let blob = store.get("teachers/1/original.png").await.unwrap();
+        assert_eq!(blob.data, vec![1, 2, 3]);
+        assert_eq!(blob.content_type, "image/png");
+        store.delete("teachers/1/original.png").await.unwrap();
+        assert!(store.get("teachers/1/original.png").await.is_err());
+        assert!(store.get("../etc/passwd").await.is_err());
+
+        let mut writer = store.writer("courses/1/materials/1.bin").await.unwrap();
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE + 7).map(|i| i as u8).collect();
+        for chunk in data.chunks(1000) {
+            writer.write(Bytes::copy_from_slice(chunk)).await.unwrap();
+        }
+        assert!(store.get("courses/1/materials/1.bin").await.is_err());
+        writer.finish().await.unwrap();
+        let end = data.len() as u64 - 1;
+        let chunks: Vec<Bytes> = store
+            .stream("courses/1/materials/1.bin", 5, end)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(chunks.concat(), data[5..].to_vec());
+
+        let mut writer = store.writer("courses/1/materials/2.bin").await.unwrap();
+        writer.write(Bytes::from_static(b"partial")).await.unwrap();
+        drop(writer);
+        assert_eq!(
+            fs::read_dir(root.join("courses/1/materials"))
+                .unwrap()
+                .count(),
+            1
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}
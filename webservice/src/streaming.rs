@@ -0,0 +1,98 @@
+use crate::errors::MyError;
+use futures_util::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+// The rows fetched ahead of the response. A slow client holds the query back rather than
+// having the rows pile up in memory.
+const ROW_BUFFER: usize = 64;
+// How long a client may leave the buffer full. The query holds a pooled connection, so a
+// client that stops reading must not keep it from the other requests.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct RowSender<T>(mpsc::Sender<Result<T, MyError>>);
+
+impl<T> RowSender<T> {
+    // Fails once the response is dropped, or the client stalls, which stops the query.
+    pub async fn send(&self, row: T) -> Result<(), MyError> {
+        actix_rt::time::timeout(WRITE_TIMEOUT, self.0.send(Ok(row)))
+            .await
+            .map_err(|_| MyError::ActixError("The client stopped reading".into()))?
+            .map_err(|_| MyError::ActixError("The response was dropped".into()))
+    }
+}
+
+// The rows sent by a query running on its own task, which owns its connection.
+pub struct Rows<T> {
+    receiver: mpsc::Receiver<Result<T, MyError>>,
+}
+
+impl<T> Stream for Rows<T> {
+    type Item = Result<T, MyError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+// Runs `fetch` in the current span, its error ending the rows.
+pub fn fetch_rows<T, F, Fut>(fetch: F) -> Rows<T>
+where
+    T: 'static,
+    F: FnOnce(RowSender<T>) -> Fut,
+    Fut: Future<Output = Result<(), MyError>> + 'static,
+{
+    let (sender, receiver) = mpsc::channel(ROW_BUFFER);
+    let errors = sender.clone();
+    let fetching = fetch(RowSender(sender));
+    actix_rt::spawn(
+        async move {
+            if let Err(err) = fetching.await {
+                // Fails only when the response was dropped or the client stalled.
+                let _ = actix_rt::time::timeout(WRITE_TIMEOUT, errors.send(Err(err))).await;
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+    Rows { receiver }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::StreamExt;
+
+    #[actix_rt::test]
+    async fn rows_should_end_with_the_error_of_the_query() {
+        let rows = fetch_rows(|rows| async move {
+            rows.send(1).await?;
+            rows.send(2).await?;
+            Err(MyError::DBError("connection reset".into()))
+        });
+        let rows: Vec<Result<i32, MyError>> = rows.collect().await;
+        assert_eq!(rows.len(), 3);
+        assert_eq!(*rows[1].as_ref().unwrap(), 2);
+        assert!(matches!(rows[2], Err(MyError::DBError(_))));
+    }
+
+    #[actix_rt::test]
+    async fn dropping_the_rows_should_stop_the_query() {
+        let (done, finished) = tokio::sync::oneshot::channel();
+        let rows = fetch_rows(|rows| async move {
+            let mut sent = 0;
+            while rows.send(sent).await.is_ok() {
+                sent += 1;
+            }
+            let _ = done.send(sent);
+            Ok(())
+        });
+        let first: Vec<Result<i32, MyError>> = rows.take(1).collect().await;
+        assert_eq!(first.len(), 1);
+        let sent = finished.await.unwrap();
+        assert!(sent <= ROW_BUFFER as i32 + 1);
+    }
+}
@@ -0,0 +1,132 @@
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::trace::TraceError;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use std::env;
+use std::time::Duration;
+use tracing::Subscriber;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Registry};
+
+pub const SERVICE_NAME: &str = "teacher-service";
+
+// Builds an OTLP/HTTP span exporter pointing at `endpoint` and registers the
+// W3C trace context propagator, so that incoming `traceparent` headers are honored.
+pub fn init_tracer(endpoint: &str) -> Result<trace::Tracer, TraceError> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint)
+                .with_timeout(Duration::from_secs(3)),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                SERVICE_NAME,
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::TokioCurrentThread)
+}
+
+pub fn subscriber(tracer: Option<trace::Tracer>) -> impl Subscriber + Send + Sync {
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    Registry::default()
+        .with(env_filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+// Spans are only exported when `OTEL_EXPORTER_OTLP_ENDPOINT` is set,
+// otherwise requests are just logged to stdout.
+pub fn init_telemetry() {
+    let tracer = env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().map(|endpoint| {
+        init_tracer(&endpoint).expect("Unable to install the OTLP trace exporter")
+    });
+    subscriber(tracer).init();
+}
+
+pub fn shutdown_telemetry() {
+    global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse, HttpServer};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tracing_actix_web::TracingLogger;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    // Stands in for an OTLP collector: accepts any export request and keeps the raw bodies.
+    // It runs on its own thread, since flushing the exporter blocks the test's runtime.
+    fn spawn_collector_stub() -> (String, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        thread::spawn(move || {
+            actix_rt::System::new().block_on(async move {
+                HttpServer::new(move || {
+                    let sink = sink.clone();
+                    App::new().default_service(web::to(move |body: web::Bytes| {
+                        sink.lock().unwrap().push(body.to_vec());
+                        async { HttpResponse::Ok().finish() }
+                    }))
+                })
+                .workers(1)
+                .listen(listener)
+                .unwrap()
+                .run()
+                .await
+            })
+        });
+        (endpoint, received)
+    }
+
+    fn hex_to_bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn request_span_should_be_exported_with_incoming_trace_id() {
+        let (endpoint, received) = spawn_collector_stub();
+        let tracer = init_tracer(&endpoint).unwrap();
+        let guard = tracing::subscriber::set_default(subscriber(Some(tracer)));
+        let app = test::init_service(
+            App::new()
+                .wrap(TracingLogger::default())
+                .route(
+                    "/health",
+                    web::get().to(|| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/health")
+            .insert_header((
+                "traceparent",
+                format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        test::read_body(resp).await;
+        drop(guard);
+        shutdown_telemetry();
+        let trace_id = hex_to_bytes(TRACE_ID);
+        let received = received.lock().unwrap();
+        assert!(!received.is_empty());
+        assert!(received
+            .iter()
+            .any(|body| body.windows(trace_id.len()).any(|w| w == trace_id.as_slice())));
+    }
+}
@@ -0,0 +1,355 @@
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{Error, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use openssl::error::ErrorStack;
+use openssl::ssl::{
+    select_next_proto, AlpnError, SniError, SslAcceptor, SslAcceptorBuilder, SslContext,
+    SslFiletype, SslMethod,
+};
+use std::env;
+use std::future::{ready, Ready};
+use std::io;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+
+const HSTS: &str = "max-age=31536000";
+// HTTP/2 is preferred over HTTP/1.1, as actix-web does.
+const ALPN_PROTOCOLS: &[u8] = b"\x02h2\x08http/1.1";
+
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub bind_address: String,
+    pub redirect_http: bool,
+}
+
+impl TlsConfig {
+    // TLS is only enabled when both `TLS_CERT_PATH` and `TLS_KEY_PATH` are set.
+    pub fn from_env() -> Option<Self> {
+        let cert_path = env::var("TLS_CERT_PATH").ok()?;
+        let key_path = env::var("TLS_KEY_PATH").ok()?;
+        Some(TlsConfig {
+            cert_path,
+            key_path,
+            bind_address: env::var("TLS_BIND_ADDRESS")
+                .unwrap_or_else(|_| "127.0.0.1:3443".to_owned()),
+            redirect_http: env::var("TLS_REDIRECT_HTTP")
+                .map(|redirect| redirect == "true")
+                .unwrap_or(false),
+        })
+    }
+
+    pub fn https_port(&self) -> u16 {
+        self.bind_address
+            .rsplit(':')
+            .next()
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(443)
+    }
+
+    // The contexts swapped in on reload replace the whole context of the connection,
+    // so they are built with the same protocol settings and ALPN as the acceptor.
+    fn server_builder(&self) -> Result<SslAcceptorBuilder, ErrorStack> {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+        builder.set_private_key_file(&self.key_path, SslFiletype::PEM)?;
+        builder.set_certificate_chain_file(&self.cert_path)?;
+        builder.check_private_key()?;
+        builder.set_alpn_select_callback(|_ssl, client_protocols| {
+            select_next_proto(ALPN_PROTOCOLS, client_protocols).ok_or(AlpnError::NOACK)
+        });
+        builder.set_alpn_protos(ALPN_PROTOCOLS)?;
+        Ok(builder)
+    }
+
+    fn load_context(&self) -> Result<SslContext, ErrorStack> {
+        Ok(self.server_builder()?.build().into_context())
+    }
+}
+
+// Holds the certificate currently served, so that it can be swapped without restarting.
+#[derive(Clone)]
+pub struct CertificateStore {
+    config: TlsConfig,
+    context: Arc<RwLock<SslContext>>,
+}
+
+impl CertificateStore {
+    pub fn new(config: TlsConfig) -> io::Result<Self> {
+        let context = config.load_context().map_err(io::Error::from)?;
+        Ok(CertificateStore {
+            config,
+            context: Arc::new(RwLock::new(context)),
+        })
+    }
+
+    pub fn reload(&self) -> Result<(), ErrorStack> {
+        let context = self.config.load_context()?;
+        *self.context.write().unwrap() = context;
+        Ok(())
+    }
+
+    // The acceptor hands every new connection the latest certificate from the
+    // server name callback, which OpenSSL runs whether the client sends SNI or not.
+    pub fn acceptor_builder(&self) -> io::Result<SslAcceptorBuilder> {
+        let mut builder = self.config.server_builder()?;
+        let context = self.context.clone();
+        builder.set_servername_callback(move |ssl, _alert| {
+            let context = context.read().unwrap();
+            ssl.set_ssl_context(&context)
+                .map_err(|_err| SniError::ALERT_FATAL)
+        });
+        Ok(builder)
+    }
+}
+
+#[cfg(unix)]
+pub async fn reload_on_sighup(store: CertificateStore) {
+    use actix_rt::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup()).expect("Unable to listen to SIGHUP");
+    while hangup.recv().await.is_some() {
+        match store.reload() {
+            Ok(()) => tracing::info!("TLS certificate reloaded"),
+            Err(err) => tracing::error!("Unable to reload the TLS certificate: {}", err),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_sighup(_store: CertificateStore) {}
+
+// The host without its port, keeping the brackets of an IPv6 address.
+fn strip_port(host: &str) -> &str {
+    match host.find(']') {
+        Some(end) if host.starts_with('[') => &host[..=end],
+        _ => host.split(':').next().unwrap_or_default(),
+    }
+}
+
+// Redirects plain HTTP requests to HTTPS when enabled, and adds
+// `Strict-Transport-Security` to the responses sent over HTTPS.
+#[derive(Clone, Debug)]
+pub struct HttpsRedirect {
+    enabled: bool,
+    https_port: u16,
+}
+
+impl HttpsRedirect {
+    pub fn new(enabled: bool, https_port: u16) -> Self {
+        HttpsRedirect {
+            enabled,
+            https_port,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for HttpsRedirect
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = HttpsRedirectMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HttpsRedirectMiddleware {
+            service: Rc::new(service),
+            redirect: self.clone(),
+        }))
+    }
+}
+
+pub struct HttpsRedirectMiddleware<S> {
+    service: Rc<S>,
+    redirect: HttpsRedirect,
+}
+
+impl<S, B> Service<ServiceRequest> for HttpsRedirectMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let secure = req.connection_info().scheme() == "https";
+        if !secure && self.redirect.enabled {
+            let host = req.connection_info().host().to_owned();
+            let host = strip_port(&host);
+            let port = match self.redirect.https_port {
+                443 => String::new(),
+                port => format!(":{}", port),
+            };
+            let path = req
+                .uri()
+                .path_and_query()
+                .map(|path| path.as_str())
+                .unwrap_or("/");
+            let location = format!("https://{}{}{}", host, port, path);
+            let resp = HttpResponse::PermanentRedirect()
+                .insert_header((header::LOCATION, location))
+                .finish();
+            return Box::pin(async move { Ok(req.into_response(resp).map_into_right_body()) });
+        }
+        let service = self.service.clone();
+        Box::pin(async move {
+            let mut res = service.call(req).await?;
+            if secure {
+                res.headers_mut().insert(
+                    header::STRICT_TRANSPORT_SECURITY,
+                    HeaderValue::from_static(HSTS),
+                );
+            }
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslConnector, SslVerifyMode};
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::fs;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    #[actix_rt::test]
+    async fn plain_http_should_be_redirected_to_https() {
+        let app = test::init_service(App::new().wrap(HttpsRedirect::new(true, 3443)).route(
+            "/teachers/",
+            web::get().to(|| async { HttpResponse::Ok().finish() }),
+        ))
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/teachers/?page=2")
+            .insert_header((header::HOST, "localhost:3000"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            resp.headers().get(header::LOCATION).unwrap(),
+            "https://localhost:3443/teachers/?page=2"
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/teachers/")
+            .insert_header((header::HOST, "localhost:3443"))
+            .insert_header(("x-forwarded-proto", "https"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()
+                .get(header::STRICT_TRANSPORT_SECURITY)
+                .unwrap(),
+            HSTS
+        );
+    }
+
+    #[actix_rt::test]
+    async fn ipv6_hosts_should_keep_their_brackets() {
+        assert_eq!(strip_port("[::1]:3000"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+        assert_eq!(strip_port("localhost:3000"), "localhost");
+        assert_eq!(strip_port("localhost"), "localhost");
+    }
+
+    // Writes a self-signed certificate for `name` and its key to `config`.
+    fn write_certificate(config: &TlsConfig, name: &str) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+        let subject = subject.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_issuer_name(&subject).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        fs::write(&config.cert_path, cert.build().to_pem().unwrap()).unwrap();
+        fs::write(&config.key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    }
+
+    // Handshakes offering h2 and HTTP/1.1, returns the protocol and the certificate name.
+    fn handshake(acceptor: &SslAcceptor) -> (Vec<u8>, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
+            connector.set_verify(SslVerifyMode::NONE);
+            connector.set_alpn_protos(ALPN_PROTOCOLS).unwrap();
+            let stream = TcpStream::connect(address).unwrap();
+            let stream = connector.build().connect("localhost", stream).unwrap();
+            let name = stream
+                .ssl()
+                .peer_certificate()
+                .unwrap()
+                .subject_name()
+                .entries_by_nid(Nid::COMMONNAME)
+                .next()
+                .unwrap()
+                .data()
+                .as_slice()
+                .to_vec();
+            let name = String::from_utf8(name).unwrap();
+            (
+                stream.ssl().selected_alpn_protocol().unwrap().to_vec(),
+                name,
+            )
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let _stream = acceptor.accept(stream).unwrap();
+        client.join().unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn h2_should_still_be_negotiated_after_a_reload() {
+        let dir = env::temp_dir();
+        let id = std::process::id();
+        let config = TlsConfig {
+            cert_path: dir
+                .join(format!("tls-test-{}.crt", id))
+                .display()
+                .to_string(),
+            key_path: dir
+                .join(format!("tls-test-{}.key", id))
+                .display()
+                .to_string(),
+            bind_address: "127.0.0.1:3443".to_owned(),
+            redirect_http: false,
+        };
+        write_certificate(&config, "before");
+        let store = CertificateStore::new(config.clone()).unwrap();
+        let acceptor = store.acceptor_builder().unwrap().build();
+        assert_eq!(handshake(&acceptor), (b"h2".to_vec(), "before".to_owned()));
+
+        write_certificate(&config, "after");
+        store.reload().unwrap();
+        assert_eq!(handshake(&acceptor), (b"h2".to_vec(), "after".to_owned()));
+        fs::remove_file(&config.cert_path).unwrap();
+        fs::remove_file(&config.key_path).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+use actix_web::http::header;
+use actix_web::middleware::DefaultHeaders;
+use actix_web::{web, Scope};
+use chrono::{DateTime, Utc};
+
+pub const API_PREFIX: &str = "/api";
+
+pub fn api_path(version: &str) -> String {
+    format!("{}/{}", API_PREFIX, version)
+}
+
+pub fn api_scope(version: &str) -> Scope {
+    web::scope(&api_path(version))
+}
+
+// Marks the routes it wraps as deprecated with a `Deprecation` header, plus an
+// optional `Sunset` date (RFC 8594) and a `Link` to the routes replacing them.
+#[derive(Clone, Debug, Default)]
+pub struct Deprecation {
+    sunset: Option<DateTime<Utc>>,
+    successor: Option<String>,
+}
+
+impl Deprecation {
+    pub fn new() -> Self {
+        Deprecation::default()
+    }
+
+    pub fn sunset(mut self, sunset: DateTime<Utc>) -> Self {
+        self.sunset = Some(sunset);
+        self
+    }
+
+    pub fn successor(mut self, successor: &str) -> Self {
+        self.successor = Some(successor.to_owned());
+        self
+    }
+
+    pub fn headers(&self) -> DefaultHeaders {
+        let mut headers = DefaultHeaders::new().add(("Deprecation", "true"));
+        if let Some(sunset) = self.sunset {
+            headers = headers.add((
+                "Sunset",
+                sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            ));
+        }
+        if let Some(successor) = &self.successor {
+            headers = headers.add((
+                header::LINK,
+                format!("<{}>; rel=\"successor-version\"", successor),
+            ));
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{test, App, HttpResponse};
+    use chrono::TimeZone;
+
+    #[actix_rt::test]
+    async fn deprecated_routes_should_carry_deprecation_headers() {
+        let deprecation = Deprecation::new()
+            .sunset(Utc.ymd(2023, 1, 1).and_hms(0, 0, 0))
+            .successor(&api_path("v2"));
+        let app = test::init_service(
+            App::new()
+                .service(
+                    api_scope("v1")
+                        .wrap(deprecation.headers())
+                        .route("/ping", web::get().to(|| async { HttpResponse::Ok().finish() })),
+                )
+                .service(
+                    api_scope("v2")
+                        .route("/ping", web::get().to(|| async { HttpResponse::Ok().finish() })),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/api/v1/ping").to_request();
+        let resp = test::call_service(&app, req).await;
+        let headers = resp.headers();
+        assert_eq!(headers.get("Deprecation").unwrap(), "true");
+        assert_eq!(headers.get("Sunset").unwrap(), "Sun, 01 Jan 2023 00:00:00 GMT");
+        assert_eq!(
+            headers.get(header::LINK).unwrap(),
+            "</api/v2>; rel=\"successor-version\""
+        );
+
+        let req = test::TestRequest::get().uri("/api/v2/ping").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get("Deprecation").is_none());
+    }
+}